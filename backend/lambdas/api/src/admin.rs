@@ -0,0 +1,184 @@
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+
+use crate::roles;
+use crate::servers::batch_get_servers;
+
+// Instance admins operate the deployment (support, abuse handling). This is
+// separate from the per-server owner/admin roles.
+
+#[derive(Debug, Serialize)]
+pub struct AdminMembership {
+    pub server_id: String,
+    /// None if the server record is missing
+    pub server_name: Option<String>,
+    pub role: String,
+    pub role_ids: Vec<String>,
+    pub joined_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetAdminRequest {
+    pub is_admin: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AdminStatus {
+    pub user_id: String,
+    pub is_admin: bool,
+}
+
+fn get_table(name: &str) -> String {
+    env::var(name).unwrap_or_else(|_| {
+        format!(
+            "agorusta-{}-dev",
+            name.to_lowercase().replace("_table", "s")
+        )
+    })
+}
+
+/// User ids from `INSTANCE_ADMIN_USER_IDS` (comma separated) are always
+/// admins, so a fresh deployment can bootstrap its first one
+fn bootstrap_admin_ids() -> Vec<String> {
+    env::var("INSTANCE_ADMIN_USER_IDS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+        .collect()
+}
+
+pub async fn is_instance_admin(db: &DynamoClient, user_id: &str) -> Result<bool, (u16, String)> {
+    if bootstrap_admin_ids().iter().any(|id| id == user_id) {
+        return Ok(true);
+    }
+
+    let result = db
+        .get_item()
+        .table_name(get_table("USERS_TABLE"))
+        .key("id", AttributeValue::S(user_id.to_string()))
+        .projection_expression("is_admin")
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+    Ok(result
+        .item()
+        .and_then(|item| item.get("is_admin")?.as_bool().ok().copied())
+        .unwrap_or(false))
+}
+
+async fn require_instance_admin(db: &DynamoClient, user_id: &str) -> Result<(), (u16, String)> {
+    if !is_instance_admin(db, user_id).await? {
+        return Err((403, "Instance admin access required".to_string()));
+    }
+    Ok(())
+}
+
+/// Every server the target user belongs to, with their role in each
+pub async fn list_user_servers(
+    db: &DynamoClient,
+    actor_id: &str,
+    target_user_id: &str,
+) -> Result<Vec<AdminMembership>, (u16, String)> {
+    require_instance_admin(db, actor_id).await?;
+
+    let mut memberships: Vec<AdminMembership> = Vec::new();
+    let mut start_key: Option<HashMap<String, AttributeValue>> = None;
+
+    loop {
+        let result = db
+            .query()
+            .table_name(get_table("MEMBERS_TABLE"))
+            .index_name("user-servers-index")
+            .key_condition_expression("user_id = :uid")
+            .expression_attribute_values(":uid", AttributeValue::S(target_user_id.to_string()))
+            .set_exclusive_start_key(start_key.take())
+            .send()
+            .await
+            .map_err(|e| (500, format!("Failed to list memberships: {}", e)))?;
+
+        memberships.extend(result.items().iter().filter_map(|item| {
+            Some(AdminMembership {
+                server_id: item.get("server_id")?.as_s().ok()?.clone(),
+                server_name: None,
+                role: item.get("role")?.as_s().ok()?.clone(),
+                role_ids: roles::parse_role_ids(item),
+                joined_at: item.get("joined_at")?.as_n().ok()?.parse().ok()?,
+            })
+        }));
+
+        start_key = result.last_evaluated_key().cloned();
+        if start_key.is_none() {
+            break;
+        }
+    }
+
+    let server_ids: Vec<String> = memberships.iter().map(|m| m.server_id.clone()).collect();
+    let names: HashMap<String, String> = batch_get_servers(db, &server_ids)
+        .await?
+        .into_iter()
+        .map(|server| (server.id, server.name))
+        .collect();
+
+    for membership in &mut memberships {
+        membership.server_name = names.get(&membership.server_id).cloned();
+    }
+    memberships.sort_by_key(|m| m.joined_at);
+
+    Ok(memberships)
+}
+
+/// Grant or revoke instance admin on another account
+pub async fn set_instance_admin(
+    db: &DynamoClient,
+    actor_id: &str,
+    target_user_id: &str,
+    body: &str,
+) -> Result<AdminStatus, (u16, String)> {
+    require_instance_admin(db, actor_id).await?;
+
+    let req: SetAdminRequest = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request: {}", e)))?;
+
+    if target_user_id == actor_id && !req.is_admin {
+        return Err((400, "You cannot revoke your own admin access".to_string()));
+    }
+
+    let result = db
+        .update_item()
+        .table_name(get_table("USERS_TABLE"))
+        .key("id", AttributeValue::S(target_user_id.to_string()))
+        .update_expression("SET is_admin = :admin")
+        .condition_expression("attribute_exists(id)")
+        .expression_attribute_values(":admin", AttributeValue::Bool(req.is_admin))
+        .send()
+        .await;
+
+    if let Err(e) = result {
+        let missing = e
+            .as_service_error()
+            .map(|se| se.is_conditional_check_failed_exception())
+            .unwrap_or(false);
+        if missing {
+            return Err((404, "User not found".to_string()));
+        }
+        return Err((500, format!("Failed to update user: {}", e)));
+    }
+
+    tracing::info!(
+        actor_id = %actor_id,
+        target_user_id = %target_user_id,
+        is_admin = req.is_admin,
+        "Instance admin flag changed"
+    );
+
+    Ok(AdminStatus {
+        user_id: target_user_id.to_string(),
+        is_admin: req.is_admin,
+    })
+}
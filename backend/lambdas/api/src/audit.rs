@@ -0,0 +1,181 @@
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use uuid::Uuid;
+
+use crate::clock;
+use crate::cursor;
+use crate::servers::load_membership;
+
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    pub entry_id: String,
+    pub actor_id: String,
+    pub action: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_id: Option<String>,
+    pub details: serde_json::Value,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditLogPage {
+    pub entries: Vec<AuditEntry>,
+    /// Pass as `cursor` to get the next page
+    pub next_cursor: Option<String>,
+}
+
+/// Optional filters for listing the audit log
+pub struct AuditFilter<'a> {
+    pub actor_id: Option<&'a str>,
+    pub action: Option<&'a str>,
+}
+
+const MAX_AUDIT_PAGE_SIZE: usize = 100;
+/// Queries per request; sparse filters return a short page with a cursor
+/// rather than reading the whole log
+const MAX_AUDIT_QUERY_PAGES: usize = 10;
+
+fn get_table(name: &str) -> String {
+    env::var(name).unwrap_or_else(|_| {
+        format!(
+            "agorusta-{}-dev",
+            name.to_lowercase().replace("_table", "s")
+        )
+    })
+}
+
+/// Append an entry to a server's audit log. Entries sort by time within the
+/// server (`entry_id` is `{created_at_ms}#{uuid}`).
+///
+/// Best effort: the action being audited has already happened, so a failed
+/// write is logged rather than surfaced to the caller.
+pub async fn record(
+    db: &DynamoClient,
+    server_id: &str,
+    actor_id: &str,
+    action: &str,
+    target_id: Option<&str>,
+    details: serde_json::Value,
+) {
+    let now = clock::now_millis();
+    let entry_id = format!("{}#{}", now, Uuid::new_v4());
+
+    let mut put = db
+        .put_item()
+        .table_name(get_table("AUDIT_LOG_TABLE"))
+        .item("server_id", AttributeValue::S(server_id.to_string()))
+        .item("entry_id", AttributeValue::S(entry_id))
+        .item("actor_id", AttributeValue::S(actor_id.to_string()))
+        .item("action", AttributeValue::S(action.to_string()))
+        .item("details", AttributeValue::S(details.to_string()))
+        .item("created_at", AttributeValue::N(now.to_string()));
+
+    if let Some(target_id) = target_id {
+        put = put.item("target_id", AttributeValue::S(target_id.to_string()));
+    }
+
+    if let Err(e) = put.send().await {
+        tracing::error!(
+            server_id = %server_id,
+            action = %action,
+            error = %e,
+            "Failed to write audit log entry"
+        );
+    }
+}
+
+/// A page of a server's audit log, newest first (owner/admin). `cursor` is
+/// the `next_cursor` of the previous page.
+pub async fn list_entries(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+    filter: &AuditFilter<'_>,
+    limit: usize,
+    cursor: Option<&str>,
+) -> Result<AuditLogPage, (u16, String)> {
+    let member = load_membership(db, server_id, user_id).await?;
+    if member.role != "owner" && member.role != "admin" {
+        return Err((403, "Only owners and admins can view the audit log".to_string()));
+    }
+
+    let limit = limit.clamp(1, MAX_AUDIT_PAGE_SIZE);
+    let cursor_scope = format!("audit-log:{}", server_id);
+    let mut start_key = cursor
+        .map(|c| cursor::decode_cursor(&cursor_scope, c))
+        .transpose()?
+        .map(|entry_id| entry_key(server_id, &entry_id));
+    let mut entries: Vec<AuditEntry> = Vec::new();
+
+    let mut conditions = Vec::new();
+    let mut values = HashMap::from([(":sid".to_string(), AttributeValue::S(server_id.to_string()))]);
+    if let Some(actor_id) = filter.actor_id {
+        conditions.push("actor_id = :actor");
+        values.insert(":actor".to_string(), AttributeValue::S(actor_id.to_string()));
+    }
+    if let Some(action) = filter.action {
+        // `action` is a reserved word
+        conditions.push("#action = :action");
+        values.insert(":action".to_string(), AttributeValue::S(action.to_string()));
+    }
+    let filter_expression = (!conditions.is_empty()).then(|| conditions.join(" AND "));
+
+    for _ in 0..MAX_AUDIT_QUERY_PAGES {
+        let mut query = db
+            .query()
+            .table_name(get_table("AUDIT_LOG_TABLE"))
+            .key_condition_expression("server_id = :sid")
+            .set_expression_attribute_values(Some(values.clone()))
+            .set_filter_expression(filter_expression.clone())
+            .scan_index_forward(false)
+            .limit((limit - entries.len()) as i32)
+            .set_exclusive_start_key(start_key.take());
+        if filter.action.is_some() {
+            query = query.expression_attribute_names("#action", "action");
+        }
+
+        let result = query
+            .send()
+            .await
+            .map_err(|e| (500, format!("Failed to load audit log: {}", e)))?;
+
+        entries.extend(result.items().iter().filter_map(parse_entry));
+        start_key = result.last_evaluated_key().cloned();
+        if start_key.is_none() || entries.len() >= limit {
+            break;
+        }
+    }
+
+    let next_cursor = start_key
+        .as_ref()
+        .and_then(|key| key.get("entry_id")?.as_s().ok())
+        .map(|entry_id| cursor::encode_cursor(&cursor_scope, entry_id))
+        .transpose()?;
+
+    Ok(AuditLogPage { entries, next_cursor })
+}
+
+fn entry_key(server_id: &str, entry_id: &str) -> HashMap<String, AttributeValue> {
+    HashMap::from([
+        ("server_id".to_string(), AttributeValue::S(server_id.to_string())),
+        ("entry_id".to_string(), AttributeValue::S(entry_id.to_string())),
+    ])
+}
+
+fn parse_entry(item: &HashMap<String, AttributeValue>) -> Option<AuditEntry> {
+    Some(AuditEntry {
+        entry_id: item.get("entry_id")?.as_s().ok()?.clone(),
+        actor_id: item.get("actor_id")?.as_s().ok()?.clone(),
+        action: item.get("action")?.as_s().ok()?.clone(),
+        target_id: item.get("target_id").and_then(|v| v.as_s().ok().cloned()),
+        details: item
+            .get("details")
+            .and_then(|v| v.as_s().ok())
+            .and_then(|d| serde_json::from_str(d).ok())
+            .unwrap_or(serde_json::Value::Null),
+        created_at: item.get("created_at")?.as_n().ok()?.parse().ok()?,
+    })
+}
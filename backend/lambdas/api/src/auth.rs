@@ -3,21 +3,37 @@ use argon2::{
     Argon2,
 };
 use rand::rngs::OsRng;
+use rand::RngCore;
 use aws_sdk_dynamodb::Client as DynamoClient;
+use base64::Engine;
+use jsonwebtoken::errors::ErrorKind;
 use jsonwebtoken::{encode, decode, Header, Validation, EncodingKey, DecodingKey};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::env;
 use uuid::Uuid;
 
+use crate::clock;
+use crate::db;
+use crate::dms;
+use crate::limits;
+use crate::rate_limit;
+use crate::sessions;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,  // user id
     pub email: String,
     pub username: String,
     pub exp: usize,   // expiration timestamp
+    /// Session the token was issued for; absent on tokens from before sessions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct RegisterRequest {
     pub email: String,
     pub username: String,
@@ -25,17 +41,62 @@ pub struct RegisterRequest {
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct LoginRequest {
     pub email: String,
     pub password: String,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ForgotPasswordResponse {
+    pub message: String,
+    /// Only returned when `DEV_MODE` is set, since there's no mail delivery
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reset_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
 #[derive(Debug, Serialize)]
 pub struct AuthResponse {
     pub token: String,
+    /// Exchanged at `POST /auth/refresh` for a new access token
+    pub refresh_token: String,
     pub user: UserResponse,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RefreshResponse {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenInfo {
+    /// Unix seconds when the presented token expires
+    pub exp: i64,
+    pub expires_in: i64,
+    /// The token is inside the refresh window; clients should call
+    /// `POST /auth/refresh`
+    pub should_refresh: bool,
+}
+
 #[derive(Debug, Serialize)]
 pub struct UserResponse {
     pub id: String,
@@ -43,10 +104,143 @@ pub struct UserResponse {
     pub username: String,
 }
 
-fn get_jwt_secret() -> String {
+/// Claims for a WebSocket resume token. The `purpose` field keeps these from
+/// being accepted anywhere an access token is expected (and vice versa).
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResumeClaims {
+    pub sub: String,
+    pub channels: Vec<String>,
+    pub purpose: String,
+    pub exp: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResumeTokenRequest {
+    #[serde(default)]
+    pub channel_ids: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResumeTokenResponse {
+    pub resume_token: String,
+    pub expires_at: i64,
+}
+
+/// What any signed-in user can see about another. Kept apart from
+/// `UserResponse` so email and credentials can't end up in it.
+#[derive(Debug, Serialize)]
+pub struct PublicProfile {
+    pub id: String,
+    pub username: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avatar_url: Option<String>,
+    /// Unknown for accounts registered before it was recorded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<i64>,
+}
+
+/// A user's published key for end-to-end encrypted DMs. The server only
+/// stores and hands it out; it never sees the private half.
+#[derive(Debug, Serialize)]
+pub struct PublicKeyResponse {
+    pub user_id: String,
+    pub public_key: String,
+    pub key_id: String,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PutPublicKeyRequest {
+    /// Base64-encoded public key
+    pub public_key: String,
+    /// Client-chosen identifier; generated if omitted
+    pub key_id: Option<String>,
+}
+
+const MAX_PUBLIC_KEY_BYTES: usize = 4 * 1024;
+
+/// Max size of the opaque per-user settings blob
+const MAX_SETTINGS_BYTES: usize = 16 * 1024;
+
+/// Login attempts per source IP, and per email, in each window
+pub const LOGIN_ATTEMPT_WINDOW_SECS: i64 = 300;
+
+/// Password reset tokens are good for an hour
+const PASSWORD_RESET_TTL_SECS: i64 = 60 * 60;
+/// Reset requests per email in each window
+const PASSWORD_RESET_REQUEST_LIMIT: u32 = 5;
+const PASSWORD_RESET_REQUEST_WINDOW_SECS: i64 = 60 * 60;
+
+/// Lifetime of an access token
+fn get_access_token_ttl() -> i64 {
+    env::var("ACCESS_TOKEN_TTL_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(60 * 60)
+}
+
+/// Lifetime of a refresh token, and of the session it belongs to
+pub fn get_refresh_token_ttl() -> i64 {
+    env::var("REFRESH_TOKEN_TTL_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(30 * 24 * 60 * 60)
+}
+
+const RESUME_TOKEN_PURPOSE: &str = "ws_resume";
+const MAX_RESUME_CHANNELS: usize = shared::limits::MAX_CHANNELS_PER_REQUEST;
+
+/// The secret new tokens and cursors are signed with
+pub fn get_jwt_secret() -> String {
     env::var("JWT_SECRET").unwrap_or_else(|_| "dev-secret-change-in-production".to_string())
 }
 
+/// Secrets a token may have been signed with: the current one first, then
+/// any listed in `JWT_SECRET_PREVIOUS` (comma-separated). Keeping the old
+/// secret there after rotating `JWT_SECRET` lets tokens already issued run
+/// out their lifetime instead of all failing at once.
+fn get_jwt_verification_secrets() -> Vec<String> {
+    let mut secrets = vec![get_jwt_secret()];
+    if let Ok(previous) = env::var("JWT_SECRET_PREVIOUS") {
+        secrets.extend(
+            previous
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string),
+        );
+    }
+    secrets
+}
+
+/// Decode a JWT signed with any accepted secret. Only a bad signature moves
+/// on to the next secret; an expired or malformed token fails straight away.
+pub fn decode_jwt<T: DeserializeOwned>(token: &str) -> Result<T, jsonwebtoken::errors::Error> {
+    decode_with_secrets(token, &get_jwt_verification_secrets())
+}
+
+fn decode_with_secrets<T: DeserializeOwned>(
+    token: &str,
+    secrets: &[String],
+) -> Result<T, jsonwebtoken::errors::Error> {
+    let validation = Validation::default();
+    let mut last_err: jsonwebtoken::errors::Error = ErrorKind::InvalidSignature.into();
+    for secret in secrets {
+        match decode::<T>(token, &DecodingKey::from_secret(secret.as_bytes()), &validation) {
+            Ok(data) => return Ok(data.claims),
+            Err(e) if matches!(e.kind(), ErrorKind::InvalidSignature) => last_err = e,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last_err)
+}
+
 pub fn hash_password(password: &str) -> Result<String, String> {
     let salt = SaltString::generate(&mut OsRng);
     let argon2 = Argon2::default();
@@ -66,17 +260,21 @@ pub fn verify_password(password: &str, hash: &str) -> bool {
         .is_ok()
 }
 
-fn create_token(user_id: &str, email: &str, username: &str) -> Result<String, String> {
-    let expiration = chrono::Utc::now()
-        .checked_add_signed(chrono::Duration::days(7))
-        .expect("valid timestamp")
-        .timestamp() as usize;
+fn create_token(
+    user_id: &str,
+    email: &str,
+    username: &str,
+    session_id: &str,
+    ttl_secs: i64,
+) -> Result<String, String> {
+    let expiration = (clock::now_secs() + ttl_secs) as usize;
 
     let claims = Claims {
         sub: user_id.to_string(),
         email: email.to_string(),
         username: username.to_string(),
         exp: expiration,
+        sid: Some(session_id.to_string()),
     };
 
     encode(
@@ -88,31 +286,162 @@ fn create_token(user_id: &str, email: &str, username: &str) -> Result<String, St
 }
 
 pub fn validate_token(token: &str) -> Result<Claims, String> {
-    decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(get_jwt_secret().as_bytes()),
-        &Validation::default(),
+    decode_jwt::<Claims>(token).map_err(|e| format!("Invalid token: {}", e))
+}
+
+fn get_resume_token_ttl() -> i64 {
+    env::var("WS_RESUME_TOKEN_TTL_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(300)
+}
+
+/// How close to expiry a token must be before `token_info` suggests
+/// refreshing it
+fn get_token_refresh_window() -> i64 {
+    env::var("TOKEN_REFRESH_WINDOW_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(300)
+}
+
+/// Remaining lifetime of an already-validated token
+pub fn token_info(claims: &Claims) -> TokenInfo {
+    lifetime(claims.exp as i64, get_token_refresh_window())
+}
+
+fn lifetime(exp: i64, refresh_window: i64) -> TokenInfo {
+    let expires_in = (exp - clock::now_secs()).max(0);
+
+    TokenInfo {
+        exp,
+        expires_in,
+        should_refresh: expires_in <= refresh_window,
+    }
+}
+
+/// Issue a short-lived token encoding the caller's current WebSocket
+/// subscriptions, which `$connect` accepts to restore them on reconnect.
+pub fn create_resume_token(
+    user_id: &str,
+    body: &str,
+) -> Result<ResumeTokenResponse, (u16, String)> {
+    let req: ResumeTokenRequest = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request: {}", e)))?;
+
+    limits::bounded_vec("channel_ids", &req.channel_ids, MAX_RESUME_CHANNELS)?;
+
+    // The token is trusted on reconnect without further checks, so it may
+    // only carry channels; conversations go through `subscribe_dm`, which
+    // checks the caller is a participant
+    if req.channel_ids.iter().any(|c| dms::is_conversation_id(c)) {
+        return Err((400, "channel_ids cannot include DM conversations".to_string()));
+    }
+
+    // DynamoDB string sets reject empty strings and duplicates
+    let mut channels: Vec<String> = req
+        .channel_ids
+        .into_iter()
+        .filter(|c| !c.is_empty())
+        .collect();
+    channels.sort();
+    channels.dedup();
+
+    let expires_at = clock::now_secs() + get_resume_token_ttl();
+
+    let claims = ResumeClaims {
+        sub: user_id.to_string(),
+        channels,
+        purpose: RESUME_TOKEN_PURPOSE.to_string(),
+        exp: expires_at as usize,
+    };
+
+    let resume_token = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(get_jwt_secret().as_bytes()),
     )
-    .map(|data| data.claims)
-    .map_err(|e| format!("Invalid token: {}", e))
+    .map_err(|e| (500, format!("Failed to create resume token: {}", e)))?;
+
+    Ok(ResumeTokenResponse {
+        resume_token,
+        expires_at,
+    })
+}
+
+pub fn get_login_rate_limit() -> u32 {
+    env::var("LOGIN_RATE_LIMIT_PER_5_MIN")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(20)
+}
+
+/// `jane@example.com` -> `j***@example.com`, so logs can correlate attempts
+/// without holding the full address
+fn mask_email(email: &str) -> String {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            let first: String = local.chars().take(1).collect();
+            format!("{}***@{}", first, domain)
+        }
+        None => "***".to_string(),
+    }
+}
+
+/// Structured warning plus a CloudWatch embedded-metric line for a failed
+/// auth attempt. Never pass passwords or tokens in here.
+pub fn log_auth_failure(action: &str, email: Option<&str>, source_ip: Option<&str>, reason: &str) {
+    tracing::warn!(
+        event = "auth_failure",
+        action = %action,
+        email = %email.map(mask_email).unwrap_or_default(),
+        source_ip = %source_ip.unwrap_or("unknown"),
+        reason = %reason,
+        "Authentication failed"
+    );
+
+    println!(
+        "{}",
+        serde_json::json!({
+            "_aws": {
+                "Timestamp": clock::now_millis(),
+                "CloudWatchMetrics": [{
+                    "Namespace": "Agorusta",
+                    "Dimensions": [["Action", "Reason"]],
+                    "Metrics": [{ "Name": "AuthFailures", "Unit": "Count" }]
+                }]
+            },
+            "Action": action,
+            "Reason": reason,
+            "AuthFailures": 1
+        })
+    );
 }
 
 pub async fn register(
     db: &DynamoClient,
     body: &str,
+    source_ip: Option<&str>,
+    user_agent: Option<&str>,
 ) -> Result<AuthResponse, (u16, String)> {
-    let req: RegisterRequest = serde_json::from_str(body)
-        .map_err(|e| (400, format!("Invalid request body: {}", e)))?;
+    let req: RegisterRequest = serde_json::from_str(body).map_err(|e| {
+        log_auth_failure("register", None, source_ip, "invalid_body");
+        (400, format!("Invalid request body: {}", e))
+    })?;
 
     // Validate input
-    if req.email.is_empty() || !req.email.contains('@') {
-        return Err((400, "Invalid email".to_string()));
-    }
-    if req.username.len() < 3 {
-        return Err((400, "Username must be at least 3 characters".to_string()));
-    }
-    if req.password.len() < 8 {
-        return Err((400, "Password must be at least 8 characters".to_string()));
+    let invalid = if req.email.is_empty() || !req.email.contains('@') {
+        Some(("invalid_email", "Invalid email"))
+    } else if req.username.len() < 3 {
+        Some(("invalid_username", "Username must be at least 3 characters"))
+    } else if req.password.len() < 8 {
+        Some(("weak_password", "Password must be at least 8 characters"))
+    } else {
+        None
+    };
+    if let Some((reason, message)) = invalid {
+        log_auth_failure("register", Some(&req.email), source_ip, reason);
+        return Err((400, message.to_string()));
     }
 
     let table_name = env::var("USERS_TABLE").unwrap_or_else(|_| "agorusta-users-dev".to_string());
@@ -129,6 +458,7 @@ pub async fn register(
         .map_err(|e| (500, format!("Database error: {}", e)))?;
 
     if existing.count() > 0 {
+        log_auth_failure("register", Some(&req.email), source_ip, "email_taken");
         return Err((409, "Email already registered".to_string()));
     }
 
@@ -143,15 +473,19 @@ pub async fn register(
         .item("email", aws_sdk_dynamodb::types::AttributeValue::S(req.email.clone()))
         .item("username", aws_sdk_dynamodb::types::AttributeValue::S(req.username.clone()))
         .item("password_hash", aws_sdk_dynamodb::types::AttributeValue::S(password_hash))
+        .item("created_at", aws_sdk_dynamodb::types::AttributeValue::N(clock::now_secs().to_string()))
         .send()
         .await
         .map_err(|e| (500, format!("Failed to create user: {}", e)))?;
 
-    let token = create_token(&user_id, &req.email, &req.username)
+    let session_id = sessions::create_session(db, &user_id, source_ip, user_agent).await?;
+    let token = create_token(&user_id, &req.email, &req.username, &session_id, get_access_token_ttl())
         .map_err(|e| (500, e))?;
+    let refresh_token = issue_refresh_token(db, &user_id, &session_id).await?;
 
     Ok(AuthResponse {
         token,
+        refresh_token,
         user: UserResponse {
             id: user_id,
             email: req.email,
@@ -163,9 +497,24 @@ pub async fn register(
 pub async fn login(
     db: &DynamoClient,
     body: &str,
+    source_ip: Option<&str>,
+    user_agent: Option<&str>,
 ) -> Result<AuthResponse, (u16, String)> {
-    let req: LoginRequest = serde_json::from_str(body)
-        .map_err(|e| (400, format!("Invalid request body: {}", e)))?;
+    let req: LoginRequest = serde_json::from_str(body).map_err(|e| {
+        log_auth_failure("login", None, source_ip, "invalid_body");
+        (400, format!("Invalid request body: {}", e))
+    })?;
+
+    // Throttle guessing against one account. The per-IP limit is applied
+    // by the router so it can report quota headers.
+    rate_limit::check(
+        db,
+        &format!("login-email:{}", req.email.to_lowercase()),
+        get_login_rate_limit(),
+        LOGIN_ATTEMPT_WINDOW_SECS,
+    )
+    .await
+    .inspect_err(|_| log_auth_failure("login", Some(&req.email), source_ip, "rate_limited"))?;
 
     let table_name = env::var("USERS_TABLE").unwrap_or_else(|_| "agorusta-users-dev".to_string());
 
@@ -182,6 +531,7 @@ pub async fn login(
 
     let items = result.items();
     if items.is_empty() {
+        log_auth_failure("login", Some(&req.email), source_ip, "unknown_email");
         return Err((401, "Invalid email or password".to_string()));
     }
 
@@ -200,14 +550,18 @@ pub async fn login(
         .ok_or((500, "Invalid user data".to_string()))?;
 
     if !verify_password(&req.password, password_hash) {
+        log_auth_failure("login", Some(&req.email), source_ip, "bad_password");
         return Err((401, "Invalid email or password".to_string()));
     }
 
-    let token = create_token(user_id, &req.email, username)
+    let session_id = sessions::create_session(db, user_id, source_ip, user_agent).await?;
+    let token = create_token(user_id, &req.email, username, &session_id, get_access_token_ttl())
         .map_err(|e| (500, e))?;
+    let refresh_token = issue_refresh_token(db, user_id, &session_id).await?;
 
     Ok(AuthResponse {
         token,
+        refresh_token,
         user: UserResponse {
             id: user_id.clone(),
             email: req.email,
@@ -215,3 +569,668 @@ pub async fn login(
         },
     })
 }
+
+// ============ Refresh tokens ============
+//
+// Access tokens are short-lived; login and registration also hand out a
+// refresh token, `<id>.<secret>`, for minting new ones. Like reset tokens,
+// only an argon2 hash of the secret is stored. Each one belongs to the
+// session it was issued with, so revoking the session (or resetting the
+// password) also stops it refreshing.
+
+fn get_refresh_tokens_table() -> String {
+    env::var("REFRESH_TOKENS_TABLE").unwrap_or_else(|_| "agorusta-refresh-tokens-dev".to_string())
+}
+
+fn invalid_refresh_token() -> (u16, String) {
+    (401, "Invalid or expired refresh token".to_string())
+}
+
+struct RefreshTokenRow {
+    id: String,
+    user_id: String,
+    session_id: String,
+}
+
+async fn issue_refresh_token(db: &DynamoClient, user_id: &str, session_id: &str) -> Result<String, (u16, String)> {
+    let id = Uuid::new_v4().to_string();
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    let secret = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(secret);
+    let secret_hash = hash_password(&secret).map_err(|e| (500, e))?;
+    let now = clock::now_secs();
+
+    db.put_item()
+        .table_name(get_refresh_tokens_table())
+        .item("id", aws_sdk_dynamodb::types::AttributeValue::S(id.clone()))
+        .item("user_id", aws_sdk_dynamodb::types::AttributeValue::S(user_id.to_string()))
+        .item("session_id", aws_sdk_dynamodb::types::AttributeValue::S(session_id.to_string()))
+        .item("secret_hash", aws_sdk_dynamodb::types::AttributeValue::S(secret_hash))
+        .item("created_at", aws_sdk_dynamodb::types::AttributeValue::N(now.to_string()))
+        .item("ttl", aws_sdk_dynamodb::types::AttributeValue::N((now + get_refresh_token_ttl()).to_string()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to create refresh token: {}", e)))?;
+
+    Ok(format!("{}.{}", id, secret))
+}
+
+/// Look up a presented refresh token, or None if it's unknown, expired or
+/// the secret doesn't match
+async fn find_refresh_token(db: &DynamoClient, token: &str) -> Result<Option<RefreshTokenRow>, (u16, String)> {
+    let Some((id, secret)) = token.split_once('.') else {
+        return Ok(None);
+    };
+
+    let result = db
+        .get_item()
+        .table_name(get_refresh_tokens_table())
+        .key("id", aws_sdk_dynamodb::types::AttributeValue::S(id.to_string()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+    let Some(item) = result.item() else {
+        return Ok(None);
+    };
+    let string = |key: &str| {
+        item.get(key)
+            .and_then(|v| v.as_s().ok())
+            .cloned()
+            .ok_or((500, "Invalid refresh token data".to_string()))
+    };
+    // TTL deletion lags, so check expiry here too
+    let expires_at = item
+        .get("ttl")
+        .and_then(|v| v.as_n().ok())
+        .and_then(|n| n.parse().ok());
+
+    if clock::is_expired(expires_at, clock::now_secs()) || !verify_password(secret, &string("secret_hash")?) {
+        return Ok(None);
+    }
+
+    Ok(Some(RefreshTokenRow {
+        id: id.to_string(),
+        user_id: string("user_id")?,
+        session_id: string("session_id")?,
+    }))
+}
+
+/// Mint a new access token for the session a refresh token belongs to
+pub async fn refresh(
+    db: &DynamoClient,
+    body: &str,
+    source_ip: Option<&str>,
+) -> Result<RefreshResponse, (u16, String)> {
+    let req: RefreshRequest = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request body: {}", e)))?;
+
+    let Some(row) = find_refresh_token(db, &req.refresh_token).await? else {
+        log_auth_failure("refresh", None, source_ip, "invalid_refresh_token");
+        return Err(invalid_refresh_token());
+    };
+    if !sessions::session_exists(db, &row.user_id, &row.session_id).await? {
+        log_auth_failure("refresh", None, source_ip, "session_revoked");
+        return Err(invalid_refresh_token());
+    }
+
+    let table_name = env::var("USERS_TABLE").unwrap_or_else(|_| "agorusta-users-dev".to_string());
+    let result = db
+        .get_item()
+        .table_name(&table_name)
+        .key("id", aws_sdk_dynamodb::types::AttributeValue::S(row.user_id.clone()))
+        .projection_expression("email, username")
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+    let user = result.item().ok_or_else(invalid_refresh_token)?;
+    let string = |key: &str| {
+        user.get(key)
+            .and_then(|v| v.as_s().ok())
+            .ok_or((500, "Invalid user data".to_string()))
+    };
+
+    let token = create_token(
+        &row.user_id,
+        string("email")?,
+        string("username")?,
+        &row.session_id,
+        get_access_token_ttl(),
+    )
+    .map_err(|e| (500, e))?;
+
+    Ok(RefreshResponse { token })
+}
+
+/// End the session a refresh token belongs to. Logging out with a token
+/// that's already gone succeeds.
+pub async fn logout(db: &DynamoClient, body: &str) -> Result<(), (u16, String)> {
+    let req: RefreshRequest = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request body: {}", e)))?;
+
+    let Some(row) = find_refresh_token(db, &req.refresh_token).await? else {
+        return Ok(());
+    };
+
+    db.delete_item()
+        .table_name(get_refresh_tokens_table())
+        .key("id", aws_sdk_dynamodb::types::AttributeValue::S(row.id))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to delete refresh token: {}", e)))?;
+
+    match sessions::revoke_session(db, &row.user_id, &row.session_id).await {
+        Ok(()) | Err((404, _)) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+// ============ Password reset ============
+//
+// A reset token is `<id>.<secret>`. The row is keyed by the id and only
+// stores an argon2 hash of the secret, so a leaked table can't be used to
+// reset anyone's password. Rows expire through DynamoDB TTL after an hour,
+// and are deleted when used.
+
+fn get_password_resets_table() -> String {
+    env::var("PASSWORD_RESETS_TABLE").unwrap_or_else(|_| "agorusta-password-resets-dev".to_string())
+}
+
+fn dev_mode() -> bool {
+    env::var("DEV_MODE").map(|v| v == "true" || v == "1").unwrap_or(false)
+}
+
+fn invalid_reset_token() -> (u16, String) {
+    (400, "Invalid or expired reset token".to_string())
+}
+
+/// Start a password reset. The response is the same whether or not the
+/// email is registered, so it can't be used to find accounts.
+pub async fn request_password_reset(
+    db: &DynamoClient,
+    body: &str,
+    source_ip: Option<&str>,
+) -> Result<ForgotPasswordResponse, (u16, String)> {
+    let req: ForgotPasswordRequest = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request body: {}", e)))?;
+
+    rate_limit::check(
+        db,
+        &format!("password-reset-email:{}", req.email.to_lowercase()),
+        PASSWORD_RESET_REQUEST_LIMIT,
+        PASSWORD_RESET_REQUEST_WINDOW_SECS,
+    )
+    .await
+    .inspect_err(|_| log_auth_failure("forgot_password", Some(&req.email), source_ip, "rate_limited"))?;
+
+    let mut response = ForgotPasswordResponse {
+        message: "If that email is registered, a reset link has been sent".to_string(),
+        reset_token: None,
+    };
+
+    // Hash before looking the email up, so an unknown address costs the same
+    // argon2 work as a registered one and response time doesn't tell them apart
+    let reset_id = Uuid::new_v4().to_string();
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    let secret = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(secret);
+    let secret_hash = hash_password(&secret).map_err(|e| (500, e))?;
+    let now = clock::now_secs();
+
+    let table_name = env::var("USERS_TABLE").unwrap_or_else(|_| "agorusta-users-dev".to_string());
+    let result = db
+        .query()
+        .table_name(&table_name)
+        .index_name("email-index")
+        .key_condition_expression("email = :email")
+        .expression_attribute_values(":email", aws_sdk_dynamodb::types::AttributeValue::S(req.email.clone()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+    let Some(user_id) = result
+        .items()
+        .first()
+        .and_then(|user| user.get("id"))
+        .and_then(|v| v.as_s().ok())
+    else {
+        log_auth_failure("forgot_password", Some(&req.email), source_ip, "unknown_email");
+        return Ok(response);
+    };
+
+    db.put_item()
+        .table_name(get_password_resets_table())
+        .item("id", aws_sdk_dynamodb::types::AttributeValue::S(reset_id.clone()))
+        .item("user_id", aws_sdk_dynamodb::types::AttributeValue::S(user_id.clone()))
+        .item("secret_hash", aws_sdk_dynamodb::types::AttributeValue::S(secret_hash))
+        .item("created_at", aws_sdk_dynamodb::types::AttributeValue::N(now.to_string()))
+        .item("ttl", aws_sdk_dynamodb::types::AttributeValue::N((now + PASSWORD_RESET_TTL_SECS).to_string()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to create reset token: {}", e)))?;
+
+    tracing::info!(user_id = %user_id, "Password reset requested");
+    if dev_mode() {
+        response.reset_token = Some(format!("{}.{}", reset_id, secret));
+    }
+
+    Ok(response)
+}
+
+/// Set a new password with a reset token. The token is used up, and every
+/// session the user had is revoked.
+pub async fn reset_password(
+    db: &DynamoClient,
+    body: &str,
+    source_ip: Option<&str>,
+) -> Result<(), (u16, String)> {
+    let req: ResetPasswordRequest = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request body: {}", e)))?;
+
+    if req.new_password.len() < 8 {
+        return Err((400, "Password must be at least 8 characters".to_string()));
+    }
+
+    let (reset_id, secret) = req.token.split_once('.').ok_or_else(invalid_reset_token)?;
+
+    let result = db
+        .get_item()
+        .table_name(get_password_resets_table())
+        .key("id", aws_sdk_dynamodb::types::AttributeValue::S(reset_id.to_string()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+    let Some(item) = result.item() else {
+        log_auth_failure("reset_password", None, source_ip, "unknown_token");
+        return Err(invalid_reset_token());
+    };
+    let user_id = item
+        .get("user_id")
+        .and_then(|v| v.as_s().ok())
+        .ok_or((500, "Invalid reset token data".to_string()))?;
+    let secret_hash = item
+        .get("secret_hash")
+        .and_then(|v| v.as_s().ok())
+        .ok_or((500, "Invalid reset token data".to_string()))?;
+    // TTL deletion lags, so check expiry here too
+    let expires_at = item
+        .get("ttl")
+        .and_then(|v| v.as_n().ok())
+        .and_then(|n| n.parse().ok());
+
+    if clock::is_expired(expires_at, clock::now_secs()) {
+        log_auth_failure("reset_password", None, source_ip, "expired_token");
+        return Err(invalid_reset_token());
+    }
+    if !verify_password(secret, secret_hash) {
+        log_auth_failure("reset_password", None, source_ip, "bad_token");
+        return Err(invalid_reset_token());
+    }
+
+    // Claim the token before changing anything so it can only be used once,
+    // even by concurrent requests
+    let claimed = db
+        .delete_item()
+        .table_name(get_password_resets_table())
+        .key("id", aws_sdk_dynamodb::types::AttributeValue::S(reset_id.to_string()))
+        .condition_expression("attribute_exists(id)")
+        .send()
+        .await;
+    if let Err(e) = claimed {
+        let used = e
+            .as_service_error()
+            .map(|se| se.is_conditional_check_failed_exception())
+            .unwrap_or(false);
+        if used {
+            return Err(invalid_reset_token());
+        }
+        return Err((500, format!("Failed to use reset token: {}", e)));
+    }
+
+    let password_hash = hash_password(&req.new_password).map_err(|e| (500, e))?;
+    let table_name = env::var("USERS_TABLE").unwrap_or_else(|_| "agorusta-users-dev".to_string());
+    db.update_item()
+        .table_name(&table_name)
+        .key("id", aws_sdk_dynamodb::types::AttributeValue::S(user_id.clone()))
+        .update_expression("SET password_hash = :hash")
+        .condition_expression("attribute_exists(id)")
+        .expression_attribute_values(":hash", aws_sdk_dynamodb::types::AttributeValue::S(password_hash))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to update password: {}", e)))?;
+
+    sessions::revoke_all_sessions(db, user_id).await?;
+
+    Ok(())
+}
+
+// ============ Settings ============
+
+/// Get the caller's settings blob, or an empty object if none is stored
+pub async fn get_settings(
+    db: &DynamoClient,
+    user_id: &str,
+) -> Result<serde_json::Value, (u16, String)> {
+    let table_name = env::var("USERS_TABLE").unwrap_or_else(|_| "agorusta-users-dev".to_string());
+
+    let result = db
+        .get_item()
+        .table_name(&table_name)
+        .key("id", aws_sdk_dynamodb::types::AttributeValue::S(user_id.to_string()))
+        .projection_expression("settings")
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+    let settings = result
+        .item()
+        .and_then(|item| item.get("settings")?.as_s().ok().cloned())
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_else(|| serde_json::json!({}));
+
+    Ok(settings)
+}
+
+/// Replace the caller's settings blob. The contents are opaque to the server;
+/// we only check it's valid JSON and under the size cap.
+pub async fn put_settings(
+    db: &DynamoClient,
+    user_id: &str,
+    body: &str,
+) -> Result<serde_json::Value, (u16, String)> {
+    if body.len() > MAX_SETTINGS_BYTES {
+        return Err((
+            400,
+            format!("Settings cannot exceed {} bytes", MAX_SETTINGS_BYTES),
+        ));
+    }
+
+    let settings: serde_json::Value = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Settings must be valid JSON: {}", e)))?;
+
+    let table_name = env::var("USERS_TABLE").unwrap_or_else(|_| "agorusta-users-dev".to_string());
+
+    db.update_item()
+        .table_name(&table_name)
+        .key("id", aws_sdk_dynamodb::types::AttributeValue::S(user_id.to_string()))
+        .update_expression("SET settings = :settings")
+        .condition_expression("attribute_exists(id)")
+        .expression_attribute_values(
+            ":settings",
+            aws_sdk_dynamodb::types::AttributeValue::S(settings.to_string()),
+        )
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to save settings: {}", e)))?;
+
+    Ok(settings)
+}
+
+// ============ Profiles ============
+
+/// Fetch a user's public profile. Only the profile fields are read, so
+/// nothing else from the user row can leak into the response.
+pub async fn get_public_profile(
+    db: &DynamoClient,
+    user_id: &str,
+) -> Result<PublicProfile, (u16, String)> {
+    let table_name = env::var("USERS_TABLE").unwrap_or_else(|_| "agorusta-users-dev".to_string());
+
+    let result = db
+        .get_item()
+        .table_name(&table_name)
+        .key("id", aws_sdk_dynamodb::types::AttributeValue::S(user_id.to_string()))
+        .projection_expression("id, username, display_name, avatar_url, created_at")
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+    result
+        .item()
+        .and_then(|item| {
+            let string = |key: &str| item.get(key).and_then(|v| v.as_s().ok()).cloned();
+            Some(PublicProfile {
+                id: string("id")?,
+                username: string("username")?,
+                display_name: string("display_name"),
+                avatar_url: string("avatar_url"),
+                created_at: item
+                    .get("created_at")
+                    .and_then(|v| v.as_n().ok())
+                    .and_then(|n| n.parse().ok()),
+            })
+        })
+        .ok_or_else(|| (404, "User not found".to_string()))
+}
+
+/// Map user ids to usernames; ids with no user record are left out
+pub async fn batch_get_usernames(
+    db: &DynamoClient,
+    user_ids: &[String],
+) -> Result<HashMap<String, String>, (u16, String)> {
+    let table_name = env::var("USERS_TABLE").unwrap_or_else(|_| "agorusta-users-dev".to_string());
+    let keys = user_ids
+        .iter()
+        .map(|id| HashMap::from([("id".to_string(), aws_sdk_dynamodb::types::AttributeValue::S(id.clone()))]))
+        .collect();
+    let items = db::batch_get(db, &table_name, keys, Some("id, username"), "users").await?;
+
+    Ok(items
+        .iter()
+        .filter_map(|item| {
+            Some((
+                item.get("id")?.as_s().ok()?.clone(),
+                item.get("username")?.as_s().ok()?.clone(),
+            ))
+        })
+        .collect())
+}
+
+// ============ Public keys ============
+
+/// Fetch another user's DM public key. 404 if they haven't published one.
+pub async fn get_public_key(
+    db: &DynamoClient,
+    user_id: &str,
+) -> Result<PublicKeyResponse, (u16, String)> {
+    let table_name = env::var("USERS_TABLE").unwrap_or_else(|_| "agorusta-users-dev".to_string());
+
+    let result = db
+        .get_item()
+        .table_name(&table_name)
+        .key("id", aws_sdk_dynamodb::types::AttributeValue::S(user_id.to_string()))
+        .projection_expression("public_key, public_key_id, public_key_updated_at")
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+    result
+        .item()
+        .and_then(|item| {
+            Some(PublicKeyResponse {
+                user_id: user_id.to_string(),
+                public_key: item.get("public_key")?.as_s().ok()?.clone(),
+                key_id: item.get("public_key_id")?.as_s().ok()?.clone(),
+                updated_at: item
+                    .get("public_key_updated_at")
+                    .and_then(|v| v.as_n().ok())
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or(0),
+            })
+        })
+        .ok_or_else(|| (404, "No public key published".to_string()))
+}
+
+/// Publish (or rotate) the caller's DM public key
+pub async fn put_public_key(
+    db: &DynamoClient,
+    user_id: &str,
+    body: &str,
+) -> Result<PublicKeyResponse, (u16, String)> {
+    let req: PutPublicKeyRequest = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request: {}", e)))?;
+
+    if req.public_key.is_empty() || req.public_key.len() > MAX_PUBLIC_KEY_BYTES {
+        return Err((
+            400,
+            format!("Public key must be 1-{} bytes", MAX_PUBLIC_KEY_BYTES),
+        ));
+    }
+    base64::engine::general_purpose::STANDARD
+        .decode(&req.public_key)
+        .map_err(|_| (400, "Public key must be base64".to_string()))?;
+
+    let key_id = req.key_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+    if key_id.is_empty() || key_id.len() > 128 {
+        return Err((400, "key_id must be 1-128 characters".to_string()));
+    }
+
+    let now = clock::now_secs();
+    let table_name = env::var("USERS_TABLE").unwrap_or_else(|_| "agorusta-users-dev".to_string());
+
+    db.update_item()
+        .table_name(&table_name)
+        .key("id", aws_sdk_dynamodb::types::AttributeValue::S(user_id.to_string()))
+        .update_expression("SET public_key = :key, public_key_id = :kid, public_key_updated_at = :now")
+        .condition_expression("attribute_exists(id)")
+        .expression_attribute_values(":key", aws_sdk_dynamodb::types::AttributeValue::S(req.public_key.clone()))
+        .expression_attribute_values(":kid", aws_sdk_dynamodb::types::AttributeValue::S(key_id.clone()))
+        .expression_attribute_values(":now", aws_sdk_dynamodb::types::AttributeValue::N(now.to_string()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to save public key: {}", e)))?;
+
+    Ok(PublicKeyResponse {
+        user_id: user_id.to_string(),
+        public_key: req.public_key,
+        key_id,
+        updated_at: now,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{set_clock, FixedClock};
+
+    fn token_signed_with(secret: &str) -> String {
+        let claims = Claims {
+            sub: "user-1".to_string(),
+            email: "ada@example.com".to_string(),
+            username: "ada".to_string(),
+            exp: (clock::now_secs() + 3600) as usize,
+            sid: None,
+        };
+        encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_bytes())).unwrap()
+    }
+
+    fn secrets(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn accepts_token_signed_with_previous_secret() {
+        let token = token_signed_with("old-secret");
+        let claims: Claims = decode_with_secrets(&token, &secrets(&["new-secret", "old-secret"])).unwrap();
+        assert_eq!(claims.sub, "user-1");
+    }
+
+    #[test]
+    fn rejects_token_signed_with_retired_secret() {
+        let token = token_signed_with("old-secret");
+        let err = decode_with_secrets::<Claims>(&token, &secrets(&["new-secret"])).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::InvalidSignature));
+    }
+
+    #[test]
+    fn expired_token_fails_without_trying_other_secrets() {
+        let claims = Claims {
+            sub: "user-1".to_string(),
+            email: "ada@example.com".to_string(),
+            username: "ada".to_string(),
+            exp: (clock::now_secs() - 3600) as usize,
+            sid: None,
+        };
+        let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(b"new-secret")).unwrap();
+        let err = decode_with_secrets::<Claims>(&token, &secrets(&["new-secret", "old-secret"])).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::ExpiredSignature));
+    }
+
+    #[test]
+    fn masks_email_local_part() {
+        assert_eq!(mask_email("ada@example.com"), "a***@example.com");
+        assert_eq!(mask_email("@example.com"), "***@example.com");
+        assert_eq!(mask_email("ünïcode@example.com"), "ü***@example.com");
+    }
+
+    #[test]
+    fn masks_value_without_at_sign_entirely() {
+        assert_eq!(mask_email("not-an-email"), "***");
+        assert_eq!(mask_email(""), "***");
+    }
+
+    #[test]
+    fn token_lifetime_outside_refresh_window() {
+        let _clock = set_clock(FixedClock(1_700_000_000_000));
+        let info = lifetime(1_700_000_000 + 3600, 300);
+        assert_eq!(info.exp, 1_700_003_600);
+        assert_eq!(info.expires_in, 3600);
+        assert!(!info.should_refresh);
+    }
+
+    #[test]
+    fn token_lifetime_inside_refresh_window() {
+        let _clock = set_clock(FixedClock(1_700_000_000_000));
+        assert!(lifetime(1_700_000_000 + 300, 300).should_refresh);
+        assert!(!lifetime(1_700_000_000 + 301, 300).should_refresh);
+    }
+
+    #[test]
+    fn expired_token_has_no_lifetime_left() {
+        let _clock = set_clock(FixedClock(1_700_000_000_000));
+        let info = lifetime(1_700_000_000 - 60, 300);
+        assert_eq!(info.expires_in, 0);
+        assert!(info.should_refresh);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn username_lookup_retries_throttled_keys() {
+        use aws_sdk_dynamodb::operation::batch_get_item::BatchGetItemOutput;
+        use aws_sdk_dynamodb::types::{AttributeValue, KeysAndAttributes};
+        use aws_smithy_mocks::{mock, mock_client, RuleMode};
+
+        let table = env::var("USERS_TABLE").unwrap_or_else(|_| "agorusta-users-dev".to_string());
+        let user = |id: &str| {
+            HashMap::from([
+                ("id".to_string(), AttributeValue::S(id.to_string())),
+                ("username".to_string(), AttributeValue::S(format!("name of {}", id))),
+            ])
+        };
+        let key = |id: &str| HashMap::from([("id".to_string(), AttributeValue::S(id.to_string()))]);
+        let first = {
+            let table = table.clone();
+            mock!(aws_sdk_dynamodb::Client::batch_get_item)
+                .match_requests(|input| input.request_items().unwrap().values().next().unwrap().keys().len() == 3)
+                .then_output(move || {
+                    BatchGetItemOutput::builder()
+                        .responses(&table, vec![user("user-1")])
+                        .unprocessed_keys(
+                            &table,
+                            KeysAndAttributes::builder().keys(key("user-2")).keys(key("gone")).build().unwrap(),
+                        )
+                        .build()
+                })
+        };
+        let retry = mock!(aws_sdk_dynamodb::Client::batch_get_item)
+            .then_output(move || BatchGetItemOutput::builder().responses(&table, vec![user("user-2")]).build());
+        let db = mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&first, &retry]);
+
+        let ids = vec!["user-1".to_string(), "user-2".to_string(), "gone".to_string()];
+        let usernames = batch_get_usernames(&db, &ids).await.unwrap();
+
+        assert_eq!(usernames.len(), 2);
+        assert_eq!(usernames["user-2"], "name of user-2");
+        assert_eq!(retry.num_calls(), 1);
+    }
+}
@@ -0,0 +1,83 @@
+use aws_sdk_dynamodb::Client as DynamoClient;
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+
+use crate::auth::{Claims, UserResponse};
+use crate::dms::{self, Conversation};
+use crate::servers::{self, Channel, ServerSummary};
+
+// Everything the client needs to render its first screen, in one request.
+// Lists are capped so a user in many servers can't make this unbounded;
+// `truncated` tells the client to page in the rest through the usual
+// endpoints.
+
+const MAX_BOOTSTRAP_SERVERS: usize = 50;
+const MAX_CHANNELS_PER_SERVER: usize = 100;
+const MAX_BOOTSTRAP_CONVERSATIONS: usize = 50;
+/// Channel queries in flight at once
+const BOOTSTRAP_CONCURRENCY: usize = 8;
+
+#[derive(Debug, Serialize)]
+pub struct BootstrapServer {
+    #[serde(flatten)]
+    pub summary: ServerSummary,
+    pub channels: Vec<Channel>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Bootstrap {
+    pub user: UserResponse,
+    pub servers: Vec<BootstrapServer>,
+    pub conversations: Vec<Conversation>,
+    /// Set when any list above was cut short
+    pub truncated: bool,
+}
+
+pub async fn get_bootstrap(db: &DynamoClient, claims: &Claims) -> Result<Bootstrap, (u16, String)> {
+    let (summaries, conversations) = futures::join!(
+        servers::list_server_summaries(db, &claims.sub),
+        dms::list_conversations(db, &claims.sub),
+    );
+    let mut summaries = summaries?;
+    let mut conversations = conversations?;
+
+    let mut truncated = false;
+    if summaries.len() > MAX_BOOTSTRAP_SERVERS {
+        summaries.truncate(MAX_BOOTSTRAP_SERVERS);
+        truncated = true;
+    }
+    if conversations.len() > MAX_BOOTSTRAP_CONVERSATIONS {
+        conversations.truncate(MAX_BOOTSTRAP_CONVERSATIONS);
+        truncated = true;
+    }
+
+    let results: Vec<Result<BootstrapServer, (u16, String)>> = stream::iter(summaries)
+        .map(|summary| async move {
+            let channels = servers::list_channels(db, &summary.id).await?;
+            Ok(BootstrapServer { summary, channels })
+        })
+        .buffered(BOOTSTRAP_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut servers = Vec::with_capacity(results.len());
+    for result in results {
+        let mut server = result?;
+        if server.channels.len() > MAX_CHANNELS_PER_SERVER {
+            server.channels.truncate(MAX_CHANNELS_PER_SERVER);
+            truncated = true;
+        }
+        servers.push(server);
+    }
+
+    Ok(Bootstrap {
+        user: UserResponse {
+            id: claims.sub.clone(),
+            email: claims.email.clone(),
+            username: claims.username.clone(),
+        },
+        servers,
+        conversations,
+        truncated,
+    })
+}
@@ -0,0 +1,473 @@
+use aws_sdk_apigatewaymanagement::primitives::Blob;
+use aws_sdk_apigatewaymanagement::Client as ApiGwClient;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use base64::Engine;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::io::Write;
+
+use crate::clock;
+
+/// Standard shape for every WebSocket event pushed to clients
+#[derive(Debug, Serialize)]
+pub struct Envelope<'a, T: Serialize> {
+    pub event: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<&'a str>,
+    pub data: &'a T,
+    /// Set to "gzip" when `data` is the base64 of the gzipped JSON rather
+    /// than the JSON itself
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compressed: Option<&'a str>,
+    pub ts: i64,
+}
+
+fn get_table(name: &str) -> String {
+    env::var(name).unwrap_or_else(|_| {
+        format!(
+            "agorusta-{}-dev",
+            name.to_lowercase().replace("_table", "s")
+        )
+    })
+}
+
+/// While clients migrate, `LEGACY_WS_EVENTS=true` emits the old
+/// `{ "type": ..., "message": ... }` shape instead of the envelope
+fn legacy_events_enabled() -> bool {
+    env::var("LEGACY_WS_EVENTS")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false)
+}
+
+/// Serialize an event in the envelope format. `legacy_type` is the event name
+/// used by the pre-envelope payloads, if there was one.
+pub fn build_payload<T: Serialize>(
+    event: &str,
+    legacy_type: Option<&str>,
+    server_id: Option<&str>,
+    channel_id: Option<&str>,
+    data: &T,
+) -> Result<Vec<u8>, serde_json::Error> {
+    let options = PayloadOptions {
+        legacy_events: legacy_events_enabled(),
+        compress_min_bytes: get_compress_min_bytes(),
+    };
+    encode_payload(event, legacy_type, server_id, channel_id, data, &options)
+}
+
+/// The env-controlled parts of payload encoding
+struct PayloadOptions {
+    legacy_events: bool,
+    compress_min_bytes: Option<usize>,
+}
+
+fn encode_payload<T: Serialize>(
+    event: &str,
+    legacy_type: Option<&str>,
+    server_id: Option<&str>,
+    channel_id: Option<&str>,
+    data: &T,
+    options: &PayloadOptions,
+) -> Result<Vec<u8>, serde_json::Error> {
+    if let (true, Some(legacy_type)) = (options.legacy_events, legacy_type) {
+        return serde_json::to_vec(&serde_json::json!({
+            "type": legacy_type,
+            "message": data
+        }));
+    }
+
+    let ts = clock::now_millis();
+    if let Some(min_bytes) = options.compress_min_bytes {
+        let json = serde_json::to_vec(data)?;
+        if json.len() >= min_bytes {
+            if let Some(compressed) = gzip_base64(&json).filter(|c| c.len() < json.len()) {
+                return serde_json::to_vec(&Envelope {
+                    event,
+                    server_id,
+                    channel_id,
+                    data: &compressed,
+                    compressed: Some("gzip"),
+                    ts,
+                });
+            }
+        }
+    }
+
+    serde_json::to_vec(&Envelope {
+        event,
+        server_id,
+        channel_id,
+        data,
+        compressed: None,
+        ts,
+    })
+}
+
+/// `data` of at least this many bytes is sent gzipped, when
+/// `BROADCAST_COMPRESS_MIN_BYTES` is set. Unset or 0 leaves every payload
+/// uncompressed, for clients that can't decompress yet.
+fn get_compress_min_bytes() -> Option<usize> {
+    env::var("BROADCAST_COMPRESS_MIN_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|v| *v > 0)
+}
+
+/// Gzip then base64 `json`, so it still fits in a JSON string. The payload
+/// is built once per broadcast, so this runs once no matter how many
+/// connections it goes to.
+fn gzip_base64(json: &[u8]) -> Option<String> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(json).ok()?;
+    let gzipped = encoder.finish().ok()?;
+    Some(base64::engine::general_purpose::STANDARD.encode(gzipped))
+}
+
+/// Max in-flight `post_to_connection` calls per broadcast
+fn get_broadcast_concurrency() -> usize {
+    env::var("BROADCAST_CONCURRENCY")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(16)
+}
+
+/// Connection ids subscribed to `subscription_id`, grouped by user and
+/// deduplicated. Connections whose token has expired are left out; they
+/// get nothing more until the client reauths. So are idle ones past their
+/// `ttl`, which DynamoDB may not get round to deleting for days.
+async fn find_subscribers(
+    db: &DynamoClient,
+    subscription_id: &str,
+) -> Result<BTreeMap<String, Vec<String>>, String> {
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut by_user: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut start_key: Option<HashMap<String, AttributeValue>> = None;
+    let now = clock::now_secs();
+
+    loop {
+        let result = db
+            .scan()
+            .table_name(get_table("CONNECTIONS_TABLE"))
+            .filter_expression("contains(channels, :channel_id) AND token_exp > :now AND #ttl > :now")
+            .expression_attribute_names("#ttl", "ttl")
+            .expression_attribute_values(
+                ":channel_id",
+                AttributeValue::S(subscription_id.to_string()),
+            )
+            .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+            .projection_expression("connection_id, user_id")
+            .set_exclusive_start_key(start_key.take())
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        for item in result.items() {
+            let Some(connection_id) = item.get("connection_id").and_then(|v| v.as_s().ok()) else {
+                continue;
+            };
+            if !seen.insert(connection_id.clone()) {
+                continue;
+            }
+            let user_id = item
+                .get("user_id")
+                .and_then(|v| v.as_s().ok())
+                .cloned()
+                .unwrap_or_default();
+            by_user.entry(user_id).or_default().push(connection_id.clone());
+        }
+
+        start_key = result.last_evaluated_key().cloned();
+        if start_key.is_none() {
+            break;
+        }
+    }
+
+    Ok(by_user)
+}
+
+/// Post to one connection, removing it if API Gateway says it's gone.
+/// Returns whether the post succeeded.
+async fn post_to_connection(
+    db: &DynamoClient,
+    apigw: &ApiGwClient,
+    connection_id: &str,
+    payload: &[u8],
+) -> bool {
+    let result = apigw
+        .post_to_connection()
+        .connection_id(connection_id)
+        .data(Blob::new(payload.to_vec()))
+        .send()
+        .await;
+
+    match result {
+        Ok(_) => {
+            tracing::debug!(connection_id = %connection_id, "Event sent");
+            true
+        }
+        Err(e) => {
+            // Check if connection is stale (GoneException)
+            let err_str = e.to_string();
+            if err_str.contains("Gone") || err_str.contains("410") {
+                tracing::info!(connection_id = %connection_id, "Stale connection, removing");
+                // Delete stale connection
+                let _ = db
+                    .delete_item()
+                    .table_name(get_table("CONNECTIONS_TABLE"))
+                    .key("connection_id", AttributeValue::S(connection_id.to_string()))
+                    .send()
+                    .await;
+            } else {
+                tracing::warn!(connection_id = %connection_id, error = %e, "Failed to send event");
+            }
+            false
+        }
+    }
+}
+
+/// Post a payload to every connection subscribed to `subscription_id` (a
+/// channel or DM conversation id), removing stale connections as we go.
+/// Returns the number of connections that were targeted.
+///
+/// Each connection is posted to once, at most `BROADCAST_CONCURRENCY` at a
+/// time. Delivery goes in rounds: every user's first connection, then every
+/// user's second, and so on, so one slow multi-device user can't hold
+/// everyone else's first delivery back.
+pub async fn send_to_subscribers(
+    db: &DynamoClient,
+    apigw: &ApiGwClient,
+    subscription_id: &str,
+    payload: &[u8],
+) -> usize {
+    let by_user = match find_subscribers(db, subscription_id).await {
+        Ok(by_user) => by_user,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to scan connections");
+            return 0;
+        }
+    };
+
+    if by_user.is_empty() {
+        tracing::debug!(subscription_id = %subscription_id, "No subscribers");
+        return 0;
+    }
+
+    let concurrency = get_broadcast_concurrency();
+    let total: usize = by_user.values().map(Vec::len).sum();
+    let rounds = by_user.values().map(Vec::len).max().unwrap_or(0);
+    let mut reached: HashSet<String> = HashSet::new();
+
+    for round in 0..rounds {
+        // Owned so the futures don't borrow from `by_user`
+        let targets: Vec<(String, String)> = by_user
+            .iter()
+            .filter_map(|(user_id, conns)| Some((user_id.clone(), conns.get(round)?.clone())))
+            .collect();
+
+        let delivered: Vec<(String, bool)> = stream::iter(targets)
+            .map(|(user_id, connection_id)| async move {
+                let ok = post_to_connection(db, apigw, &connection_id, payload).await;
+                (user_id, ok)
+            })
+            .buffer_unordered(concurrency)
+            .collect()
+            .await;
+
+        reached.extend(delivered.into_iter().filter(|(_, ok)| *ok).map(|(user_id, _)| user_id));
+    }
+
+    tracing::debug!(
+        subscription_id = %subscription_id,
+        connections = total,
+        users = by_user.len(),
+        users_reached = reached.len(),
+        "Broadcast delivered"
+    );
+
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{set_clock, FixedClock};
+    use aws_sdk_dynamodb::operation::scan::{ScanInput, ScanOutput};
+    use aws_smithy_mocks::{mock, mock_client, RuleMode};
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn connection(id: &str, user_id: &str, token_exp: i64) -> HashMap<String, AttributeValue> {
+        HashMap::from([
+            ("connection_id".to_string(), AttributeValue::S(id.to_string())),
+            ("user_id".to_string(), AttributeValue::S(user_id.to_string())),
+            ("token_exp".to_string(), AttributeValue::N(token_exp.to_string())),
+        ])
+    }
+
+    fn number_value(input: &ScanInput, name: &str) -> i64 {
+        input
+            .expression_attribute_values()
+            .and_then(|values| values.get(name))
+            .and_then(|v| v.as_n().ok())
+            .and_then(|n| n.parse().ok())
+            .unwrap()
+    }
+
+    /// Stand-in for DynamoDB applying the filter: only rows whose numeric
+    /// attribute `attr` is past the request's `:now` come back
+    fn filtered_scan(
+        attr: &'static str,
+        rows: Vec<HashMap<String, AttributeValue>>,
+    ) -> aws_smithy_mocks::Rule {
+        mock!(aws_sdk_dynamodb::Client::scan).then_compute_output(move |input| {
+            assert!(input.filter_expression().unwrap().contains(&format!("{} > :now", attr)));
+            let now = number_value(input, ":now");
+            let items = rows
+                .iter()
+                .filter(|row| {
+                    row.get(attr)
+                        .and_then(|v| v.as_n().ok())
+                        .and_then(|n| n.parse::<i64>().ok())
+                        .is_some_and(|value| value > now)
+                })
+                .cloned()
+                .collect();
+            ScanOutput::builder().set_items(Some(items)).build()
+        })
+    }
+
+    const PLAIN: PayloadOptions = PayloadOptions {
+        legacy_events: false,
+        compress_min_bytes: None,
+    };
+
+    fn decode(payload: &[u8]) -> serde_json::Value {
+        serde_json::from_slice(payload).unwrap()
+    }
+
+    #[test]
+    fn wraps_events_in_the_envelope() {
+        let _clock = set_clock(FixedClock(NOW * 1000));
+        let data = serde_json::json!({"id": "msg-1", "content": "hi"});
+        let payload = encode_payload("message_created", Some("new_message"), Some("server-1"), Some("chan-1"), &data, &PLAIN).unwrap();
+
+        let envelope = decode(&payload);
+        assert_eq!(envelope["event"], "message_created");
+        assert_eq!(envelope["server_id"], "server-1");
+        assert_eq!(envelope["channel_id"], "chan-1");
+        assert_eq!(envelope["data"], data);
+        assert_eq!(envelope["ts"], NOW * 1000);
+        assert!(envelope.get("compressed").is_none());
+    }
+
+    #[test]
+    fn leaves_out_absent_scope_fields() {
+        let payload = encode_payload("presence", None, None, None, &serde_json::json!({}), &PLAIN).unwrap();
+        let envelope = decode(&payload);
+        assert!(envelope.get("server_id").is_none());
+        assert!(envelope.get("channel_id").is_none());
+    }
+
+    #[test]
+    fn legacy_flag_emits_the_old_shape() {
+        let options = PayloadOptions {
+            legacy_events: true,
+            compress_min_bytes: None,
+        };
+        let data = serde_json::json!({"id": "msg-1"});
+        let payload = encode_payload("message_created", Some("new_message"), Some("server-1"), None, &data, &options).unwrap();
+        assert_eq!(decode(&payload), serde_json::json!({"type": "new_message", "message": data}));
+
+        // Events that never had a legacy shape keep the envelope
+        let payload = encode_payload("link_preview", None, Some("server-1"), None, &data, &options).unwrap();
+        assert_eq!(decode(&payload)["event"], "link_preview");
+    }
+
+    fn compressing(min_bytes: usize) -> PayloadOptions {
+        PayloadOptions {
+            legacy_events: false,
+            compress_min_bytes: Some(min_bytes),
+        }
+    }
+
+    #[test]
+    fn compresses_large_payloads_and_flags_them() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let data = serde_json::json!({"content": "lorem ipsum ".repeat(200)});
+        let payload = encode_payload("message_created", None, None, None, &data, &compressing(1024)).unwrap();
+
+        let envelope = decode(&payload);
+        assert_eq!(envelope["compressed"], "gzip");
+        let gzipped = base64::engine::general_purpose::STANDARD
+            .decode(envelope["data"].as_str().unwrap())
+            .unwrap();
+        let mut json = String::new();
+        GzDecoder::new(gzipped.as_slice()).read_to_string(&mut json).unwrap();
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&json).unwrap(), data);
+    }
+
+    #[test]
+    fn leaves_small_payloads_uncompressed() {
+        let data = serde_json::json!({"content": "hi"});
+        let payload = encode_payload("message_created", None, None, None, &data, &compressing(1024)).unwrap();
+        let envelope = decode(&payload);
+        assert!(envelope.get("compressed").is_none());
+        assert_eq!(envelope["data"], data);
+    }
+
+    #[test]
+    fn falls_back_when_gzip_does_not_help() {
+        // gzip's header alone outweighs a payload this small
+        let data = serde_json::json!({"c": "x"});
+        let payload = encode_payload("message_created", None, None, None, &data, &compressing(1)).unwrap();
+        let envelope = decode(&payload);
+        assert!(envelope.get("compressed").is_none());
+        assert_eq!(envelope["data"], data);
+    }
+
+    #[tokio::test]
+    async fn skips_connections_with_expired_tokens() {
+        let _clock = set_clock(FixedClock(NOW * 1000));
+        let rule = filtered_scan(
+            "token_exp",
+            vec![
+                connection("live", "user-1", NOW + 600),
+                connection("expired", "user-2", NOW - 1),
+            ],
+        );
+        let db = mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&rule]);
+
+        let subscribers = find_subscribers(&db, "chan-1").await.unwrap();
+        assert_eq!(subscribers.len(), 1);
+        assert_eq!(subscribers["user-1"], vec!["live".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn skips_connections_past_their_ttl() {
+        let _clock = set_clock(FixedClock(NOW * 1000));
+        let with_ttl = |id: &str, user_id: &str, ttl: i64| {
+            let mut row = connection(id, user_id, NOW + 600);
+            row.insert("ttl".to_string(), AttributeValue::N(ttl.to_string()));
+            row
+        };
+        let rule = filtered_scan(
+            "ttl",
+            vec![with_ttl("active", "user-1", NOW + 60), with_ttl("idle", "user-2", NOW - 3600)],
+        );
+        let db = mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&rule]);
+
+        let subscribers = find_subscribers(&db, "chan-1").await.unwrap();
+        assert_eq!(subscribers.len(), 1);
+        assert_eq!(subscribers["user-1"], vec!["active".to_string()]);
+    }
+}
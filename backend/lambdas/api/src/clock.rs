@@ -0,0 +1,119 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// The API's single source of "now". Everything that compares against the
+// current time (invite and password expiry, rate-limit windows, token and
+// session lifetimes) reads it from here, so a test can pin the time on its
+// thread with `set_clock` instead of racing the wall clock.
+
+pub trait Clock {
+    /// Unix time in milliseconds
+    fn now_millis(&self) -> i64;
+
+    /// Unix time in seconds
+    fn now_secs(&self) -> i64 {
+        self.now_millis().div_euclid(1000)
+    }
+}
+
+/// The wall clock
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> i64 {
+        chrono::Utc::now().timestamp_millis()
+    }
+}
+
+/// A clock stuck at one instant, for tests
+#[cfg(test)]
+pub struct FixedClock(pub i64);
+
+#[cfg(test)]
+impl Clock for FixedClock {
+    fn now_millis(&self) -> i64 {
+        self.0
+    }
+}
+
+thread_local! {
+    static OVERRIDE: RefCell<Option<Rc<dyn Clock>>> = const { RefCell::new(None) };
+}
+
+fn with_current<R>(f: impl FnOnce(&dyn Clock) -> R) -> R {
+    let clock = OVERRIDE.with(|o| o.borrow().clone());
+    match clock {
+        Some(clock) => f(clock.as_ref()),
+        None => f(&SystemClock),
+    }
+}
+
+pub fn now_millis() -> i64 {
+    with_current(|c| c.now_millis())
+}
+
+pub fn now_secs() -> i64 {
+    with_current(|c| c.now_secs())
+}
+
+/// Stand `clock` in for the wall clock on this thread until the returned
+/// guard is dropped. Async tests need a current-thread runtime for the
+/// override to stick.
+#[cfg(test)]
+pub fn set_clock(clock: impl Clock + 'static) -> ClockGuard {
+    let previous = OVERRIDE.with(|o| o.borrow_mut().replace(Rc::new(clock)));
+    ClockGuard { previous }
+}
+
+/// Restores the previous clock when dropped
+#[cfg(test)]
+pub struct ClockGuard {
+    previous: Option<Rc<dyn Clock>>,
+}
+
+#[cfg(test)]
+impl Drop for ClockGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        OVERRIDE.with(|o| *o.borrow_mut() = previous);
+    }
+}
+
+/// Whether an optional unix-seconds expiry has passed. Something expiring at
+/// `t` is still valid during second `t`.
+pub fn is_expired(expires_at: Option<i64>, now: i64) -> bool {
+    expires_at.is_some_and(|exp| exp < now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invite_expiry_boundary() {
+        let expires_at = 1_700_000_000;
+        let cases = [(expires_at - 1, false), (expires_at, false), (expires_at + 1, true)];
+
+        for (now, expired) in cases {
+            let _guard = set_clock(FixedClock(now * 1000));
+            assert_eq!(now_secs(), now);
+            assert_eq!(is_expired(Some(expires_at), now_secs()), expired, "now = {}", now);
+        }
+    }
+
+    #[test]
+    fn no_expiry_never_expires() {
+        let _guard = set_clock(FixedClock(i64::MAX / 2));
+        assert!(!is_expired(None, now_secs()));
+    }
+
+    #[test]
+    fn guard_restores_previous_clock() {
+        let _outer = set_clock(FixedClock(5_000));
+        {
+            let _inner = set_clock(FixedClock(9_000));
+            assert_eq!(now_secs(), 9);
+        }
+        assert_eq!(now_secs(), 5);
+    }
+}
@@ -0,0 +1,103 @@
+use jsonwebtoken::{encode, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::{decode_jwt, get_jwt_secret};
+use crate::clock;
+
+// Pagination cursors are signed with the JWT secret so clients can't forge
+// an arbitrary DynamoDB start key. `scope` names the endpoint and resource a
+// cursor was issued for, so one listing's cursor is rejected by another.
+
+const CURSOR_PURPOSE: &str = "cursor";
+/// Cursors are for paging through a listing now, not bookmarks
+const CURSOR_TTL_SECS: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CursorClaims {
+    scope: String,
+    key: String,
+    purpose: String,
+    exp: usize,
+}
+
+/// Wrap the last key of a page as an opaque cursor for `scope`
+pub fn encode_cursor(scope: &str, key: &str) -> Result<String, (u16, String)> {
+    let claims = CursorClaims {
+        scope: scope.to_string(),
+        key: key.to_string(),
+        purpose: CURSOR_PURPOSE.to_string(),
+        exp: (clock::now_secs() + CURSOR_TTL_SECS) as usize,
+    };
+
+    encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(get_jwt_secret().as_bytes()),
+    )
+    .map_err(|e| (500, format!("Failed to create cursor: {}", e)))
+}
+
+/// Recover the key from a cursor issued for `scope`. Tampered, expired, or
+/// other-scope cursors are a 400.
+pub fn decode_cursor(scope: &str, cursor: &str) -> Result<String, (u16, String)> {
+    let invalid = || (400, "Invalid cursor".to_string());
+
+    let claims = decode_jwt::<CursorClaims>(cursor).map_err(|_| invalid())?;
+
+    if claims.purpose != CURSOR_PURPOSE || claims.scope != scope {
+        return Err(invalid());
+    }
+    Ok(claims.key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{set_clock, FixedClock};
+
+    #[test]
+    fn round_trips_within_scope() {
+        let cursor = encode_cursor("messages:chan-1", "1700000000000#msg-1").unwrap();
+        assert_eq!(decode_cursor("messages:chan-1", &cursor).unwrap(), "1700000000000#msg-1");
+    }
+
+    #[test]
+    fn rejects_other_scope() {
+        let cursor = encode_cursor("messages:chan-1", "key").unwrap();
+        assert_eq!(decode_cursor("messages:chan-2", &cursor).unwrap_err().0, 400);
+        assert_eq!(decode_cursor("audit:server-1", &cursor).unwrap_err().0, 400);
+    }
+
+    #[test]
+    fn rejects_tampered_cursor() {
+        let cursor = encode_cursor("messages:chan-1", "key").unwrap();
+        let (header_and_payload, signature) = cursor.rsplit_once('.').unwrap();
+
+        // Swap in a payload claiming another key, keeping the old signature
+        let (header, _) = header_and_payload.split_once('.').unwrap();
+        let forged_claims = CursorClaims {
+            scope: "messages:chan-1".to_string(),
+            key: "forged".to_string(),
+            purpose: CURSOR_PURPOSE.to_string(),
+            exp: (clock::now_secs() + CURSOR_TTL_SECS) as usize,
+        };
+        let forged_payload = base64::Engine::encode(
+            &base64::engine::general_purpose::URL_SAFE_NO_PAD,
+            serde_json::to_vec(&forged_claims).unwrap(),
+        );
+        let forged = format!("{}.{}.{}", header, forged_payload, signature);
+        assert_eq!(decode_cursor("messages:chan-1", &forged).unwrap_err().0, 400);
+
+        assert_eq!(decode_cursor("messages:chan-1", "not-a-cursor").unwrap_err().0, 400);
+    }
+
+    #[test]
+    fn rejects_expired_cursor() {
+        let issued_at = clock::now_secs() - 2 * CURSOR_TTL_SECS;
+        let cursor = {
+            let _guard = set_clock(FixedClock(issued_at * 1000));
+            encode_cursor("messages:chan-1", "key").unwrap()
+        };
+        assert_eq!(decode_cursor("messages:chan-1", &cursor).unwrap_err().0, 400);
+    }
+}
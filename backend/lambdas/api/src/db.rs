@@ -0,0 +1,157 @@
+use aws_sdk_dynamodb::types::{AttributeValue, KeysAndAttributes};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use std::collections::HashMap;
+use std::time::Duration;
+
+// DynamoDB plumbing shared by every module. BatchGetItem hands back part of
+// a request as UnprocessedKeys when the table is throttled, so batch reads
+// all go through `batch_get` and retry the same bounded way instead of
+// looping until the throttling stops.
+
+pub type Item = HashMap<String, AttributeValue>;
+
+/// BatchGetItem takes at most 100 keys per request
+pub const BATCH_GET_MAX_KEYS: usize = 100;
+
+/// Retries of a chunk's unprocessed keys before giving up
+const BATCH_GET_MAX_RETRIES: u32 = 5;
+
+/// Wait before the first retry, doubled for each one after
+const BATCH_GET_BASE_BACKOFF: Duration = Duration::from_millis(25);
+
+/// Fetch `keys` from `table` with BatchGetItem, up to 100 per request, and
+/// return the items found; missing ones are skipped. Unprocessed keys are
+/// retried with exponential backoff, and if any are left after the last
+/// retry the whole call fails with a 503. `what` names the items in errors.
+pub async fn batch_get(
+    db: &DynamoClient,
+    table: &str,
+    keys: Vec<Item>,
+    projection: Option<&str>,
+    what: &str,
+) -> Result<Vec<Item>, (u16, String)> {
+    let mut items = Vec::with_capacity(keys.len());
+
+    for chunk in keys.chunks(BATCH_GET_MAX_KEYS) {
+        let mut pending = chunk.to_vec();
+        let mut retries = 0;
+
+        loop {
+            let request = KeysAndAttributes::builder()
+                .set_keys(Some(pending))
+                .set_projection_expression(projection.map(str::to_string))
+                .build()
+                .map_err(|e| (500, format!("Failed to build batch request: {}", e)))?;
+            let result = db
+                .batch_get_item()
+                .request_items(table, request)
+                .send()
+                .await
+                .map_err(|e| (500, format!("Failed to load {}: {}", what, e)))?;
+
+            if let Some(found) = result.responses().and_then(|r| r.get(table)) {
+                items.extend(found.iter().cloned());
+            }
+
+            pending = result
+                .unprocessed_keys()
+                .and_then(|u| u.get(table))
+                .map(|k| k.keys().to_vec())
+                .unwrap_or_default();
+            if pending.is_empty() {
+                break;
+            }
+            if retries == BATCH_GET_MAX_RETRIES {
+                return Err((
+                    503,
+                    format!("Failed to load {}: {} keys still unprocessed after retrying", what, pending.len()),
+                ));
+            }
+            tokio::time::sleep(BATCH_GET_BASE_BACKOFF * 2u32.pow(retries)).await;
+            retries += 1;
+        }
+    }
+
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_dynamodb::operation::batch_get_item::{BatchGetItemInput, BatchGetItemOutput};
+    use aws_smithy_mocks::{mock, mock_client, RuleMode};
+
+    const TABLE: &str = "things";
+
+    fn keys(count: usize) -> Vec<Item> {
+        (0..count)
+            .map(|i| HashMap::from([("id".to_string(), AttributeValue::S(format!("id-{}", i)))]))
+            .collect()
+    }
+
+    fn requested(input: &BatchGetItemInput) -> Vec<Item> {
+        input
+            .request_items()
+            .and_then(|r| r.get(TABLE))
+            .map(|k| k.keys().to_vec())
+            .unwrap_or_default()
+    }
+
+    /// Serves the first `serve` keys of each request and leaves the rest
+    /// unprocessed
+    fn output(requested: Vec<Item>, serve: usize) -> BatchGetItemOutput {
+        let unprocessed = requested[serve.min(requested.len())..].to_vec();
+        let served = requested.into_iter().take(serve).collect();
+        let mut output = BatchGetItemOutput::builder().responses(TABLE, served);
+        if !unprocessed.is_empty() {
+            output = output.unprocessed_keys(
+                TABLE,
+                KeysAndAttributes::builder().set_keys(Some(unprocessed)).build().unwrap(),
+            );
+        }
+        output.build()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_unprocessed_keys_until_all_are_served() {
+        let rule = mock!(aws_sdk_dynamodb::Client::batch_get_item)
+            .then_compute_output(|input| output(requested(input), 40));
+        let db = mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&rule]);
+
+        let items = batch_get(&db, TABLE, keys(100), None, "things").await.unwrap();
+
+        assert_eq!(items.len(), 100);
+        assert_eq!(rule.num_calls(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_with_a_503_when_keys_stay_unprocessed() {
+        let rule = mock!(aws_sdk_dynamodb::Client::batch_get_item)
+            .then_compute_output(|input| output(requested(input), 0));
+        let db = mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&rule]);
+
+        let started = tokio::time::Instant::now();
+        let (status, _) = batch_get(&db, TABLE, keys(10), None, "things").await.unwrap_err();
+
+        assert_eq!(status, 503);
+        assert_eq!(rule.num_calls(), 1 + BATCH_GET_MAX_RETRIES as usize);
+        // 25 + 50 + 100 + 200 + 400ms of backoff
+        assert_eq!(started.elapsed(), Duration::from_millis(775));
+    }
+
+    #[tokio::test]
+    async fn splits_keys_into_requests_of_100() {
+        let rule = mock!(aws_sdk_dynamodb::Client::batch_get_item).then_compute_output(|input| {
+            let keys = requested(input);
+            assert!(keys.len() <= BATCH_GET_MAX_KEYS);
+            let served = keys.len();
+            output(keys, served)
+        });
+        let db = mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&rule]);
+
+        let items = batch_get(&db, TABLE, keys(250), Some("id"), "things").await.unwrap();
+
+        assert_eq!(items.len(), 250);
+        assert_eq!(rule.num_calls(), 3);
+    }
+}
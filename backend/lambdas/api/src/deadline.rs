@@ -0,0 +1,29 @@
+use std::env;
+use std::future::Future;
+use std::time::Duration;
+
+/// How long a handler's DynamoDB work may take before we give up, well
+/// inside the Lambda timeout so the client gets a response body
+fn db_budget() -> Duration {
+    let millis = env::var("DB_TIMEOUT_MS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|ms| *ms > 0)
+        .unwrap_or(3000);
+    Duration::from_millis(millis)
+}
+
+/// Run `operation` within the DynamoDB time budget, turning an overrun into a
+/// 504 instead of letting the invocation hang until Lambda kills it
+pub async fn with_db_budget<T>(
+    operation: impl Future<Output = Result<T, (u16, String)>>,
+) -> Result<T, (u16, String)> {
+    let budget = db_budget();
+    match tokio::time::timeout(budget, operation).await {
+        Ok(result) => result,
+        Err(_) => {
+            tracing::warn!(budget_ms = budget.as_millis() as u64, "Database call exceeded time budget");
+            Err((504, "Database request timed out".to_string()))
+        }
+    }
+}
@@ -1,12 +1,18 @@
-use aws_sdk_apigatewaymanagement::primitives::Blob;
 use aws_sdk_apigatewaymanagement::Client as ApiGwClient;
 use aws_sdk_dynamodb::types::AttributeValue;
 use aws_sdk_dynamodb::Client as DynamoClient;
+use base64::Engine;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use uuid::Uuid;
 
+use crate::broadcast;
+use crate::clock;
+use crate::messages::{forwarded_from_attribute, parse_forwarded_from, ForwardedFrom};
+use crate::sanitize;
+use crate::servers;
+
 // ============ Types ============
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,6 +21,7 @@ pub struct Conversation {
     pub other_user_id: String,
     pub other_username: String,
     pub updated_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub last_message_preview: Option<String>,
     pub created_at: i64,
 }
@@ -25,8 +32,19 @@ pub struct DirectMessage {
     pub conversation_id: String,
     pub author_id: String,
     pub author_username: String,
+    /// Plain text, or base64 ciphertext when `encrypted` is set
     pub content: String,
     pub created_at: i64,
+    #[serde(default)]
+    pub encrypted: bool,
+    /// Which of the recipient's public keys the ciphertext was made for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forwarded_from: Option<ForwardedFrom>,
+    /// Id of the message in the same conversation this one replies to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -37,13 +55,34 @@ pub struct DmMessagesResponse {
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct StartConversationRequest {
     pub recipient_id: String,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct SendDmRequest {
     pub content: String,
+    /// Content is opaque client-side ciphertext; the server never decrypts it
+    #[serde(default)]
+    pub encrypted: bool,
+    pub key_id: Option<String>,
+    pub reply_to: Option<String>,
+}
+
+/// Who may DM a user: `everyone`, `server_members` (people sharing a
+/// server), or `none`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DmPrivacy {
+    pub allow_dms: String,
+}
+
+struct DmUser {
+    id: String,
+    username: String,
+    allow_dms: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -63,6 +102,49 @@ fn get_table(name: &str) -> String {
     })
 }
 
+/// Max size of an encrypted DM payload (base64 text)
+const MAX_ENCRYPTED_CONTENT_BYTES: usize = 16 * 1024;
+const ENCRYPTED_PREVIEW: &str = "🔒 Encrypted message";
+
+pub const ALLOW_DMS_EVERYONE: &str = "everyone";
+pub const ALLOW_DMS_SERVER_MEMBERS: &str = "server_members";
+pub const ALLOW_DMS_NONE: &str = "none";
+const ALLOW_DMS_OPTIONS: &[&str] = &[ALLOW_DMS_EVERYONE, ALLOW_DMS_SERVER_MEMBERS, ALLOW_DMS_NONE];
+
+const MIN_SEARCH_QUERY_CHARS: usize = 2;
+const MAX_SEARCH_RESULTS: usize = 20;
+/// Hard cap on user rows read per search request
+const MAX_SEARCH_SCANNED: usize = 1000;
+
+/// With `DM_RETENTION_DAYS` set, DMs and conversation rows carry a `ttl`
+/// this far past their last activity and DynamoDB deletes them after it.
+/// Unset keeps DM history forever.
+fn get_dm_retention_days() -> Option<i64> {
+    env::var("DM_RETENTION_DAYS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|v| *v > 0)
+}
+
+/// Unix seconds at which a DM row written now should expire, if retention
+/// is configured
+fn dm_expiry() -> Option<i64> {
+    get_dm_retention_days().map(|days| clock::now_secs() + days * 86400)
+}
+
+/// Searches allowed per user per minute (enforced by the router)
+pub fn get_search_rate_limit() -> u32 {
+    env::var("SEARCH_RATE_LIMIT_PER_MINUTE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(30)
+}
+
+/// Conversation ids contain `_`, which channel ids (UUIDs) never do
+pub fn is_conversation_id(id: &str) -> bool {
+    id.contains('_')
+}
+
 /// Generate a deterministic conversation ID from two user IDs
 fn make_conversation_id(user1: &str, user2: &str) -> String {
     let (min, max) = if user1 < user2 {
@@ -77,7 +159,7 @@ fn make_conversation_id(user1: &str, user2: &str) -> String {
 async fn get_user_by_id(
     db: &DynamoClient,
     user_id: &str,
-) -> Result<Option<(String, String)>, (u16, String)> {
+) -> Result<Option<DmUser>, (u16, String)> {
     let result = db
         .get_item()
         .table_name(get_table("USERS_TABLE"))
@@ -87,14 +169,111 @@ async fn get_user_by_id(
         .map_err(|e| (500, format!("Database error: {}", e)))?;
 
     Ok(result.item().and_then(|item| {
-        let id = item.get("id")?.as_s().ok()?.clone();
-        let username = item.get("username")?.as_s().ok()?.clone();
-        Some((id, username))
+        Some(DmUser {
+            id: item.get("id")?.as_s().ok()?.clone(),
+            username: item.get("username")?.as_s().ok()?.clone(),
+            allow_dms: parse_allow_dms(item),
+        })
     }))
 }
 
+fn parse_allow_dms(item: &HashMap<String, AttributeValue>) -> String {
+    item.get("allow_dms")
+        .and_then(|v| v.as_s().ok().cloned())
+        .unwrap_or_else(|| ALLOW_DMS_EVERYONE.to_string())
+}
+
+/// Who may open a new conversation with `recipient`
+async fn check_new_conversation_allowed(
+    db: &DynamoClient,
+    sender_id: &str,
+    recipient: &DmUser,
+) -> Result<(), (u16, String)> {
+    match recipient.allow_dms.as_str() {
+        ALLOW_DMS_NONE => Err((403, "This user doesn't accept direct messages".to_string())),
+        ALLOW_DMS_SERVER_MEMBERS => {
+            if servers::share_a_server(db, sender_id, &recipient.id).await? {
+                Ok(())
+            } else {
+                Err((
+                    403,
+                    "This user only accepts direct messages from people in a shared server".to_string(),
+                ))
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Refuse to deliver into a conversation whose other participant has turned
+/// DMs off. Conversations started earlier stay usable under any other
+/// setting.
+pub async fn check_recipient_accepts_dms(
+    db: &DynamoClient,
+    conversation: &Conversation,
+) -> Result<(), (u16, String)> {
+    let result = db
+        .get_item()
+        .table_name(get_table("USERS_TABLE"))
+        .key("id", AttributeValue::S(conversation.other_user_id.clone()))
+        .projection_expression("allow_dms")
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+    let allow_dms = result.item().map(parse_allow_dms);
+    if allow_dms.as_deref() == Some(ALLOW_DMS_NONE) {
+        return Err((403, "This user doesn't accept direct messages".to_string()));
+    }
+    Ok(())
+}
+
+/// The caller's DM privacy setting
+pub async fn get_dm_privacy(db: &DynamoClient, user_id: &str) -> Result<DmPrivacy, (u16, String)> {
+    let result = db
+        .get_item()
+        .table_name(get_table("USERS_TABLE"))
+        .key("id", AttributeValue::S(user_id.to_string()))
+        .projection_expression("allow_dms")
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+    Ok(DmPrivacy {
+        allow_dms: result
+            .item()
+            .map(parse_allow_dms)
+            .unwrap_or_else(|| ALLOW_DMS_EVERYONE.to_string()),
+    })
+}
+
+/// Change who may DM the caller
+pub async fn put_dm_privacy(db: &DynamoClient, user_id: &str, body: &str) -> Result<DmPrivacy, (u16, String)> {
+    let req: DmPrivacy = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request: {}", e)))?;
+
+    if !ALLOW_DMS_OPTIONS.contains(&req.allow_dms.as_str()) {
+        return Err((
+            400,
+            format!("allow_dms must be one of: {}", ALLOW_DMS_OPTIONS.join(", ")),
+        ));
+    }
+
+    db.update_item()
+        .table_name(get_table("USERS_TABLE"))
+        .key("id", AttributeValue::S(user_id.to_string()))
+        .update_expression("SET allow_dms = :allow")
+        .condition_expression("attribute_exists(id)")
+        .expression_attribute_values(":allow", AttributeValue::S(req.allow_dms.clone()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to save DM privacy: {}", e)))?;
+
+    Ok(req)
+}
+
 /// Check if user is a participant in the conversation
-async fn verify_participant(
+pub async fn verify_participant(
     db: &DynamoClient,
     conversation_id: &str,
     user_id: &str,
@@ -114,6 +293,31 @@ async fn verify_participant(
         .ok_or((403, "You are not a participant in this conversation".to_string()))
 }
 
+/// Look up a DM by id via the id-index, returning it only if it belongs to
+/// the given conversation
+pub async fn find_dm_message(
+    db: &DynamoClient,
+    conversation_id: &str,
+    message_id: &str,
+) -> Result<Option<DirectMessage>, (u16, String)> {
+    let result = db
+        .query()
+        .table_name(get_table("DM_MESSAGES_TABLE"))
+        .index_name("id-index")
+        .key_condition_expression("id = :mid")
+        .expression_attribute_values(":mid", AttributeValue::S(message_id.to_string()))
+        .limit(1)
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+    Ok(result
+        .items()
+        .first()
+        .and_then(parse_dm_message)
+        .filter(|m| m.conversation_id == conversation_id))
+}
+
 fn parse_conversation(item: &HashMap<String, AttributeValue>) -> Option<Conversation> {
     Some(Conversation {
         id: item.get("id")?.as_s().ok()?.clone(),
@@ -135,9 +339,58 @@ fn parse_dm_message(item: &HashMap<String, AttributeValue>) -> Option<DirectMess
         author_username: item.get("author_username")?.as_s().ok()?.clone(),
         content: item.get("content")?.as_s().ok()?.clone(),
         created_at: item.get("created_at")?.as_n().ok()?.parse().ok()?,
+        encrypted: item
+            .get("encrypted")
+            .and_then(|v| v.as_bool().ok())
+            .copied()
+            .unwrap_or(false),
+        key_id: item.get("key_id").and_then(|v| v.as_s().ok().cloned()),
+        forwarded_from: parse_forwarded_from(item),
+        reply_to: item.get("reply_to").and_then(|v| v.as_s().ok().cloned()),
     })
 }
 
+/// Validate a DM's content, returning what to store and the conversation
+/// preview. Ciphertext is only checked for shape and size.
+fn prepare_dm_content(req: &SendDmRequest) -> Result<(String, String), (u16, String)> {
+    if req.encrypted {
+        let key_id = req.key_id.as_deref().unwrap_or("");
+        if key_id.is_empty() || key_id.len() > 128 {
+            return Err((400, "Encrypted messages need a key_id of 1-128 characters".to_string()));
+        }
+        if req.content.is_empty() || req.content.len() > MAX_ENCRYPTED_CONTENT_BYTES {
+            return Err((
+                400,
+                format!("Encrypted content must be 1-{} bytes", MAX_ENCRYPTED_CONTENT_BYTES),
+            ));
+        }
+        base64::engine::general_purpose::STANDARD
+            .decode(&req.content)
+            .map_err(|_| (400, "Encrypted content must be base64".to_string()))?;
+        return Ok((req.content.clone(), ENCRYPTED_PREVIEW.to_string()));
+    }
+
+    let sanitized = sanitize::sanitize_content(&req.content)?;
+    let content = sanitized.trim();
+    if content.is_empty() {
+        return Err((400, "Message content cannot be empty".to_string()));
+    }
+    if content.len() > 2000 {
+        return Err((400, "Message content cannot exceed 2000 characters".to_string()));
+    }
+
+    Ok((content.to_string(), text_preview(content)))
+}
+
+/// Conversation list preview for a plain-text message
+pub fn text_preview(content: &str) -> String {
+    if content.chars().count() > 50 {
+        format!("{}...", content.chars().take(47).collect::<String>())
+    } else {
+        content.to_string()
+    }
+}
+
 // ============ User Search ============
 
 pub async fn search_users(
@@ -145,7 +398,8 @@ pub async fn search_users(
     query: &str,
     current_user_id: &str,
 ) -> Result<Vec<UserSearchResult>, (u16, String)> {
-    if query.trim().is_empty() {
+    // Very short prefixes match most of the user base, so don't run them
+    if query.trim().chars().count() < MIN_SEARCH_QUERY_CHARS {
         return Ok(vec![]);
     }
 
@@ -153,28 +407,40 @@ pub async fn search_users(
 
     // Scan users table and filter by username prefix
     // Note: In production, you'd want a more efficient approach (e.g., ElasticSearch)
-    // For now, we use a scan with filter since user count is small
-    let result = db
-        .scan()
-        .table_name(get_table("USERS_TABLE"))
-        .filter_expression("begins_with(username, :prefix) AND id <> :current_user")
-        .expression_attribute_values(":prefix", AttributeValue::S(query_lower.clone()))
-        .expression_attribute_values(":current_user", AttributeValue::S(current_user_id.to_string()))
-        .limit(20)
-        .send()
-        .await
-        .map_err(|e| (500, format!("Search failed: {}", e)))?;
+    // For now, we use a scan with filter since user count is small. Scan limits
+    // apply before the filter, so page until we have enough results or hit the
+    // per-request scan budget.
+    let mut users: Vec<UserSearchResult> = Vec::new();
+    let mut scanned = 0;
+    let mut start_key = None;
+
+    loop {
+        let result = db
+            .scan()
+            .table_name(get_table("USERS_TABLE"))
+            .filter_expression("begins_with(username, :prefix) AND id <> :current_user")
+            .expression_attribute_values(":prefix", AttributeValue::S(query_lower.clone()))
+            .expression_attribute_values(":current_user", AttributeValue::S(current_user_id.to_string()))
+            .limit(MAX_SEARCH_SCANNED as i32)
+            .set_exclusive_start_key(start_key)
+            .send()
+            .await
+            .map_err(|e| (500, format!("Search failed: {}", e)))?;
 
-    let users: Vec<UserSearchResult> = result
-        .items()
-        .iter()
-        .filter_map(|item| {
+        scanned += result.scanned_count() as usize;
+        users.extend(result.items().iter().filter_map(|item| {
             let id = item.get("id")?.as_s().ok()?.clone();
             let username = item.get("username")?.as_s().ok()?.clone();
             Some(UserSearchResult { id, username })
-        })
-        .collect();
+        }));
 
+        start_key = result.last_evaluated_key().cloned();
+        if start_key.is_none() || users.len() >= MAX_SEARCH_RESULTS || scanned >= MAX_SEARCH_SCANNED {
+            break;
+        }
+    }
+
+    users.truncate(MAX_SEARCH_RESULTS);
     Ok(users)
 }
 
@@ -195,10 +461,16 @@ pub async fn list_conversations(
         .await
         .map_err(|e| (500, format!("Failed to list conversations: {}", e)))?;
 
+    // The table key (id, user_id) already allows one row per user per
+    // conversation, and every write of a new row is conditional. The index is
+    // only eventually consistent though, so keep the first (newest) entry per
+    // id in case a read ever overlaps a row being rewritten.
+    let mut seen = HashSet::new();
     let conversations: Vec<Conversation> = result
         .items()
         .iter()
         .filter_map(parse_conversation)
+        .filter(|c| seen.insert(c.id.clone()))
         .collect();
 
     Ok(conversations)
@@ -217,13 +489,14 @@ pub async fn start_or_get_conversation(
         return Err((400, "Cannot start a conversation with yourself".to_string()));
     }
 
-    // Get recipient info
-    let (recipient_id, recipient_username) = get_user_by_id(db, &req.recipient_id)
+    // Get recipient info; this must happen before any writes so a deleted
+    // recipient never gets conversation rows
+    let recipient = get_user_by_id(db, &req.recipient_id)
         .await?
         .ok_or((404, "User not found".to_string()))?;
 
-    let conversation_id = make_conversation_id(user_id, &recipient_id);
-    let now = chrono::Utc::now().timestamp_millis();
+    let conversation_id = make_conversation_id(user_id, &recipient.id);
+    let now = clock::now_millis();
 
     // Check if conversation already exists for this user
     let existing = db
@@ -241,43 +514,120 @@ pub async fn start_or_get_conversation(
         }
     }
 
-    // Create conversation records for both users
-    // Record for current user
-    db.put_item()
-        .table_name(get_table("DM_CONVERSATIONS_TABLE"))
-        .item("id", AttributeValue::S(conversation_id.clone()))
-        .item("user_id", AttributeValue::S(user_id.to_string()))
-        .item("other_user_id", AttributeValue::S(recipient_id.clone()))
-        .item("other_username", AttributeValue::S(recipient_username.clone()))
-        .item("updated_at", AttributeValue::N(now.to_string()))
-        .item("created_at", AttributeValue::N(now.to_string()))
-        .send()
-        .await
-        .map_err(|e| (500, format!("Failed to create conversation: {}", e)))?;
+    check_new_conversation_allowed(db, user_id, &recipient).await?;
+
+    // Create conversation records for both users. Both puts are conditional,
+    // so if a concurrent start wins the race we return its record instead of
+    // overwriting it.
+    let created = put_conversation_row(
+        db,
+        &conversation_id,
+        user_id,
+        &recipient.id,
+        &recipient.username,
+        now,
+    )
+    .await?;
+    if !created {
+        return verify_participant(db, &conversation_id, user_id).await;
+    }
 
-    // Record for recipient
-    db.put_item()
-        .table_name(get_table("DM_CONVERSATIONS_TABLE"))
-        .item("id", AttributeValue::S(conversation_id.clone()))
-        .item("user_id", AttributeValue::S(recipient_id.clone()))
-        .item("other_user_id", AttributeValue::S(user_id.to_string()))
-        .item("other_username", AttributeValue::S(username.to_string()))
-        .item("updated_at", AttributeValue::N(now.to_string()))
-        .item("created_at", AttributeValue::N(now.to_string()))
-        .send()
-        .await
-        .map_err(|e| (500, format!("Failed to create conversation: {}", e)))?;
+    // The recipient row may already exist if they started the conversation
+    // first; either way it's in place afterwards
+    put_conversation_row(db, &conversation_id, &recipient.id, user_id, username, now).await?;
 
     Ok(Conversation {
         id: conversation_id,
-        other_user_id: recipient_id,
-        other_username: recipient_username,
+        other_user_id: recipient.id,
+        other_username: recipient.username,
         updated_at: now,
         last_message_preview: None,
         created_at: now,
     })
 }
 
+/// Send a plain-text DM on someone's behalf without a request body, e.g. a
+/// server's welcome message. Conversation rows are created as needed.
+pub async fn send_system_dm(
+    db: &DynamoClient,
+    from_user_id: &str,
+    to_user_id: &str,
+    to_username: &str,
+    content: &str,
+) -> Result<DirectMessage, (u16, String)> {
+    let sender = get_user_by_id(db, from_user_id)
+        .await?
+        .ok_or((404, "User not found".to_string()))?;
+
+    let now = clock::now_millis();
+    let conversation = Conversation {
+        id: make_conversation_id(&sender.id, to_user_id),
+        other_user_id: to_user_id.to_string(),
+        other_username: to_username.to_string(),
+        updated_at: now,
+        last_message_preview: None,
+        created_at: now,
+    };
+    let message = DirectMessage {
+        id: Uuid::new_v4().to_string(),
+        conversation_id: conversation.id.clone(),
+        author_id: sender.id,
+        author_username: sender.username,
+        content: content.to_string(),
+        created_at: now,
+        encrypted: false,
+        key_id: None,
+        forwarded_from: None,
+        reply_to: None,
+    };
+
+    check_recipient_accepts_dms(db, &conversation).await?;
+    store_dm_message(db, &conversation, &message, &text_preview(content)).await?;
+    Ok(message)
+}
+
+/// Write one participant's conversation row if it doesn't exist yet.
+/// Returns false when the row was already there.
+async fn put_conversation_row(
+    db: &DynamoClient,
+    conversation_id: &str,
+    user_id: &str,
+    other_user_id: &str,
+    other_username: &str,
+    now: i64,
+) -> Result<bool, (u16, String)> {
+    let mut put = db
+        .put_item()
+        .table_name(get_table("DM_CONVERSATIONS_TABLE"))
+        .item("id", AttributeValue::S(conversation_id.to_string()))
+        .item("user_id", AttributeValue::S(user_id.to_string()))
+        .item("other_user_id", AttributeValue::S(other_user_id.to_string()))
+        .item("other_username", AttributeValue::S(other_username.to_string()))
+        .item("updated_at", AttributeValue::N(now.to_string()))
+        .item("created_at", AttributeValue::N(now.to_string()))
+        .condition_expression("attribute_not_exists(id)");
+    // A conversation nobody writes in expires like its messages would
+    if let Some(ttl) = dm_expiry() {
+        put = put.item("ttl", AttributeValue::N(ttl.to_string()));
+    }
+    let result = put.send().await;
+
+    match result {
+        Ok(_) => Ok(true),
+        Err(e) => {
+            let exists = e
+                .as_service_error()
+                .map(|se| se.is_conditional_check_failed_exception())
+                .unwrap_or(false);
+            if exists {
+                Ok(false)
+            } else {
+                Err((500, format!("Failed to create conversation: {}", e)))
+            }
+        }
+    }
+}
+
 pub async fn get_conversation(
     db: &DynamoClient,
     conversation_id: &str,
@@ -298,8 +648,36 @@ pub async fn list_dm_messages(
     // Verify user is participant
     verify_participant(db, conversation_id, user_id).await?;
 
-    let limit = limit.min(100).max(1);
+    let limit = limit.clamp(1, 100);
+
+    let mut messages = recent_dm_messages(db, conversation_id, limit + 1, before).await?;
 
+    let has_more = messages.len() > limit;
+    if has_more {
+        messages.truncate(limit);
+    }
+
+    let next_cursor = if has_more {
+        messages.last().map(|m| m.created_at)
+    } else {
+        None
+    };
+
+    Ok(DmMessagesResponse {
+        messages,
+        has_more,
+        next_cursor,
+    })
+}
+
+/// Up to `limit` messages in a conversation, newest first, optionally only
+/// those sent before `before`. Callers check participation.
+pub async fn recent_dm_messages(
+    db: &DynamoClient,
+    conversation_id: &str,
+    limit: usize,
+    before: Option<i64>,
+) -> Result<Vec<DirectMessage>, (u16, String)> {
     let mut query = db
         .query()
         .table_name(get_table("DM_MESSAGES_TABLE"))
@@ -310,7 +688,7 @@ pub async fn list_dm_messages(
         })
         .expression_attribute_values(":cid", AttributeValue::S(conversation_id.to_string()))
         .scan_index_forward(false)
-        .limit((limit + 1) as i32);
+        .limit(limit as i32);
 
     if let Some(before_ts) = before {
         query = query.expression_attribute_values(":before", AttributeValue::N(before_ts.to_string()));
@@ -321,28 +699,11 @@ pub async fn list_dm_messages(
         .await
         .map_err(|e| (500, format!("Failed to list messages: {}", e)))?;
 
-    let mut messages: Vec<DirectMessage> = result
+    Ok(result
         .items()
         .iter()
         .filter_map(parse_dm_message)
-        .collect();
-
-    let has_more = messages.len() > limit;
-    if has_more {
-        messages.truncate(limit);
-    }
-
-    let next_cursor = if has_more {
-        messages.last().map(|m| m.created_at)
-    } else {
-        None
-    };
-
-    Ok(DmMessagesResponse {
-        messages,
-        has_more,
-        next_cursor,
-    })
+        .collect())
 }
 
 pub async fn send_dm_message(
@@ -354,107 +715,172 @@ pub async fn send_dm_message(
 ) -> Result<DirectMessage, (u16, String)> {
     // Verify user is participant
     let conversation = verify_participant(db, conversation_id, user_id).await?;
+    check_recipient_accepts_dms(db, &conversation).await?;
 
     let req: SendDmRequest = serde_json::from_str(body)
         .map_err(|e| (400, format!("Invalid request: {}", e)))?;
 
-    let content = req.content.trim();
-    if content.is_empty() {
-        return Err((400, "Message content cannot be empty".to_string()));
-    }
-    if content.len() > 2000 {
-        return Err((400, "Message content cannot exceed 2000 characters".to_string()));
+    let (content, preview) = prepare_dm_content(&req)?;
+
+    // Either participant's messages can be replied to, but only within this
+    // conversation
+    if let Some(reply_to) = req.reply_to.as_deref() {
+        if find_dm_message(db, conversation_id, reply_to).await?.is_none() {
+            return Err((400, "Reply target not found in this conversation".to_string()));
+        }
     }
 
-    let now = chrono::Utc::now().timestamp_millis();
+    let now = clock::now_millis();
     let message = DirectMessage {
         id: Uuid::new_v4().to_string(),
         conversation_id: conversation_id.to_string(),
         author_id: user_id.to_string(),
         author_username: username.to_string(),
-        content: content.to_string(),
+        content,
         created_at: now,
+        encrypted: req.encrypted,
+        key_id: req.key_id.filter(|_| req.encrypted),
+        forwarded_from: None,
+        reply_to: req.reply_to,
     };
 
-    // Store message
-    db.put_item()
+    store_dm_message(db, &conversation, &message, &preview).await?;
+
+    Ok(message)
+}
+
+/// Write a new DM and bump both participants' conversation rows
+pub async fn store_dm_message(
+    db: &DynamoClient,
+    conversation: &Conversation,
+    message: &DirectMessage,
+    preview: &str,
+) -> Result<(), (u16, String)> {
+    let mut put = db
+        .put_item()
         .table_name(get_table("DM_MESSAGES_TABLE"))
         .item("conversation_id", AttributeValue::S(message.conversation_id.clone()))
         .item("created_at", AttributeValue::N(message.created_at.to_string()))
         .item("id", AttributeValue::S(message.id.clone()))
         .item("author_id", AttributeValue::S(message.author_id.clone()))
         .item("author_username", AttributeValue::S(message.author_username.clone()))
-        .item("content", AttributeValue::S(message.content.clone()))
-        .send()
+        .item("content", AttributeValue::S(message.content.clone()));
+    if let Some(key_id) = &message.key_id {
+        put = put
+            .item("encrypted", AttributeValue::Bool(true))
+            .item("key_id", AttributeValue::S(key_id.clone()));
+    }
+    if let Some(origin) = &message.forwarded_from {
+        put = put.item("forwarded_from", forwarded_from_attribute(origin));
+    }
+    if let Some(reply_to) = &message.reply_to {
+        put = put.item("reply_to", AttributeValue::S(reply_to.clone()));
+    }
+    if let Some(ttl) = dm_expiry() {
+        put = put.item("ttl", AttributeValue::N(ttl.to_string()));
+    }
+    put.send()
         .await
         .map_err(|e| (500, format!("Failed to save message: {}", e)))?;
 
-    // Update conversation records for both users
-    let preview = if content.len() > 50 {
-        format!("{}...", &content[..47])
-    } else {
-        content.to_string()
-    };
+    // Update conversation records for both users. The recipient's row may be
+    // gone, so it's recreated from the author's side instead of upserted.
+    let rows = [
+        (message.author_id.as_str(), conversation.other_user_id.as_str(), conversation.other_username.as_str()),
+        (conversation.other_user_id.as_str(), message.author_id.as_str(), message.author_username.as_str()),
+    ];
+    for (user_id, other_user_id, other_username) in rows {
+        if let Err((_, e)) = touch_conversation_row(
+            db,
+            conversation,
+            message,
+            user_id,
+            other_user_id,
+            other_username,
+            preview,
+        )
+        .await
+        {
+            tracing::warn!(conversation_id = %message.conversation_id, user_id = %user_id, error = %e, "Failed to update conversation row");
+        }
+    }
+
+    Ok(())
+}
 
-    // Update current user's conversation record
-    let _ = db
+/// Bump one participant's conversation row for a new message, rewriting the
+/// whole row when it's missing so `other_user_id`/`other_username` are set.
+async fn touch_conversation_row(
+    db: &DynamoClient,
+    conversation: &Conversation,
+    message: &DirectMessage,
+    user_id: &str,
+    other_user_id: &str,
+    other_username: &str,
+    preview: &str,
+) -> Result<(), (u16, String)> {
+    // With retention on, each message pushes the row's expiry out so the
+    // conversation outlives its newest message, not its first
+    let ttl = dm_expiry();
+    let mut update = db
         .update_item()
         .table_name(get_table("DM_CONVERSATIONS_TABLE"))
-        .key("id", AttributeValue::S(conversation_id.to_string()))
+        .key("id", AttributeValue::S(message.conversation_id.clone()))
         .key("user_id", AttributeValue::S(user_id.to_string()))
-        .update_expression("SET updated_at = :updated, last_message_preview = :preview")
-        .expression_attribute_values(":updated", AttributeValue::N(now.to_string()))
-        .expression_attribute_values(":preview", AttributeValue::S(preview.clone()))
-        .send()
-        .await;
+        .update_expression(if ttl.is_some() {
+            "SET updated_at = :updated, last_message_preview = :preview, #ttl = :ttl"
+        } else {
+            "SET updated_at = :updated, last_message_preview = :preview"
+        })
+        .condition_expression("attribute_exists(id)")
+        .expression_attribute_values(":updated", AttributeValue::N(message.created_at.to_string()))
+        .expression_attribute_values(":preview", AttributeValue::S(preview.to_string()));
+    if let Some(ttl) = ttl {
+        update = update
+            .expression_attribute_names("#ttl", "ttl")
+            .expression_attribute_values(":ttl", AttributeValue::N(ttl.to_string()));
+    }
+    let result = update.send().await;
+
+    if let Err(e) = result {
+        let missing = e
+            .as_service_error()
+            .map(|se| se.is_conditional_check_failed_exception())
+            .unwrap_or(false);
+        if !missing {
+            return Err((500, format!("Failed to update conversation: {}", e)));
+        }
 
-    // Update other user's conversation record
-    let _ = db
-        .update_item()
-        .table_name(get_table("DM_CONVERSATIONS_TABLE"))
-        .key("id", AttributeValue::S(conversation_id.to_string()))
-        .key("user_id", AttributeValue::S(conversation.other_user_id.clone()))
-        .update_expression("SET updated_at = :updated, last_message_preview = :preview")
-        .expression_attribute_values(":updated", AttributeValue::N(now.to_string()))
-        .expression_attribute_values(":preview", AttributeValue::S(preview))
-        .send()
-        .await;
+        let mut put = db
+            .put_item()
+            .table_name(get_table("DM_CONVERSATIONS_TABLE"))
+            .item("id", AttributeValue::S(message.conversation_id.clone()))
+            .item("user_id", AttributeValue::S(user_id.to_string()))
+            .item("other_user_id", AttributeValue::S(other_user_id.to_string()))
+            .item("other_username", AttributeValue::S(other_username.to_string()))
+            .item("updated_at", AttributeValue::N(message.created_at.to_string()))
+            .item("last_message_preview", AttributeValue::S(preview.to_string()))
+            .item("created_at", AttributeValue::N(conversation.created_at.to_string()));
+        if let Some(ttl) = ttl {
+            put = put.item("ttl", AttributeValue::N(ttl.to_string()));
+        }
+        put.send()
+            .await
+            .map_err(|e| (500, format!("Failed to restore conversation: {}", e)))?;
+    }
 
-    Ok(message)
+    Ok(())
 }
 
 /// Broadcast a DM to WebSocket connections subscribed to the conversation
 pub async fn broadcast_dm(db: &DynamoClient, apigw: &ApiGwClient, message: &DirectMessage) {
-    // Find all connections subscribed to this conversation
-    let scan_result = db
-        .scan()
-        .table_name(get_table("CONNECTIONS_TABLE"))
-        .filter_expression("contains(channels, :conv_id)")
-        .expression_attribute_values(
-            ":conv_id",
-            AttributeValue::S(message.conversation_id.clone()),
-        )
-        .send()
-        .await;
-
-    let connections = match scan_result {
-        Ok(result) => result.items().to_vec(),
-        Err(e) => {
-            tracing::error!(error = %e, "Failed to scan connections for DM");
-            return;
-        }
-    };
-
-    if connections.is_empty() {
-        tracing::debug!(conversation_id = %message.conversation_id, "No subscribers for conversation");
-        return;
-    }
-
-    let payload = serde_json::json!({
-        "type": "new_dm",
-        "message": message
-    });
-    let payload_bytes = match serde_json::to_vec(&payload) {
+    let payload = match broadcast::build_payload(
+        "dm_created",
+        Some("new_dm"),
+        None,
+        Some(&message.conversation_id),
+        message,
+    ) {
         Ok(b) => b,
         Err(e) => {
             tracing::error!(error = %e, "Failed to serialize DM");
@@ -462,34 +888,78 @@ pub async fn broadcast_dm(db: &DynamoClient, apigw: &ApiGwClient, message: &Dire
         }
     };
 
-    for conn in &connections {
-        let connection_id = match conn.get("connection_id").and_then(|v| v.as_s().ok()) {
-            Some(id) => id.clone(),
-            None => continue,
-        };
-
-        let result = apigw
-            .post_to_connection()
-            .connection_id(&connection_id)
-            .data(Blob::new(payload_bytes.clone()))
-            .send()
-            .await;
-
-        if let Err(e) = result {
-            let err_str = e.to_string();
-            if err_str.contains("Gone") || err_str.contains("410") {
-                let _ = db
-                    .delete_item()
-                    .table_name(get_table("CONNECTIONS_TABLE"))
-                    .key("connection_id", AttributeValue::S(connection_id))
-                    .send()
-                    .await;
-            }
-        }
-    }
+    broadcast::send_to_subscribers(db, apigw, &message.conversation_id, &payload).await;
 
     tracing::info!(
         conversation_id = %message.conversation_id,
         "DM broadcast complete"
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(content: &str, encrypted: bool, key_id: Option<&str>) -> SendDmRequest {
+        SendDmRequest {
+            content: content.to_string(),
+            encrypted,
+            key_id: key_id.map(str::to_string),
+            reply_to: None,
+        }
+    }
+
+    #[test]
+    fn plain_content_is_trimmed_and_previewed() {
+        let (content, preview) = prepare_dm_content(&request("  hello there  ", false, None)).unwrap();
+        assert_eq!(content, "hello there");
+        assert_eq!(preview, "hello there");
+
+        let long = "x".repeat(60);
+        let (_, preview) = prepare_dm_content(&request(&long, false, None)).unwrap();
+        assert_eq!(preview, format!("{}...", "x".repeat(47)));
+    }
+
+    #[test]
+    fn plain_content_must_be_1_to_2000_characters() {
+        assert_eq!(prepare_dm_content(&request("   ", false, None)).unwrap_err().0, 400);
+        assert_eq!(prepare_dm_content(&request(&"x".repeat(2001), false, None)).unwrap_err().0, 400);
+        assert!(prepare_dm_content(&request(&"x".repeat(2000), false, None)).is_ok());
+    }
+
+    #[test]
+    fn encrypted_content_is_stored_as_is_with_a_fixed_preview() {
+        let ciphertext = "c2VjcmV0IGJ5dGVz";
+        let (content, preview) = prepare_dm_content(&request(ciphertext, true, Some("key-1"))).unwrap();
+        assert_eq!(content, ciphertext);
+        assert_eq!(preview, ENCRYPTED_PREVIEW);
+    }
+
+    #[test]
+    fn encrypted_content_needs_a_key_id_and_base64() {
+        let ciphertext = "c2VjcmV0IGJ5dGVz";
+        assert_eq!(prepare_dm_content(&request(ciphertext, true, None)).unwrap_err().0, 400);
+        assert_eq!(prepare_dm_content(&request(ciphertext, true, Some(""))).unwrap_err().0, 400);
+        assert_eq!(prepare_dm_content(&request(ciphertext, true, Some(&"k".repeat(129)))).unwrap_err().0, 400);
+        assert_eq!(prepare_dm_content(&request("not base64!", true, Some("key-1"))).unwrap_err().0, 400);
+        assert_eq!(prepare_dm_content(&request("", true, Some("key-1"))).unwrap_err().0, 400);
+    }
+
+    #[tokio::test]
+    async fn refusing_all_dms_is_a_403_for_new_conversations_too() {
+        use aws_sdk_dynamodb::operation::query::QueryOutput;
+        use aws_smithy_mocks::{mock, mock_client, RuleMode};
+
+        let queries = mock!(aws_sdk_dynamodb::Client::query).then_output(|| QueryOutput::builder().build());
+        let db = mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&queries]);
+        let recipient = DmUser {
+            id: "user-2".to_string(),
+            username: "grace".to_string(),
+            allow_dms: ALLOW_DMS_NONE.to_string(),
+        };
+
+        let err = check_new_conversation_allowed(&db, "user-1", &recipient).await.unwrap_err();
+        assert_eq!(err, (403, "This user doesn't accept direct messages".to_string()));
+        assert_eq!(queries.num_calls(), 0);
+    }
+}
@@ -0,0 +1,181 @@
+use aws_sdk_dynamodb::types::{AttributeValue, Select};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+
+use crate::clock;
+
+// Drafts are private per-user state synced across devices. `scope_id` is
+// the channel or DM conversation id the draft belongs to; it isn't checked
+// against membership since only the owner ever reads it back.
+
+#[derive(Debug, Serialize)]
+pub struct Draft {
+    pub scope_id: String,
+    pub content: String,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PutDraftRequest {
+    pub content: String,
+}
+
+const MAX_DRAFT_CHARS: usize = 2000;
+const MAX_DRAFTS_PER_USER: usize = 200;
+const MAX_SCOPE_ID_CHARS: usize = 128;
+
+fn get_table(name: &str) -> String {
+    env::var(name).unwrap_or_else(|_| {
+        format!(
+            "agorusta-{}-dev",
+            name.to_lowercase().replace("_table", "s")
+        )
+    })
+}
+
+fn parse_draft(item: &HashMap<String, AttributeValue>) -> Option<Draft> {
+    Some(Draft {
+        scope_id: item.get("scope_id")?.as_s().ok()?.clone(),
+        content: item.get("content")?.as_s().ok()?.clone(),
+        updated_at: item.get("updated_at")?.as_n().ok()?.parse().ok()?,
+    })
+}
+
+/// All of the caller's drafts, most recently edited first
+pub async fn list_drafts(db: &DynamoClient, user_id: &str) -> Result<Vec<Draft>, (u16, String)> {
+    let mut drafts: Vec<Draft> = Vec::new();
+    let mut start_key: Option<HashMap<String, AttributeValue>> = None;
+
+    loop {
+        let result = db
+            .query()
+            .table_name(get_table("DRAFTS_TABLE"))
+            .key_condition_expression("user_id = :uid")
+            .expression_attribute_values(":uid", AttributeValue::S(user_id.to_string()))
+            .set_exclusive_start_key(start_key.take())
+            .send()
+            .await
+            .map_err(|e| (500, format!("Failed to list drafts: {}", e)))?;
+
+        drafts.extend(result.items().iter().filter_map(parse_draft));
+
+        start_key = result.last_evaluated_key().cloned();
+        if start_key.is_none() {
+            break;
+        }
+    }
+
+    drafts.sort_by_key(|d| std::cmp::Reverse(d.updated_at));
+    Ok(drafts)
+}
+
+async fn count_drafts(db: &DynamoClient, user_id: &str) -> Result<usize, (u16, String)> {
+    let mut count = 0usize;
+    let mut start_key: Option<HashMap<String, AttributeValue>> = None;
+
+    loop {
+        let result = db
+            .query()
+            .table_name(get_table("DRAFTS_TABLE"))
+            .key_condition_expression("user_id = :uid")
+            .expression_attribute_values(":uid", AttributeValue::S(user_id.to_string()))
+            .select(Select::Count)
+            .set_exclusive_start_key(start_key.take())
+            .send()
+            .await
+            .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+        count += result.count() as usize;
+
+        start_key = result.last_evaluated_key().cloned();
+        if start_key.is_none() {
+            break;
+        }
+    }
+
+    Ok(count)
+}
+
+async fn draft_exists(db: &DynamoClient, user_id: &str, scope_id: &str) -> Result<bool, (u16, String)> {
+    let result = db
+        .get_item()
+        .table_name(get_table("DRAFTS_TABLE"))
+        .key("user_id", AttributeValue::S(user_id.to_string()))
+        .key("scope_id", AttributeValue::S(scope_id.to_string()))
+        .projection_expression("scope_id")
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+    Ok(result.item().is_some())
+}
+
+/// Create or replace the caller's draft for a channel or conversation
+pub async fn put_draft(
+    db: &DynamoClient,
+    user_id: &str,
+    scope_id: &str,
+    body: &str,
+) -> Result<Draft, (u16, String)> {
+    if scope_id.is_empty() || scope_id.len() > MAX_SCOPE_ID_CHARS {
+        return Err((400, "Invalid draft scope".to_string()));
+    }
+
+    let req: PutDraftRequest = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request: {}", e)))?;
+
+    if req.content.is_empty() {
+        return Err((400, "Draft content cannot be empty; delete the draft instead".to_string()));
+    }
+    if req.content.chars().count() > MAX_DRAFT_CHARS {
+        return Err((
+            400,
+            format!("Draft cannot exceed {} characters", MAX_DRAFT_CHARS),
+        ));
+    }
+
+    // Replacing an existing draft never counts against the cap
+    if !draft_exists(db, user_id, scope_id).await?
+        && count_drafts(db, user_id).await? >= MAX_DRAFTS_PER_USER
+    {
+        return Err((
+            400,
+            format!("You cannot have more than {} drafts", MAX_DRAFTS_PER_USER),
+        ));
+    }
+
+    let draft = Draft {
+        scope_id: scope_id.to_string(),
+        content: req.content,
+        updated_at: clock::now_millis(),
+    };
+
+    db.put_item()
+        .table_name(get_table("DRAFTS_TABLE"))
+        .item("user_id", AttributeValue::S(user_id.to_string()))
+        .item("scope_id", AttributeValue::S(draft.scope_id.clone()))
+        .item("content", AttributeValue::S(draft.content.clone()))
+        .item("updated_at", AttributeValue::N(draft.updated_at.to_string()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to save draft: {}", e)))?;
+
+    Ok(draft)
+}
+
+/// Remove a draft (e.g. once it's been sent). Deleting a missing draft is
+/// not an error.
+pub async fn delete_draft(db: &DynamoClient, user_id: &str, scope_id: &str) -> Result<(), (u16, String)> {
+    db.delete_item()
+        .table_name(get_table("DRAFTS_TABLE"))
+        .key("user_id", AttributeValue::S(user_id.to_string()))
+        .key("scope_id", AttributeValue::S(scope_id.to_string()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to delete draft: {}", e)))?;
+
+    Ok(())
+}
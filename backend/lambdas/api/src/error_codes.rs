@@ -0,0 +1,123 @@
+// Machine-readable codes sent as `code` next to the human `error` message.
+// Handlers keep returning `(status, message)`; the router looks the code up
+// here so clients can branch on something stable and only show `error`.
+// Messages are matched by prefix, which covers ones with formatted details,
+// and anything unlisted falls back to a code for its status.
+
+const MESSAGE_CODES: &[(&str, &str)] = &[
+    ("Invalid request", "invalid_request"),
+    ("Invalid cursor", "invalid_cursor"),
+    ("Invalid path", "invalid_path"),
+    ("Invalid email or password", "invalid_credentials"),
+    ("Invalid server name or password", "invalid_server_credentials"),
+    ("Invalid or expired reset token", "invalid_reset_token"),
+    ("Invalid or expired refresh token", "invalid_refresh_token"),
+    ("Session has been revoked", "session_revoked"),
+    ("Instance admin access required", "admin_required"),
+    ("You are not a member of this server", "not_a_member"),
+    ("You are not a participant in this conversation", "not_a_participant"),
+    ("You are already a member of this server", "already_a_member"),
+    ("You are timed out", "timed_out"),
+    ("You are banned from this server", "banned"),
+    ("You don't have permission", "missing_permission"),
+    ("Only moderators can post in announcement channels", "announcement_channel"),
+    ("Only ", "insufficient_role"),
+    ("Server not found", "server_not_found"),
+    ("Channel not found", "channel_not_found"),
+    ("Message not found", "message_not_found"),
+    ("Member not found", "member_not_found"),
+    ("User not found", "user_not_found"),
+    ("Invite not found", "invite_not_found"),
+    ("Password not found", "password_not_found"),
+    ("Role not found", "role_not_found"),
+    ("Session not found", "session_not_found"),
+    ("Follow not found", "follow_not_found"),
+    ("No public key published", "public_key_not_found"),
+    ("This invite has expired", "invite_expired"),
+    ("This invite has reached its usage limit", "invite_exhausted"),
+    ("This invite is no longer valid", "invite_invalid"),
+    ("You have already used this invite", "invite_already_used"),
+    ("This server requires an invite or password to join", "invite_required"),
+    ("This user doesn't accept direct messages", "dms_disabled"),
+    ("This user only accepts direct messages", "dms_restricted"),
+    ("This channel is read-only", "channel_read_only"),
+    ("Cannot post text to a voice channel", "voice_channel"),
+    ("Message content", "invalid_content"),
+    ("Email already registered", "email_taken"),
+    ("A server with this name already exists", "server_name_taken"),
+    ("Too many requests", "rate_limited"),
+];
+
+fn status_code(status: u16) -> &'static str {
+    match status {
+        400 => "bad_request",
+        401 => "unauthorized",
+        403 => "forbidden",
+        404 => "not_found",
+        405 => "method_not_allowed",
+        409 => "conflict",
+        410 => "gone",
+        413 => "payload_too_large",
+        429 => "rate_limited",
+        503 => "unavailable",
+        504 => "timeout",
+        _ if status >= 500 => "internal_error",
+        _ => "error",
+    }
+}
+
+/// The code for an error response. Server errors always get the status
+/// code, since their messages carry internal detail rather than a reason.
+pub fn error_code(status: u16, message: &str) -> &'static str {
+    if status < 500 {
+        if let Some((_, code)) = MESSAGE_CODES.iter().find(|(prefix, _)| message.starts_with(prefix)) {
+            return code;
+        }
+    }
+    status_code(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_messages_get_their_code() {
+        assert_eq!(error_code(404, "Server not found"), "server_not_found");
+        assert_eq!(error_code(404, "Channel not found"), "channel_not_found");
+        assert_eq!(error_code(403, "You are not a member of this server"), "not_a_member");
+        assert_eq!(error_code(400, "Invalid request body: expected value at line 1"), "invalid_request");
+    }
+
+    #[test]
+    fn longer_prefixes_win_over_shorter_ones() {
+        assert_eq!(
+            error_code(403, "Only moderators can post in announcement channels"),
+            "announcement_channel"
+        );
+        assert_eq!(error_code(403, "Only the owner can do that"), "insufficient_role");
+    }
+
+    #[test]
+    fn unknown_messages_fall_back_to_the_status() {
+        assert_eq!(error_code(404, "Nothing here"), "not_found");
+        assert_eq!(error_code(409, "Already done"), "conflict");
+        assert_eq!(error_code(418, "Teapot"), "error");
+    }
+
+    #[test]
+    fn server_errors_ignore_the_message() {
+        assert_eq!(error_code(500, "Server not found"), "internal_error");
+        assert_eq!(error_code(503, "Server not found"), "unavailable");
+    }
+
+    #[test]
+    fn longer_prefixes_are_listed_first() {
+        // A prefix listed after a shorter prefix of itself could never match
+        for (i, (prefix, _)) in MESSAGE_CODES.iter().enumerate() {
+            for (earlier, _) in &MESSAGE_CODES[..i] {
+                assert!(!prefix.starts_with(earlier), "{:?} is shadowed by {:?}", prefix, earlier);
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use aws_sdk_dynamodb::Client as DynamoClient;
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+
+use crate::cursor;
+use crate::dms::{self, DirectMessage};
+
+// A single time-ordered stream of activity aimed at the user. Each source is
+// queried for its newest items before a shared time bound and the results
+// are merged in memory; the cursor carries the bound for the next page.
+//
+// Direct messages are the only source today. Mentions and favorited
+// channels aren't tracked anywhere yet; they slot in as further `FeedItem`
+// variants once they are.
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum FeedItem {
+    /// A DM someone sent the user
+    Dm { message: DirectMessage },
+}
+
+impl FeedItem {
+    fn created_at(&self) -> i64 {
+        match self {
+            FeedItem::Dm { message } => message.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeedPage {
+    pub items: Vec<FeedItem>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+/// Conversations read per page; the feed covers the most recently active
+const MAX_FEED_CONVERSATIONS: usize = 25;
+/// DM queries in flight at once
+const FEED_CONCURRENCY: usize = 8;
+
+pub async fn get_feed(
+    db: &DynamoClient,
+    user_id: &str,
+    limit: usize,
+    cursor: Option<&str>,
+) -> Result<FeedPage, (u16, String)> {
+    let limit = limit.clamp(1, 100);
+    let cursor_scope = format!("feed:{}", user_id);
+    let before: Option<i64> = cursor
+        .map(|c| cursor::decode_cursor(&cursor_scope, c))
+        .transpose()?
+        .map(|key| key.parse().map_err(|_| (400, "Invalid cursor".to_string())))
+        .transpose()?;
+
+    let (mut items, complete_after) = received_dms(db, user_id, limit, before).await?;
+
+    // Below `complete_after` some source may have more items we didn't fetch,
+    // so the page stops there and the next one picks up from it
+    if let Some(floor) = complete_after {
+        items.retain(|item| item.created_at() >= floor);
+    }
+    items.sort_by_key(|item| std::cmp::Reverse(item.created_at()));
+
+    let next_before = if items.len() > limit {
+        items.truncate(limit);
+        items.last().map(FeedItem::created_at)
+    } else {
+        complete_after
+    };
+    let next_cursor = next_before
+        .map(|b| cursor::encode_cursor(&cursor_scope, &b.to_string()))
+        .transpose()?;
+
+    Ok(FeedPage { items, next_cursor })
+}
+
+/// The newest DMs from other people before `before`, plus the time above
+/// which the result is complete (None when every conversation was read to
+/// the end)
+async fn received_dms(
+    db: &DynamoClient,
+    user_id: &str,
+    limit: usize,
+    before: Option<i64>,
+) -> Result<(Vec<FeedItem>, Option<i64>), (u16, String)> {
+    let conversations = dms::list_conversations(db, user_id).await?;
+    // A conversation last updated after the bound may still hold older
+    // messages, but one whose first message is after it can't contribute
+    let conversation_ids: Vec<String> = conversations
+        .into_iter()
+        .filter(|c| before.is_none_or(|b| c.created_at < b))
+        .take(MAX_FEED_CONVERSATIONS)
+        .map(|c| c.id)
+        .collect();
+
+    let results: Vec<Result<Vec<DirectMessage>, (u16, String)>> = stream::iter(conversation_ids)
+        .map(|conversation_id| async move {
+            dms::recent_dm_messages(db, &conversation_id, limit, before).await
+        })
+        .buffer_unordered(FEED_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut items = Vec::new();
+    let mut complete_after: Option<i64> = None;
+    for result in results {
+        let messages = result?;
+        // A full page means the conversation may go further back
+        if messages.len() == limit {
+            if let Some(oldest) = messages.last() {
+                complete_after = complete_after.max(Some(oldest.created_at));
+            }
+        }
+        items.extend(
+            messages
+                .into_iter()
+                .filter(|m| m.author_id != user_id)
+                .map(|message| FeedItem::Dm { message }),
+        );
+    }
+    Ok((items, complete_after))
+}
@@ -0,0 +1,320 @@
+use aws_sdk_apigatewaymanagement::Client as ApiGwClient;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::clock;
+use crate::messages::{self, check_membership, ForwardedFrom, Message};
+use crate::roles;
+use crate::servers::{get_channel_record, get_server_record, next_message_seq, CHANNEL_TYPE_ANNOUNCEMENT, CHANNEL_TYPE_TEXT};
+
+// A follow mirrors every new post in an announcement channel (server A) into
+// a text channel of another server (B). Rows are keyed by the source channel
+// so posting only needs one query to find where to mirror.
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Follow {
+    pub source_server_id: String,
+    pub source_channel_id: String,
+    pub target_server_id: String,
+    pub target_channel_id: String,
+    pub created_by: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FollowRequest {
+    pub target_server_id: String,
+    pub target_channel_id: String,
+}
+
+/// Follows mirrored per announcement; more are ignored
+const MAX_FOLLOWS_PER_CHANNEL: usize = 100;
+/// Mirrored copies written at once
+const MIRROR_CONCURRENCY: usize = 8;
+/// The POST message response waits on mirroring, so it gives up after this;
+/// copies not written by then are skipped
+const MIRROR_BUDGET: Duration = Duration::from_millis(1500);
+
+fn get_table(name: &str) -> String {
+    env::var(name).unwrap_or_else(|_| {
+        format!(
+            "agorusta-{}-dev",
+            name.to_lowercase().replace("_table", "s")
+        )
+    })
+}
+
+fn parse_follow(item: &HashMap<String, AttributeValue>) -> Option<Follow> {
+    Some(Follow {
+        source_server_id: item.get("source_server_id")?.as_s().ok()?.clone(),
+        source_channel_id: item.get("source_channel_id")?.as_s().ok()?.clone(),
+        target_server_id: item.get("target_server_id")?.as_s().ok()?.clone(),
+        target_channel_id: item.get("target_channel_id")?.as_s().ok()?.clone(),
+        created_by: item.get("created_by")?.as_s().ok()?.clone(),
+        created_at: item.get("created_at")?.as_n().ok()?.parse().ok()?,
+    })
+}
+
+/// Mirror an announcement channel into a channel of another server. The
+/// caller must be a member of the source server and able to manage channels
+/// in the target.
+pub async fn follow_channel(
+    db: &DynamoClient,
+    source_server_id: &str,
+    source_channel_id: &str,
+    user_id: &str,
+    body: &str,
+) -> Result<Follow, (u16, String)> {
+    check_membership(db, source_server_id, user_id).await?;
+
+    let req: FollowRequest = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request: {}", e)))?;
+    let source = get_channel_record(db, source_server_id, source_channel_id).await?;
+    if source.channel_type != CHANNEL_TYPE_ANNOUNCEMENT {
+        return Err((400, "Only announcement channels can be followed".to_string()));
+    }
+
+    if req.target_server_id == source_server_id {
+        return Err((400, "Cannot follow a channel into its own server".to_string()));
+    }
+    if !roles::has_permission(db, &req.target_server_id, user_id, roles::MANAGE_CHANNELS).await? {
+        return Err((403, "You don't have permission to manage channels in the target server".to_string()));
+    }
+    let target = get_channel_record(db, &req.target_server_id, &req.target_channel_id).await?;
+    if target.channel_type != CHANNEL_TYPE_TEXT {
+        return Err((400, "Announcements can only be mirrored into a text channel".to_string()));
+    }
+
+    let follow = Follow {
+        source_server_id: source_server_id.to_string(),
+        source_channel_id: source_channel_id.to_string(),
+        target_server_id: req.target_server_id,
+        target_channel_id: req.target_channel_id,
+        created_by: user_id.to_string(),
+        created_at: clock::now_secs(),
+    };
+
+    db.put_item()
+        .table_name(get_table("FOLLOWS_TABLE"))
+        .item("source_channel_id", AttributeValue::S(follow.source_channel_id.clone()))
+        .item("target_channel_id", AttributeValue::S(follow.target_channel_id.clone()))
+        .item("source_server_id", AttributeValue::S(follow.source_server_id.clone()))
+        .item("target_server_id", AttributeValue::S(follow.target_server_id.clone()))
+        .item("created_by", AttributeValue::S(follow.created_by.clone()))
+        .item("created_at", AttributeValue::N(follow.created_at.to_string()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to follow channel: {}", e)))?;
+
+    Ok(follow)
+}
+
+/// Stop mirroring into a target channel. Needs manage_channels in the
+/// target server, same as following.
+pub async fn unfollow_channel(
+    db: &DynamoClient,
+    source_server_id: &str,
+    source_channel_id: &str,
+    target_channel_id: &str,
+    user_id: &str,
+) -> Result<(), (u16, String)> {
+    let result = db
+        .get_item()
+        .table_name(get_table("FOLLOWS_TABLE"))
+        .key("source_channel_id", AttributeValue::S(source_channel_id.to_string()))
+        .key("target_channel_id", AttributeValue::S(target_channel_id.to_string()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+    let follow = result
+        .item()
+        .and_then(parse_follow)
+        .filter(|f| f.source_server_id == source_server_id)
+        .ok_or((404, "Follow not found".to_string()))?;
+
+    if !roles::has_permission(db, &follow.target_server_id, user_id, roles::MANAGE_CHANNELS).await? {
+        return Err((403, "You don't have permission to manage channels in the target server".to_string()));
+    }
+
+    db.delete_item()
+        .table_name(get_table("FOLLOWS_TABLE"))
+        .key("source_channel_id", AttributeValue::S(source_channel_id.to_string()))
+        .key("target_channel_id", AttributeValue::S(target_channel_id.to_string()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to unfollow channel: {}", e)))?;
+
+    Ok(())
+}
+
+/// Copy a just-posted message into every channel following its channel and
+/// broadcast the copies, `MIRROR_CONCURRENCY` at a time and within
+/// `MIRROR_BUDGET`. Best effort: failures are logged, and the original post
+/// has already succeeded.
+pub async fn mirror_announcement(
+    db: &DynamoClient,
+    apigw: Option<&ApiGwClient>,
+    server_id: &str,
+    message: &Message,
+) {
+    let step = mirror(db, apigw, server_id, message);
+    if tokio::time::timeout(MIRROR_BUDGET, step).await.is_err() {
+        tracing::warn!(channel_id = %message.channel_id, "Mirroring announcement timed out");
+    }
+}
+
+async fn mirror(
+    db: &DynamoClient,
+    apigw: Option<&ApiGwClient>,
+    server_id: &str,
+    message: &Message,
+) {
+    let result = db
+        .query()
+        .table_name(get_table("FOLLOWS_TABLE"))
+        .key_condition_expression("source_channel_id = :cid")
+        .expression_attribute_values(":cid", AttributeValue::S(message.channel_id.clone()))
+        .limit(MAX_FOLLOWS_PER_CHANNEL as i32)
+        .send()
+        .await;
+
+    let follows: Vec<Follow> = match result {
+        Ok(output) => output.items().iter().filter_map(parse_follow).collect(),
+        Err(e) => {
+            tracing::warn!(channel_id = %message.channel_id, error = %e, "Failed to load follows");
+            return;
+        }
+    };
+    if follows.is_empty() {
+        return;
+    }
+
+    let server_name = get_server_record(db, server_id).await.ok().map(|s| s.name);
+
+    stream::iter(follows)
+        .for_each_concurrent(MIRROR_CONCURRENCY, |follow| {
+            mirror_to(db, apigw, message, server_name.clone(), follow)
+        })
+        .await;
+}
+
+/// Write and broadcast one mirrored copy
+async fn mirror_to(
+    db: &DynamoClient,
+    apigw: Option<&ApiGwClient>,
+    message: &Message,
+    server_name: Option<String>,
+    follow: Follow,
+) {
+    let created_at = clock::now_millis();
+    let seq = match next_message_seq(db, &follow.target_server_id, &follow.target_channel_id, created_at).await {
+        Ok(seq) => seq,
+        Err((_, e)) => {
+            tracing::warn!(target_channel_id = %follow.target_channel_id, error = %e, "Failed to mirror announcement");
+            return;
+        }
+    };
+    let mirrored = Message {
+        id: Uuid::new_v4().to_string(),
+        channel_id: follow.target_channel_id.clone(),
+        author_id: message.author_id.clone(),
+        author_username: message.author_username.clone(),
+        content: message.content.clone(),
+        created_at,
+        seq: Some(seq),
+        forwarded_from: Some(ForwardedFrom {
+            author_id: message.author_id.clone(),
+            author_username: message.author_username.clone(),
+            source_type: "announcement".to_string(),
+            created_at: message.created_at,
+            server_name,
+        }),
+        author_is_member: None,
+    };
+
+    if let Err((_, e)) = messages::store_message(db, &mirrored).await {
+        tracing::warn!(target_channel_id = %follow.target_channel_id, error = %e, "Failed to mirror announcement");
+        return;
+    }
+    if let Some(apigw) = apigw {
+        messages::broadcast_message(db, apigw, &follow.target_server_id, &mirrored).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_dynamodb::operation::get_item::GetItemOutput;
+    use aws_sdk_dynamodb::operation::put_item::{PutItemError, PutItemOutput};
+    use aws_sdk_dynamodb::operation::query::QueryOutput;
+    use aws_sdk_dynamodb::operation::update_item::UpdateItemOutput;
+    use aws_sdk_dynamodb::types::error::ResourceNotFoundException;
+    use aws_smithy_mocks::{mock, mock_client, MockResponse, RuleMode};
+    use std::sync::{Arc, Mutex};
+
+    fn follow_row(target_channel_id: &str) -> HashMap<String, AttributeValue> {
+        HashMap::from([
+            ("source_server_id".to_string(), AttributeValue::S("server-a".to_string())),
+            ("source_channel_id".to_string(), AttributeValue::S("news".to_string())),
+            ("target_server_id".to_string(), AttributeValue::S("server-b".to_string())),
+            ("target_channel_id".to_string(), AttributeValue::S(target_channel_id.to_string())),
+            ("created_by".to_string(), AttributeValue::S("user-1".to_string())),
+            ("created_at".to_string(), AttributeValue::N("0".to_string())),
+        ])
+    }
+
+    #[tokio::test]
+    async fn failed_copy_does_not_stop_the_others() {
+        let follows = mock!(aws_sdk_dynamodb::Client::query).then_output(|| {
+            QueryOutput::builder()
+                .items(follow_row("chan-1"))
+                .items(follow_row("chan-2"))
+                .items(follow_row("chan-3"))
+                .build()
+        });
+        let server = mock!(aws_sdk_dynamodb::Client::get_item).then_output(|| GetItemOutput::builder().build());
+        let seq = mock!(aws_sdk_dynamodb::Client::update_item).then_output(|| {
+            UpdateItemOutput::builder()
+                .attributes("message_seq", AttributeValue::N("1".to_string()))
+                .build()
+        });
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let stored = written.clone();
+        let put = mock!(aws_sdk_dynamodb::Client::put_item).then_compute_response(move |input| {
+            let channel_id = input.item().unwrap()["channel_id"].as_s().unwrap().clone();
+            if channel_id == "chan-2" {
+                return MockResponse::Error(PutItemError::ResourceNotFoundException(
+                    ResourceNotFoundException::builder().build(),
+                ));
+            }
+            stored.lock().unwrap().push(channel_id);
+            MockResponse::Output(PutItemOutput::builder().build())
+        });
+        let db = mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&follows, &server, &seq, &put]);
+
+        let message = Message {
+            id: "msg-1".to_string(),
+            channel_id: "news".to_string(),
+            author_id: "user-1".to_string(),
+            author_username: "ada".to_string(),
+            content: "Release day".to_string(),
+            created_at: 0,
+            seq: Some(1),
+            forwarded_from: None,
+            author_is_member: None,
+        };
+        mirror_announcement(&db, None, "server-a", &message).await;
+
+        let mut written = written.lock().unwrap().clone();
+        written.sort();
+        assert_eq!(written, vec!["chan-1".to_string(), "chan-3".to_string()]);
+    }
+}
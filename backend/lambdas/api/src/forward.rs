@@ -0,0 +1,163 @@
+use aws_sdk_dynamodb::Client as DynamoClient;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::clock;
+use crate::dms::{self, DirectMessage};
+use crate::messages::{self, ForwardedFrom, Message};
+use crate::servers::{get_channel_record, next_message_seq, CHANNEL_TYPE_VOICE};
+
+// Forwarding copies a message's content into another channel or DM. The
+// copy is authored by the forwarder and carries `forwarded_from` naming the
+// original author.
+
+/// Where a message lives (source) or should be copied to (target). Targets
+/// ignore `message_id`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MessageRef {
+    Channel {
+        server_id: String,
+        channel_id: String,
+        message_id: Option<String>,
+    },
+    Dm {
+        conversation_id: String,
+        message_id: Option<String>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ForwardRequest {
+    pub source: MessageRef,
+    pub target: MessageRef,
+}
+
+/// The newly created copy, tagged with where it landed
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ForwardedMessage {
+    Channel { server_id: String, message: Message },
+    Dm { message: DirectMessage },
+}
+
+/// Content and attribution of the message being forwarded
+struct Original {
+    content: String,
+    forwarded_from: ForwardedFrom,
+}
+
+async fn load_source(
+    db: &DynamoClient,
+    source: &MessageRef,
+    user_id: &str,
+) -> Result<Original, (u16, String)> {
+    let not_found = || (404, "Message not found".to_string());
+
+    match source {
+        MessageRef::Channel { server_id, channel_id, message_id } => {
+            let message_id = message_id
+                .as_deref()
+                .ok_or((400, "source.message_id is required".to_string()))?;
+            messages::check_membership(db, server_id, user_id).await?;
+            get_channel_record(db, server_id, channel_id).await?;
+
+            let message = messages::find_message(db, channel_id, message_id)
+                .await?
+                .ok_or_else(not_found)?;
+            let forwarded_from = message.forwarded_from.unwrap_or(ForwardedFrom {
+                author_id: message.author_id,
+                author_username: message.author_username,
+                source_type: "channel".to_string(),
+                created_at: message.created_at,
+                server_name: None,
+            });
+            Ok(Original { content: message.content, forwarded_from })
+        }
+        MessageRef::Dm { conversation_id, message_id } => {
+            let message_id = message_id
+                .as_deref()
+                .ok_or((400, "source.message_id is required".to_string()))?;
+            dms::verify_participant(db, conversation_id, user_id).await?;
+
+            let message = dms::find_dm_message(db, conversation_id, message_id)
+                .await?
+                .ok_or_else(not_found)?;
+            // Ciphertext is bound to the original recipient's key
+            if message.encrypted {
+                return Err((400, "Encrypted messages cannot be forwarded".to_string()));
+            }
+            let forwarded_from = message.forwarded_from.unwrap_or(ForwardedFrom {
+                author_id: message.author_id,
+                author_username: message.author_username,
+                source_type: "dm".to_string(),
+                created_at: message.created_at,
+                server_name: None,
+            });
+            Ok(Original { content: message.content, forwarded_from })
+        }
+    }
+}
+
+/// Copy a message the user can read into a channel or DM they can write to
+pub async fn forward_message(
+    db: &DynamoClient,
+    user_id: &str,
+    username: &str,
+    body: &str,
+) -> Result<ForwardedMessage, (u16, String)> {
+    let req: ForwardRequest = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request: {}", e)))?;
+
+    let original = load_source(db, &req.source, user_id).await?;
+    let now = clock::now_millis();
+
+    match req.target {
+        MessageRef::Channel { server_id, channel_id, .. } => {
+            let member = messages::check_can_post(db, &server_id, user_id).await?;
+            let channel = get_channel_record(db, &server_id, &channel_id).await?;
+            if channel.channel_type == CHANNEL_TYPE_VOICE && !channel.text_in_voice {
+                return Err((400, "Cannot post text to a voice channel".to_string()));
+            }
+            messages::check_channel_poster(db, &channel, &member).await?;
+            let seq = next_message_seq(db, &server_id, &channel_id, now).await?;
+
+            let message = Message {
+                id: Uuid::new_v4().to_string(),
+                channel_id,
+                author_id: user_id.to_string(),
+                author_username: username.to_string(),
+                content: original.content,
+                created_at: now,
+                seq: Some(seq),
+                forwarded_from: Some(original.forwarded_from),
+                author_is_member: None,
+            };
+            messages::store_message(db, &message).await?;
+
+            Ok(ForwardedMessage::Channel { server_id, message })
+        }
+        MessageRef::Dm { conversation_id, .. } => {
+            let conversation = dms::verify_participant(db, &conversation_id, user_id).await?;
+            dms::check_recipient_accepts_dms(db, &conversation).await?;
+
+            let preview = dms::text_preview(&original.content);
+            let message = DirectMessage {
+                id: Uuid::new_v4().to_string(),
+                conversation_id,
+                author_id: user_id.to_string(),
+                author_username: username.to_string(),
+                content: original.content,
+                created_at: now,
+                encrypted: false,
+                key_id: None,
+                forwarded_from: Some(original.forwarded_from),
+                reply_to: None,
+            };
+            dms::store_dm_message(db, &conversation, &message, &preview).await?;
+
+            Ok(ForwardedMessage::Dm { message })
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use aws_sdk_dynamodb::types::{AttributeValue, Select};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+
+use crate::clock;
+use crate::messages::{self, Message};
+use crate::servers::{get_channel_record, load_membership};
+
+// Server highlights are a board of messages picked by owners/admins from any
+// channel. Rows only reference the message; content is read from the
+// messages table on listing so edits show up and deletions drop out.
+
+/// Stored reference to a highlighted message
+struct HighlightRef {
+    channel_id: String,
+    message_id: String,
+    highlighted_by: String,
+    highlighted_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Highlight {
+    pub message: Message,
+    pub highlighted_by: String,
+    pub highlighted_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HighlightRequest {
+    pub channel_id: String,
+    pub message_id: String,
+}
+
+const MAX_HIGHLIGHTS_PER_SERVER: usize = 50;
+/// Message lookups in flight at once when hydrating the board
+const HYDRATE_CONCURRENCY: usize = 8;
+
+fn get_table(name: &str) -> String {
+    env::var(name).unwrap_or_else(|_| {
+        format!(
+            "agorusta-{}-dev",
+            name.to_lowercase().replace("_table", "s")
+        )
+    })
+}
+
+async fn require_owner_or_admin(db: &DynamoClient, server_id: &str, user_id: &str) -> Result<(), (u16, String)> {
+    let member = load_membership(db, server_id, user_id).await?;
+    if member.role != "owner" && member.role != "admin" {
+        return Err((403, "Only owners and admins can manage highlights".to_string()));
+    }
+    Ok(())
+}
+
+async fn count_highlights(db: &DynamoClient, server_id: &str) -> Result<usize, (u16, String)> {
+    let result = db
+        .query()
+        .table_name(get_table("HIGHLIGHTS_TABLE"))
+        .key_condition_expression("server_id = :sid")
+        .expression_attribute_values(":sid", AttributeValue::S(server_id.to_string()))
+        .select(Select::Count)
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+    Ok(result.count() as usize)
+}
+
+/// Add a message to the server's highlights board (owner/admin).
+/// Highlighting the same message again is a no-op.
+pub async fn pin_to_server(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+    body: &str,
+) -> Result<Highlight, (u16, String)> {
+    require_owner_or_admin(db, server_id, user_id).await?;
+
+    let req: HighlightRequest = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request: {}", e)))?;
+    get_channel_record(db, server_id, &req.channel_id).await?;
+    let message = messages::find_message(db, &req.channel_id, &req.message_id)
+        .await?
+        .ok_or((404, "Message not found".to_string()))?;
+
+    if count_highlights(db, server_id).await? >= MAX_HIGHLIGHTS_PER_SERVER {
+        return Err((
+            400,
+            format!("A server cannot have more than {} highlights", MAX_HIGHLIGHTS_PER_SERVER),
+        ));
+    }
+
+    let highlighted_at = clock::now_secs();
+    let result = db
+        .put_item()
+        .table_name(get_table("HIGHLIGHTS_TABLE"))
+        .item("server_id", AttributeValue::S(server_id.to_string()))
+        .item("message_id", AttributeValue::S(message.id.clone()))
+        .item("channel_id", AttributeValue::S(message.channel_id.clone()))
+        .item("highlighted_by", AttributeValue::S(user_id.to_string()))
+        .item("highlighted_at", AttributeValue::N(highlighted_at.to_string()))
+        .condition_expression("attribute_not_exists(message_id)")
+        .send()
+        .await;
+
+    if let Err(e) = result {
+        let exists = e
+            .as_service_error()
+            .map(|se| se.is_conditional_check_failed_exception())
+            .unwrap_or(false);
+        if !exists {
+            return Err((500, format!("Failed to add highlight: {}", e)));
+        }
+    }
+
+    Ok(Highlight {
+        message,
+        highlighted_by: user_id.to_string(),
+        highlighted_at,
+    })
+}
+
+/// Remove a message from the highlights board (owner/admin)
+pub async fn unpin_from_server(
+    db: &DynamoClient,
+    server_id: &str,
+    message_id: &str,
+    user_id: &str,
+) -> Result<(), (u16, String)> {
+    require_owner_or_admin(db, server_id, user_id).await?;
+
+    db.delete_item()
+        .table_name(get_table("HIGHLIGHTS_TABLE"))
+        .key("server_id", AttributeValue::S(server_id.to_string()))
+        .key("message_id", AttributeValue::S(message_id.to_string()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to remove highlight: {}", e)))?;
+
+    Ok(())
+}
+
+/// The server's highlights with full message content, most recently
+/// highlighted first. Messages deleted since being highlighted are skipped.
+pub async fn list_server_pins(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+) -> Result<Vec<Highlight>, (u16, String)> {
+    load_membership(db, server_id, user_id).await?;
+
+    let result = db
+        .query()
+        .table_name(get_table("HIGHLIGHTS_TABLE"))
+        .key_condition_expression("server_id = :sid")
+        .expression_attribute_values(":sid", AttributeValue::S(server_id.to_string()))
+        .limit(MAX_HIGHLIGHTS_PER_SERVER as i32)
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to list highlights: {}", e)))?;
+
+    let refs: Vec<HighlightRef> = result.items().iter().filter_map(parse_highlight_ref).collect();
+
+    let hydrated: Vec<Result<Option<Highlight>, (u16, String)>> = stream::iter(refs)
+        .map(|r| async move {
+            let message = messages::find_message(db, &r.channel_id, &r.message_id).await?;
+            Ok(message.map(|message| Highlight {
+                message,
+                highlighted_by: r.highlighted_by,
+                highlighted_at: r.highlighted_at,
+            }))
+        })
+        .buffer_unordered(HYDRATE_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut highlights = Vec::new();
+    for result in hydrated {
+        if let Some(highlight) = result? {
+            highlights.push(highlight);
+        }
+    }
+    highlights.sort_by_key(|h| std::cmp::Reverse(h.highlighted_at));
+
+    Ok(highlights)
+}
+
+fn parse_highlight_ref(item: &HashMap<String, AttributeValue>) -> Option<HighlightRef> {
+    Some(HighlightRef {
+        channel_id: item.get("channel_id")?.as_s().ok()?.clone(),
+        message_id: item.get("message_id")?.as_s().ok()?.clone(),
+        highlighted_by: item.get("highlighted_by")?.as_s().ok()?.clone(),
+        highlighted_at: item.get("highlighted_at")?.as_n().ok()?.parse().ok()?,
+    })
+}
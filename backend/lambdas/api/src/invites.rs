@@ -1,4 +1,5 @@
-use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue, ReturnValuesOnConditionCheckFailure};
 use aws_sdk_dynamodb::Client as DynamoClient;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
@@ -6,7 +7,13 @@ use std::env;
 use uuid::Uuid;
 
 use crate::auth::{hash_password, verify_password};
-use crate::servers::{Member, ServerWithChannels};
+use crate::clock;
+use crate::rate_limit;
+use crate::roles;
+use crate::servers::{
+    find_membership, get_server_record, is_banned, list_channels, sort_channels, Member, ServerWithChannels,
+    CHANNEL_TYPE_VOICE, JOIN_POLICY_OPEN,
+};
 
 // ============ Types ============
 
@@ -17,7 +24,9 @@ pub struct Invite {
     pub server_name: String,
     pub created_by: String,
     pub created_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub expires_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub max_uses: Option<i32>,
     pub use_count: i32,
 }
@@ -28,9 +37,14 @@ pub struct InviteInfo {
     pub server_name: String,
     pub server_id: String,
     pub member_count: usize,
+    /// Names of the server's text channels, for the join screen. Only sent
+    /// when asked for with `include_channels=true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channels: Option<Vec<String>>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct CreateInviteRequest {
     pub expires_in_hours: Option<i32>,
     pub max_uses: Option<i32>,
@@ -40,24 +54,39 @@ pub struct CreateInviteRequest {
 pub struct ServerPassword {
     pub id: String,
     pub server_id: String,
-    pub password_hash: String,
     pub created_by: String,
     pub created_at: i64,
     pub expires_at: Option<i64>,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct CreatePasswordRequest {
     pub password: String,
     pub expires_in_hours: Option<i32>,
 }
 
+/// Omitting `expires_in_hours` (or sending null) makes the password never expire
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdatePasswordRequest {
+    pub expires_in_hours: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct JoinByNameRequest {
     pub server_name: String,
     pub password: String,
 }
 
+/// Password join attempts allowed per (server, user) per window. Each
+/// attempt costs an Argon2 verify per stored password.
+const JOIN_PASSWORD_ATTEMPTS: u32 = 5;
+const JOIN_PASSWORD_WINDOW_SECS: i64 = 300;
+/// Most unexpired passwords checked per attempt
+const MAX_PASSWORDS_CHECKED: usize = 10;
+
 // ============ Helpers ============
 
 fn get_table(name: &str) -> String {
@@ -69,17 +98,92 @@ fn get_table(name: &str) -> String {
     })
 }
 
+fn get_max_active_invites() -> usize {
+    env::var("MAX_ACTIVE_INVITES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(50)
+}
+
+/// Count invites for a server that are neither expired nor used up
+async fn count_active_invites(db: &DynamoClient, server_id: &str) -> Result<usize, (u16, String)> {
+    let now = clock::now_secs();
+    let mut active = 0;
+    let mut start_key = None;
+
+    loop {
+        let result = db
+            .query()
+            .table_name(get_table("INVITES_TABLE"))
+            .index_name("server-invites-index")
+            .key_condition_expression("server_id = :sid")
+            .expression_attribute_values(":sid", AttributeValue::S(server_id.to_string()))
+            .projection_expression("expires_at, max_uses, use_count")
+            .set_exclusive_start_key(start_key)
+            .send()
+            .await
+            .map_err(|e| (500, format!("Failed to count invites: {}", e)))?;
+
+        active += result
+            .items()
+            .iter()
+            .filter(|item| {
+                let expired = clock::is_expired(
+                    item.get("expires_at").and_then(|v| v.as_n().ok()?.parse().ok()),
+                    now,
+                );
+                let use_count: i32 = item
+                    .get("use_count")
+                    .and_then(|v| v.as_n().ok()?.parse().ok())
+                    .unwrap_or(0);
+                let exhausted = item
+                    .get("max_uses")
+                    .and_then(|v| v.as_n().ok()?.parse::<i32>().ok())
+                    .is_some_and(|max| use_count >= max);
+                !expired && !exhausted
+            })
+            .count();
+
+        start_key = result.last_evaluated_key().cloned();
+        if start_key.is_none() {
+            break;
+        }
+    }
+
+    Ok(active)
+}
+
+/// Invite code length from `INVITE_CODE_LENGTH`, clamped to 6-16. Longer
+/// codes are harder to guess on instances that need it.
+fn get_invite_code_length() -> usize {
+    env::var("INVITE_CODE_LENGTH")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(8)
+        .clamp(6, MAX_INVITE_CODE_LENGTH)
+}
+
+/// No 0/O, 1/l/I, so codes survive being read aloud or retyped
+const INVITE_CODE_CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZabcdefghjkmnpqrstuvwxyz23456789";
+const MAX_INVITE_CODE_LENGTH: usize = 16;
+
 fn generate_invite_code() -> String {
-    const CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZabcdefghjkmnpqrstuvwxyz23456789";
     let mut rng = rand::thread_rng();
-    (0..8)
+    (0..get_invite_code_length())
         .map(|_| {
-            let idx = rng.gen_range(0..CHARSET.len());
-            CHARSET[idx] as char
+            let idx = rng.gen_range(0..INVITE_CODE_CHARSET.len());
+            INVITE_CODE_CHARSET[idx] as char
         })
         .collect()
 }
 
+/// Whether `code` could be an invite code we generated
+pub fn is_valid_code(code: &str) -> bool {
+    !code.is_empty()
+        && code.len() <= MAX_INVITE_CODE_LENGTH
+        && code.bytes().all(|b| INVITE_CODE_CHARSET.contains(&b))
+}
+
 async fn get_server_by_id(
     db: &DynamoClient,
     server_id: &str,
@@ -149,18 +253,7 @@ async fn get_member_role(
     server_id: &str,
     user_id: &str,
 ) -> Result<Option<String>, (u16, String)> {
-    let result = db
-        .get_item()
-        .table_name(get_table("MEMBERS_TABLE"))
-        .key("server_id", AttributeValue::S(server_id.to_string()))
-        .key("user_id", AttributeValue::S(user_id.to_string()))
-        .send()
-        .await
-        .map_err(|e| (500, format!("Database error: {}", e)))?;
-
-    Ok(result
-        .item()
-        .and_then(|item| item.get("role")?.as_s().ok().cloned()))
+    Ok(find_membership(db, server_id, user_id).await?.map(|m| m.role))
 }
 
 async fn count_members(db: &DynamoClient, server_id: &str) -> Result<usize, (u16, String)> {
@@ -177,6 +270,13 @@ async fn count_members(db: &DynamoClient, server_id: &str) -> Result<usize, (u16
     Ok(result.count() as usize)
 }
 
+async fn check_not_banned(db: &DynamoClient, server_id: &str, user_id: &str) -> Result<(), (u16, String)> {
+    if is_banned(db, server_id, user_id).await? {
+        return Err((403, "You are banned from this server".to_string()));
+    }
+    Ok(())
+}
+
 pub async fn add_member(
     db: &DynamoClient,
     server_id: &str,
@@ -184,7 +284,7 @@ pub async fn add_member(
     username: &str,
     role: &str,
 ) -> Result<Member, (u16, String)> {
-    let now = chrono::Utc::now().timestamp();
+    let now = clock::now_secs();
 
     let member = Member {
         server_id: server_id.to_string(),
@@ -192,46 +292,135 @@ pub async fn add_member(
         username: username.to_string(),
         role: role.to_string(),
         joined_at: now,
+        role_ids: vec![],
+        timeout_until: None,
     };
 
-    db.put_item()
+    // Conditional so two concurrent joins can't both succeed (or clobber an
+    // existing member's role); the loser gets 409
+    let result = db
+        .put_item()
         .table_name(get_table("MEMBERS_TABLE"))
         .item("server_id", AttributeValue::S(member.server_id.clone()))
         .item("user_id", AttributeValue::S(member.user_id.clone()))
         .item("username", AttributeValue::S(member.username.clone()))
         .item("role", AttributeValue::S(member.role.clone()))
         .item("joined_at", AttributeValue::N(now.to_string()))
+        .condition_expression("attribute_not_exists(user_id)")
         .send()
-        .await
-        .map_err(|e| (500, format!("Failed to add member: {}", e)))?;
+        .await;
+
+    if let Err(e) = result {
+        let exists = e
+            .as_service_error()
+            .map(|se| se.is_conditional_check_failed_exception())
+            .unwrap_or(false);
+        if exists {
+            return Err((409, "You are already a member of this server".to_string()));
+        }
+        return Err((500, format!("Failed to add member: {}", e)));
+    }
+
+    if role == "member" {
+        send_welcome(db, server_id, &member).await;
+    }
 
     Ok(member)
 }
 
+/// Fill `{server}` and `{user}` in one pass, so a name that itself contains a
+/// placeholder is left as typed
+fn fill_welcome_template(template: &str, server_name: &str, username: &str) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        if let Some(after) = tail.strip_prefix("{server}") {
+            out.push_str(server_name);
+            rest = after;
+        } else if let Some(after) = tail.strip_prefix("{user}") {
+            out.push_str(username);
+            rest = after;
+        } else {
+            out.push('{');
+            rest = &tail[1..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// DM the server's welcome message, if it has one, to a member who just
+/// joined. Best-effort: a failure here never fails the join.
+async fn send_welcome(db: &DynamoClient, server_id: &str, member: &Member) {
+    let server = match get_server_record(db, server_id).await {
+        Ok(server) => server,
+        Err((_, e)) => {
+            tracing::warn!(server_id = %server_id, error = %e, "Failed to load server for welcome message");
+            return;
+        }
+    };
+    let Some(template) = server.welcome_message.as_deref() else {
+        return;
+    };
+    if server.owner_id == member.user_id {
+        return;
+    }
+
+    let content = fill_welcome_template(template, &server.name, &member.username);
+    if let Err((_, e)) = crate::dms::send_system_dm(
+        db,
+        &server.owner_id,
+        &member.user_id,
+        &member.username,
+        &content,
+    )
+    .await
+    {
+        tracing::warn!(server_id = %server_id, user_id = %member.user_id, error = %e, "Failed to send welcome message");
+    }
+}
+
 // ============ Invite Functions ============
 
+/// An empty body means no expiry and no use limit.
+fn parse_create_invite(body: &str) -> Result<CreateInviteRequest, (u16, String)> {
+    if body.trim().is_empty() {
+        return Ok(CreateInviteRequest::default());
+    }
+    serde_json::from_str(body).map_err(|e| (400, format!("Invalid request: {}", e)))
+}
+
 pub async fn create_invite(
     db: &DynamoClient,
     server_id: &str,
     user_id: &str,
     body: &str,
 ) -> Result<Invite, (u16, String)> {
-    // Check user is owner or admin
-    let role = get_member_role(db, server_id, user_id)
-        .await?
-        .ok_or((403, "You are not a member of this server".to_string()))?;
-
-    if role != "owner" && role != "admin" {
-        return Err((403, "Only owners and admins can create invites".to_string()));
+    // Check user can manage invites (owner, admin, or a custom role)
+    if !roles::has_permission(db, server_id, user_id, roles::MANAGE_INVITES).await? {
+        return Err((403, "You don't have permission to create invites".to_string()));
     }
 
-    let req: CreateInviteRequest = serde_json::from_str(body).unwrap_or(CreateInviteRequest {
-        expires_in_hours: None,
-        max_uses: None,
-    });
+    let req = parse_create_invite(body)?;
+
+    // A soft cap: concurrent creates can each pass the count and overshoot
+    // it by the number in flight. A counter on the server item would drift
+    // as invites expire through TTL or get used up, so it isn't worth it
+    let max_active = get_max_active_invites();
+    if count_active_invites(db, server_id).await? >= max_active {
+        return Err((
+            409,
+            format!(
+                "This server already has {} active invites. Delete unused invites before creating more",
+                max_active
+            ),
+        ));
+    }
 
     let (server_name, _) = get_server_by_id(db, server_id).await?;
-    let now = chrono::Utc::now().timestamp();
+    let now = clock::now_secs();
 
     let expires_at = req
         .expires_in_hours
@@ -295,13 +484,9 @@ pub async fn list_invites(
     server_id: &str,
     user_id: &str,
 ) -> Result<Vec<Invite>, (u16, String)> {
-    // Check user is owner or admin
-    let role = get_member_role(db, server_id, user_id)
-        .await?
-        .ok_or((403, "You are not a member of this server".to_string()))?;
-
-    if role != "owner" && role != "admin" {
-        return Err((403, "Only owners and admins can view invites".to_string()));
+    // Check user can manage invites (owner, admin, or a custom role)
+    if !roles::has_permission(db, server_id, user_id, roles::MANAGE_INVITES).await? {
+        return Err((403, "You don't have permission to view invites".to_string()));
     }
 
     let result = db
@@ -315,7 +500,7 @@ pub async fn list_invites(
         .await
         .map_err(|e| (500, format!("Failed to list invites: {}", e)))?;
 
-    let now = chrono::Utc::now().timestamp();
+    let now = clock::now_secs();
     let invites: Vec<Invite> = result
         .items()
         .iter()
@@ -325,10 +510,8 @@ pub async fn list_invites(
                 .and_then(|v| v.as_n().ok()?.parse().ok());
 
             // Filter out expired invites
-            if let Some(exp) = expires_at {
-                if exp < now {
-                    return None;
-                }
+            if clock::is_expired(expires_at, now) {
+                return None;
             }
 
             Some(Invite {
@@ -362,13 +545,9 @@ pub async fn delete_invite(
     code: &str,
     user_id: &str,
 ) -> Result<(), (u16, String)> {
-    // Check user is owner or admin
-    let role = get_member_role(db, server_id, user_id)
-        .await?
-        .ok_or((403, "You are not a member of this server".to_string()))?;
-
-    if role != "owner" && role != "admin" {
-        return Err((403, "Only owners and admins can delete invites".to_string()));
+    // Check user can manage invites (owner, admin, or a custom role)
+    if !roles::has_permission(db, server_id, user_id, roles::MANAGE_INVITES).await? {
+        return Err((403, "You don't have permission to delete invites".to_string()));
     }
 
     // Verify invite belongs to this server
@@ -416,15 +595,12 @@ pub async fn get_invite_info(db: &DynamoClient, code: &str) -> Result<InviteInfo
         .item()
         .ok_or((404, "Invite not found or expired".to_string()))?;
 
-    let now = chrono::Utc::now().timestamp();
+    let now = clock::now_secs();
 
     // Check if expired
-    if let Some(expires_at) = item.get("expires_at").and_then(|v| v.as_n().ok()) {
-        if let Ok(exp) = expires_at.parse::<i64>() {
-            if exp < now {
-                return Err((410, "This invite has expired".to_string()));
-            }
-        }
+    let expires_at = item.get("expires_at").and_then(|v| v.as_n().ok()?.parse().ok());
+    if clock::is_expired(expires_at, now) {
+        return Err((410, "This invite has expired".to_string()));
     }
 
     // Check max uses
@@ -446,6 +622,16 @@ pub async fn get_invite_info(db: &DynamoClient, code: &str) -> Result<InviteInfo
         .and_then(|v| v.as_s().ok())
         .ok_or((500, "Invalid invite data".to_string()))?;
 
+    // Servers can opt in to invites dying with their creator's membership
+    if let Some(created_by) = item.get("created_by").and_then(|v| v.as_s().ok()) {
+        let server = get_server_record(db, server_id).await?;
+        if server.invalidate_invites_on_creator_leave
+            && get_member_role(db, server_id, created_by).await?.is_none()
+        {
+            return Err((410, "This invite is no longer valid".to_string()));
+        }
+    }
+
     let server_name = item
         .get("server_name")
         .and_then(|v| v.as_s().ok())
@@ -459,9 +645,36 @@ pub async fn get_invite_info(db: &DynamoClient, code: &str) -> Result<InviteInfo
         server_name,
         server_id: server_id.clone(),
         member_count,
+        channels: None,
     })
 }
 
+/// Invite info for the join screen, optionally with the names of the
+/// server's text and announcement channels in sidebar order. Anyone holding
+/// a valid code can see these, so only names are exposed: no ids, topics
+/// or messages.
+pub async fn get_invite_preview(
+    db: &DynamoClient,
+    code: &str,
+    include_channels: bool,
+) -> Result<InviteInfo, (u16, String)> {
+    let mut info = get_invite_info(db, code).await?;
+
+    if include_channels {
+        let mut channels = list_channels(db, &info.server_id).await?;
+        sort_channels(&mut channels, Some("position"))?;
+        info.channels = Some(
+            channels
+                .into_iter()
+                .filter(|c| c.channel_type != CHANNEL_TYPE_VOICE)
+                .map(|c| c.name)
+                .collect(),
+        );
+    }
+
+    Ok(info)
+}
+
 pub async fn join_by_code(
     db: &DynamoClient,
     code: &str,
@@ -478,22 +691,146 @@ pub async fn join_by_code(
     {
         return Err((409, "You are already a member of this server".to_string()));
     }
+    check_not_banned(db, &invite_info.server_id, user_id).await?;
+
+    // Each account can redeem an invite once, so leaving and rejoining (or
+    // racing two joins) can't inflate use_count
+    record_invite_use(db, code, user_id).await?;
+
+    // Take a use before adding the member, so concurrent joins can't push
+    // use_count past max_uses
+    if let Err(e) = claim_invite_use(db, code).await {
+        release_invite_use(db, code, user_id).await;
+        return Err(e);
+    }
 
-    // Increment use count
-    db.update_item()
+    // If a concurrent join beat us this fails with 409; give the use back
+    if let Err(e) = add_member(db, &invite_info.server_id, user_id, username, "member").await {
+        unclaim_invite_use(db, code).await;
+        release_invite_use(db, code, user_id).await;
+        return Err(e);
+    }
+
+    // Return server with channels
+    crate::servers::get_server(db, &invite_info.server_id, user_id, true).await
+}
+
+/// Increment `code`'s use_count, refusing once it has expired or reached
+/// max_uses. The check is part of the write, so it can't act on a stale read.
+async fn claim_invite_use(db: &DynamoClient, code: &str) -> Result<(), (u16, String)> {
+    let now = clock::now_secs();
+    let result = db
+        .update_item()
         .table_name(get_table("INVITES_TABLE"))
         .key("code", AttributeValue::S(code.to_string()))
         .update_expression("SET use_count = use_count + :inc")
+        .condition_expression(
+            "attribute_exists(code) \
+             AND (attribute_not_exists(max_uses) OR use_count < max_uses) \
+             AND (attribute_not_exists(expires_at) OR expires_at >= :now)",
+        )
         .expression_attribute_values(":inc", AttributeValue::N("1".to_string()))
+        .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+        .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld)
         .send()
-        .await
-        .map_err(|e| (500, format!("Failed to update invite: {}", e)))?;
+        .await;
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(e) => match e.as_service_error() {
+            Some(UpdateItemError::ConditionalCheckFailedException(failed)) => {
+                let Some(item) = failed.item() else {
+                    return Err((404, "Invite not found or expired".to_string()));
+                };
+                let expires_at = item.get("expires_at").and_then(|v| v.as_n().ok()?.parse().ok());
+                if clock::is_expired(expires_at, now) {
+                    Err((410, "This invite has expired".to_string()))
+                } else {
+                    Err((410, "This invite has reached its usage limit".to_string()))
+                }
+            }
+            _ => Err((500, format!("Failed to update invite: {}", e))),
+        },
+    }
+}
 
-    // Add member
-    add_member(db, &invite_info.server_id, user_id, username, "member").await?;
+/// Undo `claim_invite_use` after a failed join
+async fn unclaim_invite_use(db: &DynamoClient, code: &str) {
+    let result = db
+        .update_item()
+        .table_name(get_table("INVITES_TABLE"))
+        .key("code", AttributeValue::S(code.to_string()))
+        .update_expression("SET use_count = use_count - :dec")
+        .condition_expression("attribute_exists(code) AND use_count > :zero")
+        .expression_attribute_values(":dec", AttributeValue::N("1".to_string()))
+        .expression_attribute_values(":zero", AttributeValue::N("0".to_string()))
+        .send()
+        .await;
+    if let Err(e) = result {
+        tracing::warn!(code = %code, error = %e, "Failed to give back invite use");
+    }
+}
 
-    // Return server with channels
-    crate::servers::get_server(db, &invite_info.server_id, user_id).await
+/// Note that `user_id` redeemed `code`, refusing a second redemption
+async fn record_invite_use(db: &DynamoClient, code: &str, user_id: &str) -> Result<(), (u16, String)> {
+    let result = db
+        .put_item()
+        .table_name(get_table("INVITE_USES_TABLE"))
+        .item("code", AttributeValue::S(code.to_string()))
+        .item("user_id", AttributeValue::S(user_id.to_string()))
+        .item("used_at", AttributeValue::N(clock::now_secs().to_string()))
+        .condition_expression("attribute_not_exists(user_id)")
+        .send()
+        .await;
+
+    if let Err(e) = result {
+        let used = e
+            .as_service_error()
+            .map(|se| se.is_conditional_check_failed_exception())
+            .unwrap_or(false);
+        if used {
+            return Err((409, "You have already used this invite".to_string()));
+        }
+        return Err((500, format!("Failed to record invite use: {}", e)));
+    }
+
+    Ok(())
+}
+
+/// Undo `record_invite_use` when the join it was for didn't happen
+async fn release_invite_use(db: &DynamoClient, code: &str, user_id: &str) {
+    let result = db
+        .delete_item()
+        .table_name(get_table("INVITE_USES_TABLE"))
+        .key("code", AttributeValue::S(code.to_string()))
+        .key("user_id", AttributeValue::S(user_id.to_string()))
+        .send()
+        .await;
+    if let Err(e) = result {
+        tracing::warn!(code = %code, user_id = %user_id, error = %e, "Failed to release invite use");
+    }
+}
+
+/// Join a server whose join policy is open, without an invite or password
+pub async fn join_open_server(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+    username: &str,
+) -> Result<ServerWithChannels, (u16, String)> {
+    let server = get_server_record(db, server_id).await?;
+    if server.join_policy != JOIN_POLICY_OPEN {
+        return Err((403, "This server requires an invite or password to join".to_string()));
+    }
+
+    if get_member_role(db, server_id, user_id).await?.is_some() {
+        return Err((409, "You are already a member of this server".to_string()));
+    }
+    check_not_banned(db, server_id, user_id).await?;
+
+    add_member(db, server_id, user_id, username, "member").await?;
+
+    crate::servers::get_server(db, server_id, user_id, true).await
 }
 
 // ============ Server Password Functions ============
@@ -526,7 +863,7 @@ pub async fn create_server_password(
     let password_hash =
         hash_password(&req.password).map_err(|e| (500, format!("Failed to hash password: {}", e)))?;
 
-    let now = chrono::Utc::now().timestamp();
+    let now = clock::now_secs();
     let expires_at = req.expires_in_hours.map(|h| now + (h as i64 * 3600));
     let id = Uuid::new_v4().to_string();
 
@@ -535,7 +872,7 @@ pub async fn create_server_password(
         .table_name(get_table("SERVER_PASSWORDS_TABLE"))
         .item("id", AttributeValue::S(id.clone()))
         .item("server_id", AttributeValue::S(server_id.to_string()))
-        .item("password_hash", AttributeValue::S(password_hash.clone()))
+        .item("password_hash", AttributeValue::S(password_hash))
         .item("created_by", AttributeValue::S(user_id.to_string()))
         .item("created_at", AttributeValue::N(now.to_string()));
 
@@ -552,7 +889,6 @@ pub async fn create_server_password(
     Ok(ServerPassword {
         id,
         server_id: server_id.to_string(),
-        password_hash,
         created_by: user_id.to_string(),
         created_at: now,
         expires_at,
@@ -584,7 +920,7 @@ pub async fn list_server_passwords(
         .await
         .map_err(|e| (500, format!("Failed to list passwords: {}", e)))?;
 
-    let now = chrono::Utc::now().timestamp();
+    let now = clock::now_secs();
     let passwords: Vec<ServerPassword> = result
         .items()
         .iter()
@@ -594,16 +930,13 @@ pub async fn list_server_passwords(
                 .and_then(|v| v.as_n().ok()?.parse().ok());
 
             // Filter out expired passwords
-            if let Some(exp) = expires_at {
-                if exp < now {
-                    return None;
-                }
+            if clock::is_expired(expires_at, now) {
+                return None;
             }
 
             Some(ServerPassword {
                 id: item.get("id")?.as_s().ok()?.clone(),
                 server_id: item.get("server_id")?.as_s().ok()?.clone(),
-                password_hash: item.get("password_hash")?.as_s().ok()?.clone(),
                 created_by: item.get("created_by")?.as_s().ok()?.clone(),
                 created_at: item.get("created_at")?.as_n().ok()?.parse().ok()?,
                 expires_at,
@@ -614,6 +947,87 @@ pub async fn list_server_passwords(
     Ok(passwords)
 }
 
+/// Change a password's expiry, counted from now. The hash can't be changed
+/// here; create a new password instead.
+pub async fn update_server_password(
+    db: &DynamoClient,
+    server_id: &str,
+    password_id: &str,
+    user_id: &str,
+    body: &str,
+) -> Result<ServerPassword, (u16, String)> {
+    // Check user is owner
+    let role = get_member_role(db, server_id, user_id)
+        .await?
+        .ok_or((403, "You are not a member of this server".to_string()))?;
+
+    if role != "owner" {
+        return Err((
+            403,
+            "Only the server owner can update passwords".to_string(),
+        ));
+    }
+
+    let req: UpdatePasswordRequest = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request: {}", e)))?;
+
+    if req.expires_in_hours.is_some_and(|h| h <= 0) {
+        return Err((400, "expires_in_hours must be positive".to_string()));
+    }
+
+    let now = clock::now_secs();
+    let expires_at = req.expires_in_hours.map(|h| now + (h as i64 * 3600));
+
+    let mut update = db
+        .update_item()
+        .table_name(get_table("SERVER_PASSWORDS_TABLE"))
+        .key("id", AttributeValue::S(password_id.to_string()))
+        // Verify password belongs to this server
+        .condition_expression("server_id = :sid")
+        .expression_attribute_values(":sid", AttributeValue::S(server_id.to_string()))
+        .return_values(ReturnValue::AllNew);
+    update = match expires_at {
+        Some(exp) => update
+            .update_expression("SET expires_at = :exp, #ttl = :exp")
+            .expression_attribute_names("#ttl", "ttl")
+            .expression_attribute_values(":exp", AttributeValue::N(exp.to_string())),
+        None => update
+            .update_expression("REMOVE expires_at, #ttl")
+            .expression_attribute_names("#ttl", "ttl"),
+    };
+
+    let result = update.send().await.map_err(|e| {
+        let missing = e
+            .as_service_error()
+            .map(|se| se.is_conditional_check_failed_exception())
+            .unwrap_or(false);
+        if missing {
+            (404, "Password not found".to_string())
+        } else {
+            (500, format!("Failed to update password: {}", e))
+        }
+    })?;
+
+    let item = result
+        .attributes()
+        .ok_or((500, "Invalid password data".to_string()))?;
+
+    Ok(ServerPassword {
+        id: password_id.to_string(),
+        server_id: server_id.to_string(),
+        created_by: item
+            .get("created_by")
+            .and_then(|v| v.as_s().ok())
+            .cloned()
+            .unwrap_or_default(),
+        created_at: item
+            .get("created_at")
+            .and_then(|v| v.as_n().ok()?.parse().ok())
+            .unwrap_or(0),
+        expires_at,
+    })
+}
+
 pub async fn delete_server_password(
     db: &DynamoClient,
     server_id: &str,
@@ -682,6 +1096,15 @@ pub async fn join_by_name(
     if get_member_role(db, &server_id, user_id).await?.is_some() {
         return Err((409, "You are already a member of this server".to_string()));
     }
+    check_not_banned(db, &server_id, user_id).await?;
+
+    rate_limit::check(
+        db,
+        &format!("join-password:{}:{}", server_id, user_id),
+        JOIN_PASSWORD_ATTEMPTS,
+        JOIN_PASSWORD_WINDOW_SECS,
+    )
+    .await?;
 
     // Get all passwords for this server
     let result = db
@@ -694,19 +1117,15 @@ pub async fn join_by_name(
         .await
         .map_err(|e| (500, format!("Database error: {}", e)))?;
 
-    let now = chrono::Utc::now().timestamp();
+    let now = clock::now_secs();
     let mut password_matched = false;
 
-    for item in result.items() {
-        // Check if expired
-        if let Some(expires_at) = item.get("expires_at").and_then(|v| v.as_n().ok()) {
-            if let Ok(exp) = expires_at.parse::<i64>() {
-                if exp < now {
-                    continue; // Skip expired passwords
-                }
-            }
-        }
+    let unexpired = result.items().iter().filter(|item| {
+        let expires_at = item.get("expires_at").and_then(|v| v.as_n().ok()?.parse().ok());
+        !clock::is_expired(expires_at, now)
+    });
 
+    for item in unexpired.take(MAX_PASSWORDS_CHECKED) {
         if let Some(hash) = item.get("password_hash").and_then(|v| v.as_s().ok()) {
             if verify_password(&req.password, hash) {
                 password_matched = true;
@@ -723,5 +1142,98 @@ pub async fn join_by_name(
     add_member(db, &server_id, user_id, username, "member").await?;
 
     // Return server with channels
-    crate::servers::get_server(db, &server_id, user_id).await
+    crate::servers::get_server(db, &server_id, user_id, true).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn fills_server_and_user_placeholders() {
+        assert_eq!(
+            fill_welcome_template("Welcome to {server}, {user}! {user}, say hi.", "Rustaceans", "ada"),
+            "Welcome to Rustaceans, ada! ada, say hi."
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_and_unclosed_braces() {
+        assert_eq!(fill_welcome_template("{other} {server", "S", "u"), "{other} {server");
+        assert_eq!(fill_welcome_template("{{user}}", "S", "u"), "{u}");
+        assert_eq!(fill_welcome_template("no placeholders", "S", "u"), "no placeholders");
+    }
+
+    #[test]
+    fn does_not_expand_placeholders_inside_substituted_values() {
+        assert_eq!(fill_welcome_template("Hi {user}", "S", "{server}"), "Hi {server}");
+    }
+
+    #[test]
+    fn create_invite_body_may_be_empty() {
+        let req = parse_create_invite("").unwrap();
+        assert!(req.expires_in_hours.is_none() && req.max_uses.is_none());
+        let req = parse_create_invite(r#"{"max_uses":5}"#).unwrap();
+        assert_eq!(req.max_uses, Some(5));
+    }
+
+    #[test]
+    fn malformed_create_invite_body_is_a_400() {
+        for body in ["{", r#"{"max_uses":"five"}"#, r#"{"uses":5}"#] {
+            let (status, message) = parse_create_invite(body).unwrap_err();
+            assert_eq!(status, 400);
+            assert!(message.starts_with("Invalid request: "), "{}", message);
+        }
+    }
+
+    fn claim_db(old_item: Option<HashMap<String, AttributeValue>>) -> (aws_smithy_mocks::Rule, DynamoClient) {
+        use aws_sdk_dynamodb::types::error::ConditionalCheckFailedException;
+        use aws_smithy_mocks::{mock, mock_client, MockResponse, RuleMode};
+
+        let rule = mock!(aws_sdk_dynamodb::Client::update_item).then_compute_response(move |input| {
+            let condition = input.condition_expression().unwrap();
+            assert!(condition.contains("attribute_not_exists(max_uses) OR use_count < max_uses"));
+            assert!(condition.contains("attribute_not_exists(expires_at) OR expires_at >= :now"));
+            MockResponse::Error(UpdateItemError::ConditionalCheckFailedException(
+                ConditionalCheckFailedException::builder().set_item(old_item.clone()).build(),
+            ))
+        });
+        let db = mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&rule]);
+        (rule, db)
+    }
+
+    #[tokio::test]
+    async fn exhausted_invite_is_refused_by_the_increment() {
+        let invite = HashMap::from([
+            ("code".to_string(), AttributeValue::S("abc".to_string())),
+            ("use_count".to_string(), AttributeValue::N("5".to_string())),
+            ("max_uses".to_string(), AttributeValue::N("5".to_string())),
+        ]);
+        let (rule, db) = claim_db(Some(invite));
+        let err = claim_invite_use(&db, "abc").await.unwrap_err();
+        assert_eq!(err, (410, "This invite has reached its usage limit".to_string()));
+        assert_eq!(rule.num_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn invite_expiring_after_the_read_is_refused_by_the_increment() {
+        use crate::clock::{set_clock, FixedClock};
+
+        let _clock = set_clock(FixedClock(1_000_000));
+        let invite = HashMap::from([
+            ("code".to_string(), AttributeValue::S("abc".to_string())),
+            ("use_count".to_string(), AttributeValue::N("0".to_string())),
+            ("expires_at".to_string(), AttributeValue::N("999".to_string())),
+        ]);
+        let (_rule, db) = claim_db(Some(invite));
+        let err = claim_invite_use(&db, "abc").await.unwrap_err();
+        assert_eq!(err, (410, "This invite has expired".to_string()));
+    }
+
+    #[tokio::test]
+    async fn deleted_invite_is_not_recreated_by_the_increment() {
+        let (_rule, db) = claim_db(None);
+        assert_eq!(claim_invite_use(&db, "abc").await.unwrap_err().0, 404);
+    }
 }
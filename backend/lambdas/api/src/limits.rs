@@ -0,0 +1,31 @@
+// Caps on arrays in request bodies. Every list a client sends is checked
+// here before any DynamoDB work, so one oversized body can't fan out into
+// thousands of reads or writes.
+
+/// Reject `items` if it holds more than `max` entries, naming `field` in the
+/// 400 so the client knows which list to shorten
+pub fn bounded_vec<T>(field: &str, items: &[T], max: usize) -> Result<(), (u16, String)> {
+    if items.len() > max {
+        return Err((400, format!("{} cannot have more than {} items", field, max)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_lists_up_to_the_cap() {
+        assert!(bounded_vec("channel_ids", &[0; 100], 100).is_ok());
+        assert!(bounded_vec("channel_ids", &[] as &[u8], 100).is_ok());
+    }
+
+    #[test]
+    fn rejects_lists_over_the_cap_naming_the_field() {
+        assert_eq!(
+            bounded_vec("permissions", &[0; 33], 32),
+            Err((400, "permissions cannot have more than 32 items".to_string()))
+        );
+    }
+}
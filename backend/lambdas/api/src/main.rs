@@ -5,11 +5,36 @@ use std::env;
 use std::sync::Arc;
 use tracing_subscriber::EnvFilter;
 
+mod admin;
+mod audit;
 mod auth;
+mod bootstrap;
+mod broadcast;
+mod clock;
+mod cursor;
+mod db;
+mod deadline;
 mod dms;
+mod drafts;
+mod error_codes;
+mod feed;
+mod follows;
+mod forward;
+mod highlights;
 mod invites;
+mod limits;
+mod link_previews;
 mod messages;
+mod path_params;
+mod presence;
+mod rate_limit;
+mod reactions;
+mod roles;
+mod sanitize;
 mod servers;
+mod sessions;
+mod starred;
+mod typing;
 
 struct AppState {
     db: DynamoClient,
@@ -21,7 +46,7 @@ fn cors_response(status: u16, body: impl Into<Body>) -> Result<Response<Body>, E
         .status(status)
         .header("content-type", "application/json")
         .header("access-control-allow-origin", "*")
-        .header("access-control-allow-methods", "GET, POST, PUT, DELETE, OPTIONS")
+        .header("access-control-allow-methods", "GET, POST, PUT, PATCH, DELETE, OPTIONS")
         .header("access-control-allow-headers", "Content-Type, Authorization")
         .body(body.into())?)
 }
@@ -32,7 +57,272 @@ fn json_response<T: serde::Serialize>(status: u16, data: &T) -> Result<Response<
 }
 
 fn error_response(status: u16, message: &str) -> Result<Response<Body>, Error> {
-    cors_response(status, format!(r#"{{"error":"{}"}}"#, message))
+    let body = serde_json::json!({
+        "error": message,
+        "code": error_codes::error_code(status, message),
+    });
+    cors_response(status, body.to_string())
+}
+
+fn file_response(
+    content_type: &str,
+    filename: &str,
+    body: String,
+    truncated: bool,
+) -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", content_type)
+        .header("content-disposition", format!(r#"attachment; filename="{}""#, filename))
+        .header("x-export-truncated", truncated.to_string())
+        .header("access-control-allow-origin", "*")
+        .header("access-control-expose-headers", "Content-Disposition, X-Export-Truncated")
+        .body(Body::from(body))?)
+}
+
+/// Every (method, path pattern) the router handles, used to answer 405 with
+/// an `Allow` header when a known path is hit with the wrong method. Keep in
+/// sync with the match in `handler`.
+const ROUTES: &[(&str, &str)] = &[
+    ("GET", "/health"),
+    ("POST", "/auth/register"),
+    ("POST", "/auth/login"),
+    ("POST", "/auth/refresh"),
+    ("POST", "/auth/logout"),
+    ("POST", "/auth/forgot-password"),
+    ("POST", "/auth/reset-password"),
+    ("GET", "/auth/me"),
+    ("GET", "/auth/token-info"),
+    ("GET", "/auth/sessions"),
+    ("DELETE", "/auth/sessions"),
+    ("DELETE", "/auth/sessions/:session_id"),
+    ("GET", "/auth/me/settings"),
+    ("PUT", "/auth/me/settings"),
+    ("PUT", "/auth/me/public-key"),
+    ("GET", "/auth/me/dm-privacy"),
+    ("PUT", "/auth/me/dm-privacy"),
+    ("POST", "/ws/resume-token"),
+    ("GET", "/servers"),
+    ("GET", "/bootstrap"),
+    ("GET", "/feed"),
+    ("GET", "/servers/summary"),
+    ("POST", "/servers"),
+    ("GET", "/servers/:server_id"),
+    ("PATCH", "/servers/:server_id"),
+    ("GET", "/servers/:server_id/channels"),
+    ("POST", "/servers/:server_id/channels"),
+    ("PUT", "/servers/:server_id/channels/order"),
+    ("PATCH", "/servers/:server_id/channels/:channel_id"),
+    ("GET", "/servers/:server_id/audit-log"),
+    ("GET", "/servers/:server_id/highlights"),
+    ("POST", "/servers/:server_id/highlights"),
+    ("DELETE", "/servers/:server_id/highlights/:message_id"),
+    ("GET", "/servers/:server_id/integrity"),
+    ("POST", "/servers/:server_id/integrity/repair"),
+    ("POST", "/servers/:server_id/repair-channels"),
+    ("GET", "/servers/:server_id/members"),
+    ("GET", "/servers/:server_id/presence"),
+    ("GET", "/servers/:server_id/autocomplete"),
+    ("GET", "/servers/:server_id/roles"),
+    ("POST", "/servers/:server_id/roles"),
+    ("PATCH", "/servers/:server_id/roles/:role_id"),
+    ("DELETE", "/servers/:server_id/roles/:role_id"),
+    ("PUT", "/servers/:server_id/members/:member_id/roles/:role_id"),
+    ("DELETE", "/servers/:server_id/members/:member_id/roles/:role_id"),
+    ("POST", "/servers/:server_id/members/:member_id/purge-messages"),
+    ("POST", "/servers/:server_id/members/:member_id/timeout"),
+    ("DELETE", "/servers/:server_id/members/:member_id"),
+    ("POST", "/servers/:server_id/bans"),
+    ("DELETE", "/servers/:server_id/members/:member_id/timeout"),
+    ("GET", "/servers/:server_id/channels/:channel_id/search"),
+    ("GET", "/servers/:server_id/channels/:channel_id/messages"),
+    ("POST", "/servers/:server_id/channels/:channel_id/messages"),
+    ("GET", "/servers/:server_id/channels/:channel_id/messages/:message_id/context"),
+    ("GET", "/servers/:server_id/channels/:channel_id/export"),
+    ("POST", "/servers/:server_id/channels/:channel_id/messages/:message_id/reactions/:emoji"),
+    ("DELETE", "/servers/:server_id/channels/:channel_id/messages/:message_id/reactions/:emoji"),
+    ("GET", "/servers/:server_id/channels/:channel_id/messages/:message_id/reactions/:emoji"),
+    ("POST", "/servers/:server_id/channels/:channel_id/messages/:message_id/reactions/:emoji/toggle"),
+    ("GET", "/servers/:server_id/channels/:channel_id/top-messages"),
+    ("GET", "/servers/:server_id/channels/:channel_id/typing"),
+    ("POST", "/servers/:server_id/channels/:channel_id/typing"),
+    ("DELETE", "/servers/:server_id/channels/:channel_id/typing"),
+    ("POST", "/servers/:server_id/channels/:channel_id/follow"),
+    ("DELETE", "/servers/:server_id/channels/:channel_id/follow/:target_channel_id"),
+    ("GET", "/servers/:server_id/reactions/stats"),
+    ("POST", "/servers/:server_id/invites"),
+    ("GET", "/servers/:server_id/invites"),
+    ("DELETE", "/servers/:server_id/invites/:code"),
+    ("GET", "/invites/:code"),
+    ("POST", "/invites/:code/join"),
+    ("POST", "/servers/:server_id/passwords"),
+    ("GET", "/servers/:server_id/passwords"),
+    ("PATCH", "/servers/:server_id/passwords/:password_id"),
+    ("DELETE", "/servers/:server_id/passwords/:password_id"),
+    ("POST", "/servers/join"),
+    ("POST", "/servers/:server_id/join"),
+    ("POST", "/messages/forward"),
+    ("POST", "/messages/:message_id/star"),
+    ("DELETE", "/messages/:message_id/star"),
+    ("GET", "/starred"),
+    ("GET", "/drafts"),
+    ("PUT", "/drafts/:scope_id"),
+    ("DELETE", "/drafts/:scope_id"),
+    ("GET", "/users/search"),
+    ("GET", "/users/:user_id"),
+    ("GET", "/users/:user_id/public-key"),
+    ("GET", "/dms"),
+    ("POST", "/dms"),
+    ("GET", "/dms/:conversation_id"),
+    ("GET", "/dms/:conversation_id/messages"),
+    ("POST", "/dms/:conversation_id/messages"),
+    ("POST", "/dms/:conversation_id/messages/:message_id/reactions/:emoji"),
+    ("DELETE", "/dms/:conversation_id/messages/:message_id/reactions/:emoji"),
+    ("GET", "/admin/users/:user_id/servers"),
+    ("PUT", "/admin/users/:user_id/admin"),
+];
+
+/// Methods accepted for `segments`, if any route pattern matches the path
+fn allowed_methods(segments: &[&str]) -> Vec<&'static str> {
+    let mut methods: Vec<&'static str> = ROUTES
+        .iter()
+        .filter(|(_, pattern)| {
+            let parts: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+            parts.len() == segments.len()
+                && parts
+                    .iter()
+                    .zip(segments)
+                    .all(|(part, segment)| part.starts_with(':') || part == segment)
+        })
+        .map(|(method, _)| *method)
+        .collect();
+    methods.sort_unstable();
+    methods.dedup();
+    methods
+}
+
+/// Why no `method` route accepts the path params in `segments`, if routes
+/// of that shape exist but every one of them rejects a param
+fn invalid_path_param(method: &str, segments: &[&str]) -> Option<String> {
+    let mut invalid = None;
+    for (_, pattern) in ROUTES.iter().filter(|(m, _)| *m == method) {
+        let parts: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+        if parts.len() != segments.len()
+            || parts.iter().zip(segments).any(|(part, segment)| !part.starts_with(':') && part != segment)
+        {
+            continue;
+        }
+        let rejected = parts
+            .iter()
+            .zip(segments)
+            .filter_map(|(part, segment)| part.strip_prefix(':').map(|name| (name, segment)))
+            .find(|(name, segment)| !path_params::is_valid(name, segment));
+        match rejected {
+            // A literal route like /servers/join is tried alongside the
+            // param one, so one route accepting the path is enough
+            None => return None,
+            Some((name, _)) => {
+                invalid.get_or_insert(name);
+            }
+        }
+    }
+    invalid.map(|name| format!("Invalid path parameter: {}", name))
+}
+
+fn method_not_allowed(allowed: &[&str]) -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .status(405)
+        .header("content-type", "application/json")
+        .header("allow", format!("{}, OPTIONS", allowed.join(", ")))
+        .header("access-control-allow-origin", "*")
+        .header("access-control-expose-headers", "Allow")
+        .body(Body::from(r#"{"error":"method not allowed","code":"method_not_allowed"}"#))?)
+}
+
+/// Attach the bucket's quota to a response from a rate-limited route
+fn with_rate_limit_headers(
+    response: Result<Response<Body>, Error>,
+    limit: &rate_limit::RateLimitState,
+) -> Result<Response<Body>, Error> {
+    let mut response = response?;
+    let headers = response.headers_mut();
+    headers.insert("x-ratelimit-limit", limit.limit.into());
+    headers.insert("x-ratelimit-remaining", limit.remaining.into());
+    headers.insert("x-ratelimit-reset", limit.reset_at.into());
+    if limit.exceeded {
+        headers.insert("retry-after", limit.retry_after().into());
+    }
+    headers.insert(
+        "access-control-expose-headers",
+        lambda_http::http::HeaderValue::from_static(
+            "X-RateLimit-Limit, X-RateLimit-Remaining, X-RateLimit-Reset, Retry-After",
+        ),
+    );
+    Ok(response)
+}
+
+/// Percent-decode a dynamic path segment (e.g. an emoji in a reactions route)
+fn decode_segment(segment: &str) -> String {
+    percent_encoding::percent_decode_str(segment)
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+/// API Gateway stage the request came through, from the request context
+fn request_stage(event: &Request) -> Option<String> {
+    use lambda_http::request::RequestContext;
+
+    match event.request_context_ref() {
+        Some(RequestContext::ApiGatewayV2(ctx)) => ctx.stage.clone(),
+        Some(RequestContext::ApiGatewayV1(ctx)) => ctx.stage.clone(),
+        _ => None,
+    }
+}
+
+/// Remove the stage prefix from the request path. With a stage from the
+/// request context only that exact segment is stripped (the `$default`
+/// stage has none); otherwise fall back to the known `/dev` and `/prod`
+/// prefixes.
+fn strip_stage<'a>(raw_path: &'a str, stage: Option<&str>) -> &'a str {
+    match stage {
+        Some("$default") => raw_path,
+        Some(stage) => raw_path
+            .strip_prefix('/')
+            .and_then(|rest| rest.strip_prefix(stage))
+            .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+            .unwrap_or(raw_path),
+        None => raw_path
+            .strip_prefix("/dev")
+            .or_else(|| raw_path.strip_prefix("/prod"))
+            .unwrap_or(raw_path),
+    }
+}
+
+/// Caller's IP as seen by API Gateway, for logging and rate limiting
+fn source_ip(event: &Request) -> Option<String> {
+    use lambda_http::request::RequestContext;
+
+    let from_context = match event.request_context_ref() {
+        Some(RequestContext::ApiGatewayV2(ctx)) => ctx.http.source_ip.clone(),
+        Some(RequestContext::ApiGatewayV1(ctx)) => ctx.identity.source_ip.clone(),
+        _ => None,
+    };
+
+    from_context.or_else(|| {
+        event
+            .headers()
+            .get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(',').next())
+            .map(|ip| ip.trim().to_string())
+    })
+}
+
+fn user_agent(event: &Request) -> Option<&str> {
+    event
+        .headers()
+        .get("user-agent")
+        .and_then(|v| v.to_str().ok())
 }
 
 fn get_auth(event: &Request) -> Option<auth::Claims> {
@@ -45,13 +335,14 @@ fn get_auth(event: &Request) -> Option<auth::Claims> {
     auth::validate_token(token).ok()
 }
 
+#[allow(clippy::result_large_err)]
 fn require_auth(event: &Request) -> Result<auth::Claims, Response<Body>> {
     get_auth(event).ok_or_else(|| {
         Response::builder()
             .status(401)
             .header("content-type", "application/json")
             .header("access-control-allow-origin", "*")
-            .body(Body::from(r#"{"error":"unauthorized"}"#))
+            .body(Body::from(r#"{"error":"unauthorized","code":"unauthorized"}"#))
             .unwrap()
     })
 }
@@ -60,11 +351,9 @@ async fn handler(event: Request, state: Arc<AppState>) -> Result<Response<Body>,
     let raw_path = event.uri().path();
     let method = event.method().as_str();
 
-    // Strip stage prefix (e.g., /dev, /prod) from path
-    let path = raw_path
-        .strip_prefix("/dev")
-        .or_else(|| raw_path.strip_prefix("/prod"))
-        .unwrap_or(raw_path);
+    // Strip stage prefix (e.g., /dev, /prod, /staging) from path
+    let stage = request_stage(&event);
+    let path = strip_stage(raw_path, stage.as_deref());
     let path = if path.is_empty() { "/" } else { path };
 
     tracing::info!(path = %path, method = %method, "Handling request");
@@ -81,8 +370,22 @@ async fn handler(event: Request, state: Arc<AppState>) -> Result<Response<Body>,
         Body::Empty => String::new(),
     };
 
-    // Parse path segments for dynamic routes
+    // Parse path segments for dynamic routes. An empty one (`/invites/` or
+    // `/servers//channels`) would otherwise collapse into a different route.
+    if path != "/" && path.strip_prefix('/').unwrap_or(path).split('/').any(|s| s.is_empty()) {
+        return error_response(400, "Invalid path: empty segment");
+    }
     let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    if let Some(message) = invalid_path_param(method, &segments) {
+        return error_response(400, &message);
+    }
+
+    // A token from a revoked session is refused on every route
+    if let Some(claims) = get_auth(&event) {
+        if let Err((status, message)) = sessions::check_session(&state.db, &claims).await {
+            return error_response(status, &message);
+        }
+    }
 
     match (method, segments.as_slice()) {
         // Health check
@@ -92,121 +395,925 @@ async fn handler(event: Request, state: Arc<AppState>) -> Result<Response<Body>,
 
         // ============ Auth routes ============
         ("POST", ["auth", "register"]) => {
-            match auth::register(&state.db, &body).await {
+            match auth::register(&state.db, &body, source_ip(&event).as_deref(), user_agent(&event)).await {
                 Ok(response) => json_response(201, &response),
                 Err((status, message)) => error_response(status, &message),
             }
         }
         ("POST", ["auth", "login"]) => {
-            match auth::login(&state.db, &body).await {
+            let ip = source_ip(&event);
+            let limit = rate_limit::hit(
+                &state.db,
+                &format!("login-ip:{}", ip.as_deref().unwrap_or("unknown")),
+                auth::get_login_rate_limit(),
+                auth::LOGIN_ATTEMPT_WINDOW_SECS,
+            )
+            .await;
+
+            let response = if limit.exceeded {
+                auth::log_auth_failure("login", None, ip.as_deref(), "rate_limited");
+                let (status, message) = limit.error();
+                error_response(status, &message)
+            } else {
+                match auth::login(&state.db, &body, ip.as_deref(), user_agent(&event)).await {
+                    Ok(response) => json_response(200, &response),
+                    Err((status, message)) => error_response(status, &message),
+                }
+            };
+            with_rate_limit_headers(response, &limit)
+        }
+        ("POST", ["auth", "refresh"]) => {
+            match auth::refresh(&state.db, &body, source_ip(&event).as_deref()).await {
+                Ok(response) => json_response(200, &response),
+                Err((status, message)) => error_response(status, &message),
+            }
+        }
+        ("POST", ["auth", "logout"]) => {
+            match auth::logout(&state.db, &body).await {
+                Ok(()) => cors_response(204, ""),
+                Err((status, message)) => error_response(status, &message),
+            }
+        }
+        ("POST", ["auth", "forgot-password"]) => {
+            match auth::request_password_reset(&state.db, &body, source_ip(&event).as_deref()).await {
                 Ok(response) => json_response(200, &response),
                 Err((status, message)) => error_response(status, &message),
             }
         }
-        ("GET", ["auth", "me"]) => {
+        ("POST", ["auth", "reset-password"]) => {
+            match auth::reset_password(&state.db, &body, source_ip(&event).as_deref()).await {
+                Ok(()) => cors_response(204, ""),
+                Err((status, message)) => error_response(status, &message),
+            }
+        }
+        ("GET", ["auth", "me"]) => {
+            match require_auth(&event) {
+                Ok(claims) => json_response(200, &serde_json::json!({
+                    "id": claims.sub,
+                    "email": claims.email
+                })),
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("GET", ["auth", "token-info"]) => {
+            match require_auth(&event) {
+                Ok(claims) => json_response(200, &auth::token_info(&claims)),
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("GET", ["auth", "sessions"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match sessions::list_sessions(&state.db, &claims).await {
+                        Ok(sessions) => json_response(200, &sessions),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("DELETE", ["auth", "sessions"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match sessions::revoke_other_sessions(&state.db, &claims).await {
+                        Ok(response) => json_response(200, &response),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("DELETE", ["auth", "sessions", session_id]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match sessions::revoke_session(&state.db, &claims.sub, session_id).await {
+                        Ok(()) => cors_response(204, ""),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("GET", ["auth", "me", "settings"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match auth::get_settings(&state.db, &claims.sub).await {
+                        Ok(settings) => json_response(200, &settings),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("PUT", ["auth", "me", "settings"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match auth::put_settings(&state.db, &claims.sub, &body).await {
+                        Ok(settings) => json_response(200, &settings),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("PUT", ["auth", "me", "public-key"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match auth::put_public_key(&state.db, &claims.sub, &body).await {
+                        Ok(key) => json_response(200, &key),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+
+        ("GET", ["auth", "me", "dm-privacy"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match dms::get_dm_privacy(&state.db, &claims.sub).await {
+                        Ok(privacy) => json_response(200, &privacy),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("PUT", ["auth", "me", "dm-privacy"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match dms::put_dm_privacy(&state.db, &claims.sub, &body).await {
+                        Ok(privacy) => json_response(200, &privacy),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+
+        // ============ WebSocket routes ============
+        ("POST", ["ws", "resume-token"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match auth::create_resume_token(&claims.sub, &body) {
+                        Ok(response) => json_response(200, &response),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+
+        // ============ Server routes ============
+        ("GET", ["servers"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match servers::list_user_servers(&state.db, &claims.sub).await {
+                        Ok(servers) => json_response(200, &servers),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("GET", ["bootstrap"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match deadline::with_db_budget(bootstrap::get_bootstrap(&state.db, &claims)).await {
+                        Ok(payload) => json_response(200, &payload),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("GET", ["feed"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    let query_params = event.query_string_parameters();
+                    let limit: usize = query_params
+                        .first("limit")
+                        .and_then(|v: &str| v.parse().ok())
+                        .unwrap_or(50);
+                    let cursor = query_params.first("cursor");
+
+                    match deadline::with_db_budget(feed::get_feed(&state.db, &claims.sub, limit, cursor)).await {
+                        Ok(page) => json_response(200, &page),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("GET", ["servers", "summary"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match servers::list_server_summaries(&state.db, &claims.sub).await {
+                        Ok(summaries) => json_response(200, &summaries),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("POST", ["servers"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match servers::create_server(&state.db, &claims.sub, &claims.username, &body).await {
+                        Ok(server) => json_response(201, &server),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("GET", ["servers", server_id]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    let sort = event.query_string_parameters().first("sort").map(str::to_string);
+                    let consistent = event.query_string_parameters().first("consistent") == Some("true");
+                    match deadline::with_db_budget(servers::get_server(&state.db, server_id, &claims.sub, consistent)).await {
+                        Ok(mut server) => match servers::sort_channels(&mut server.channels, sort.as_deref()) {
+                            Ok(()) => json_response(200, &server),
+                            Err((status, message)) => error_response(status, &message),
+                        },
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+
+        ("PATCH", ["servers", server_id]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match servers::update_server(&state.db, server_id, &claims.sub, &body).await {
+                        Ok(server) => json_response(200, &server),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+
+        // ============ Channel routes ============
+        ("GET", ["servers", server_id, "channels"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    let sort = event.query_string_parameters().first("sort").map(str::to_string);
+                    let consistent = event.query_string_parameters().first("consistent") == Some("true");
+                    // First check membership
+                    match deadline::with_db_budget(servers::get_server(&state.db, server_id, &claims.sub, consistent)).await {
+                        Ok(mut server) => match servers::sort_channels(&mut server.channels, sort.as_deref()) {
+                            Ok(()) => json_response(200, &server.channels),
+                            Err((status, message)) => error_response(status, &message),
+                        },
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("PUT", ["servers", server_id, "channels", "order"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match servers::reorder_channels(&state.db, server_id, &claims.sub, &body).await {
+                        Ok(channels) => json_response(200, &channels),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("POST", ["servers", server_id, "channels"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match servers::create_channel(&state.db, server_id, &claims.sub, &body).await {
+                        Ok(channel) => json_response(201, &channel),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+
+        // ============ Integrity routes ============
+        ("GET", ["servers", server_id, "audit-log"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    let query_params = event.query_string_parameters();
+                    let filter = audit::AuditFilter {
+                        actor_id: query_params.first("actor"),
+                        action: query_params.first("action"),
+                    };
+                    let limit: usize = query_params
+                        .first("limit")
+                        .and_then(|v: &str| v.parse().ok())
+                        .unwrap_or(50);
+                    let cursor = query_params.first("cursor");
+
+                    match deadline::with_db_budget(audit::list_entries(
+                        &state.db,
+                        server_id,
+                        &claims.sub,
+                        &filter,
+                        limit,
+                        cursor,
+                    ))
+                    .await
+                    {
+                        Ok(page) => json_response(200, &page),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("GET", ["servers", server_id, "highlights"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match deadline::with_db_budget(highlights::list_server_pins(&state.db, server_id, &claims.sub)).await {
+                        Ok(highlights) => json_response(200, &highlights),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("POST", ["servers", server_id, "highlights"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match highlights::pin_to_server(&state.db, server_id, &claims.sub, &body).await {
+                        Ok(highlight) => json_response(201, &highlight),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("DELETE", ["servers", server_id, "highlights", message_id]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match highlights::unpin_from_server(&state.db, server_id, message_id, &claims.sub).await {
+                        Ok(()) => cors_response(204, ""),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("GET", ["servers", server_id, "integrity"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match servers::get_integrity(&state.db, server_id, &claims.sub).await {
+                        Ok(report) => json_response(200, &report),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("POST", ["servers", server_id, "integrity", "repair"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match servers::repair_owner(&state.db, server_id, &claims.sub).await {
+                        Ok(report) => json_response(200, &report),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("POST", ["servers", server_id, "repair-channels"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match servers::repair_channels(&state.db, server_id, &claims.sub).await {
+                        Ok(channels) => json_response(200, &channels),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+
+        // ============ Member routes ============
+        ("GET", ["servers", server_id, "members"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    let consistent = event.query_string_parameters().first("consistent") == Some("true");
+                    match servers::list_members(&state.db, server_id, &claims.sub, consistent).await {
+                        Ok(members) => json_response(200, &members),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("GET", ["servers", server_id, "presence"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match deadline::with_db_budget(presence::list_server_presence(&state.db, server_id, &claims.sub)).await {
+                        Ok(presence) => json_response(200, &presence),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+
+        ("GET", ["servers", server_id, "autocomplete"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    let query_params = event.query_string_parameters();
+                    let query = query_params.first("q").unwrap_or("");
+                    match query_params.first("type") {
+                        Some("mention") => {
+                            match servers::autocomplete_members(&state.db, server_id, &claims.sub, query).await {
+                                Ok(members) => json_response(200, &members),
+                                Err((status, message)) => error_response(status, &message),
+                            }
+                        }
+                        // No custom emoji yet, so there is nothing server-specific to suggest
+                        Some("emoji") => json_response(200, &Vec::<serde_json::Value>::new()),
+                        _ => error_response(400, "type must be mention or emoji"),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+
+        // ============ Role routes ============
+        ("GET", ["servers", server_id, "roles"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match roles::list_roles(&state.db, server_id, &claims.sub).await {
+                        Ok(roles_list) => json_response(200, &roles_list),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("POST", ["servers", server_id, "roles"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match roles::create_role(&state.db, server_id, &claims.sub, &body).await {
+                        Ok(role) => json_response(201, &role),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("PATCH", ["servers", server_id, "roles", role_id]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match roles::update_role(&state.db, server_id, role_id, &claims.sub, &body).await {
+                        Ok(role) => json_response(200, &role),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("DELETE", ["servers", server_id, "roles", role_id]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match roles::delete_role(&state.db, server_id, role_id, &claims.sub).await {
+                        Ok(()) => cors_response(204, ""),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("PUT", ["servers", server_id, "members", member_id, "roles", role_id]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match roles::assign_role(&state.db, server_id, member_id, role_id, &claims.sub).await {
+                        Ok(role_ids) => json_response(200, &serde_json::json!({ "role_ids": role_ids })),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("DELETE", ["servers", server_id, "members", member_id, "roles", role_id]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match roles::unassign_role(&state.db, server_id, member_id, role_id, &claims.sub).await {
+                        Ok(role_ids) => json_response(200, &serde_json::json!({ "role_ids": role_ids })),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("POST", ["servers", server_id, "members", member_id, "purge-messages"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match messages::delete_user_messages(&state.db, server_id, member_id, &claims.sub).await {
+                        Ok(result) => json_response(200, &result),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+
+        ("POST", ["servers", server_id, "members", member_id, "timeout"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match servers::set_member_timeout(&state.db, server_id, member_id, &claims.sub, &body).await {
+                        Ok(timeout) => json_response(200, &timeout),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("DELETE", ["servers", server_id, "members", member_id, "timeout"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match servers::clear_member_timeout(&state.db, server_id, member_id, &claims.sub).await {
+                        Ok(timeout) => json_response(200, &timeout),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("DELETE", ["servers", server_id, "members", member_id]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match servers::kick_member(&state.db, server_id, member_id, &claims.sub).await {
+                        Ok(()) => cors_response(204, ""),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("POST", ["servers", server_id, "bans"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match servers::ban_member(&state.db, server_id, &claims.sub, &body).await {
+                        Ok(ban) => json_response(201, &ban),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+
+        // ============ Message routes ============
+        ("PATCH", ["servers", server_id, "channels", channel_id]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match servers::update_channel(&state.db, server_id, channel_id, &claims.sub, &body).await {
+                        Ok(channel) => json_response(200, &channel),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("GET", ["servers", server_id, "channels", channel_id, "search"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    let query_params = event.query_string_parameters();
+                    let query = query_params.first("q").unwrap_or("");
+                    let limit = rate_limit::hit(
+                        &state.db,
+                        &format!("message-search:{}", claims.sub),
+                        dms::get_search_rate_limit(),
+                        60,
+                    )
+                    .await;
+
+                    let response = if limit.exceeded {
+                        let (status, message) = limit.error();
+                        error_response(status, &message)
+                    } else {
+                        match deadline::with_db_budget(messages::search_messages(
+                            &state.db,
+                            server_id,
+                            channel_id,
+                            &claims.sub,
+                            query,
+                        ))
+                        .await
+                        {
+                            Ok(results) => json_response(200, &results),
+                            Err((status, message)) => error_response(status, &message),
+                        }
+                    };
+                    with_rate_limit_headers(response, &limit)
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("GET", ["servers", server_id, "channels", channel_id, "messages"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    // Parse query params for pagination
+                    let query_params = event.query_string_parameters();
+                    let limit: usize = query_params
+                        .first("limit")
+                        .and_then(|v: &str| v.parse().ok())
+                        .unwrap_or(50);
+                    let before: Option<i64> = query_params
+                        .first("before")
+                        .and_then(|v: &str| v.parse().ok());
+
+                    let result = match query_params.first("author") {
+                        Some(author_id) => {
+                            deadline::with_db_budget(messages::list_messages_by_author(
+                                &state.db,
+                                server_id,
+                                channel_id,
+                                author_id,
+                                &claims.sub,
+                                limit,
+                                before,
+                            ))
+                            .await
+                        }
+                        None => {
+                            deadline::with_db_budget(messages::list_messages(
+                                &state.db,
+                                server_id,
+                                channel_id,
+                                &claims.sub,
+                                limit,
+                                before,
+                            ))
+                            .await
+                        }
+                    };
+
+                    // Opt-in, since it costs an extra batch read per page
+                    let with_previews = query_params.first("previews") == Some("true");
+                    let result = match result {
+                        Ok(mut response) if with_previews => {
+                            let ids: Vec<String> =
+                                response.messages.iter().map(|m| m.id.clone()).collect();
+                            link_previews::load_previews(&state.db, &ids).await.map(|previews| {
+                                response.link_previews = previews;
+                                response
+                            })
+                        }
+                        other => other,
+                    };
+
+                    // Also opt-in: two batch reads over the page's distinct authors
+                    let with_author_status = query_params.first("author_status") == Some("true");
+                    let result = match result {
+                        Ok(mut response) if with_author_status => {
+                            messages::flag_ex_members(&state.db, server_id, &mut response.messages)
+                                .await
+                                .map(|()| response)
+                        }
+                        other => other,
+                    };
+
+                    match result {
+                        Ok(response) => json_response(200, &response),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("POST", ["servers", server_id, "channels", channel_id, "messages"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match messages::create_message(
+                        &state.db,
+                        server_id,
+                        channel_id,
+                        &claims.sub,
+                        &claims.username,
+                        &body,
+                    )
+                    .await
+                    {
+                        Ok(message) => {
+                            // Broadcast to WebSocket subscribers (fire and forget)
+                            if let Some(apigw) = &state.apigw {
+                                messages::broadcast_message(&state.db, apigw, server_id, &message).await;
+                            }
+                            // Best effort and each time-boxed, so they run side by
+                            // side; the message itself has already been broadcast
+                            tokio::join!(
+                                link_previews::generate_for_message(
+                                    &state.db,
+                                    state.apigw.as_ref(),
+                                    server_id,
+                                    &message,
+                                ),
+                                follows::mirror_announcement(
+                                    &state.db,
+                                    state.apigw.as_ref(),
+                                    server_id,
+                                    &message,
+                                ),
+                            );
+                            json_response(201, &message)
+                        }
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+
+        ("GET", ["servers", server_id, "channels", channel_id, "messages", message_id, "context"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    let radius: usize = event
+                        .query_string_parameters()
+                        .first("radius")
+                        .and_then(|v: &str| v.parse().ok())
+                        .unwrap_or(10);
+
+                    match messages::get_message_context(
+                        &state.db,
+                        server_id,
+                        channel_id,
+                        message_id,
+                        &claims.sub,
+                        radius,
+                    )
+                    .await
+                    {
+                        Ok(context) => json_response(200, &context),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+
+        ("GET", ["servers", server_id, "channels", channel_id, "export"]) => {
             match require_auth(&event) {
-                Ok(claims) => json_response(200, &serde_json::json!({
-                    "id": claims.sub,
-                    "email": claims.email
-                })),
+                Ok(claims) => {
+                    // Explicit ?format= wins over the Accept header
+                    let query_params = event.query_string_parameters();
+                    let accept = event
+                        .headers()
+                        .get("accept")
+                        .and_then(|v| v.to_str().ok())
+                        .unwrap_or("");
+                    let format = match query_params.first("format") {
+                        Some("csv") => messages::ExportFormat::Csv,
+                        Some("ndjson") => messages::ExportFormat::Ndjson,
+                        Some(_) => messages::ExportFormat::Json,
+                        None if accept.contains("text/csv") => messages::ExportFormat::Csv,
+                        None if accept.contains("application/x-ndjson") => messages::ExportFormat::Ndjson,
+                        None => messages::ExportFormat::Json,
+                    };
+
+                    match messages::export_messages(&state.db, server_id, channel_id, &claims.sub, format).await {
+                        Ok(export) => file_response(
+                            export.content_type,
+                            &export.filename,
+                            export.body,
+                            export.truncated,
+                        ),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
                 Err(resp) => Ok(resp),
             }
         }
 
-        // ============ Server routes ============
-        ("GET", ["servers"]) => {
+        // ============ Reaction routes ============
+        ("GET", ["servers", server_id, "channels", channel_id, "top-messages"]) => {
             match require_auth(&event) {
                 Ok(claims) => {
-                    match servers::list_user_servers(&state.db, &claims.sub).await {
-                        Ok(servers) => json_response(200, &servers),
+                    match reactions::top_messages(&state.db, server_id, channel_id, &claims.sub).await {
+                        Ok(response) => json_response(200, &response),
                         Err((status, message)) => error_response(status, &message),
                     }
                 }
                 Err(resp) => Ok(resp),
             }
         }
-        ("POST", ["servers"]) => {
+        ("GET", ["servers", server_id, "channels", channel_id, "typing"]) => {
             match require_auth(&event) {
                 Ok(claims) => {
-                    match servers::create_server(&state.db, &claims.sub, &claims.username, &body).await {
-                        Ok(server) => json_response(201, &server),
+                    match typing::list_typing(&state.db, server_id, channel_id, &claims.sub).await {
+                        Ok(users) => json_response(200, &users),
                         Err((status, message)) => error_response(status, &message),
                     }
                 }
                 Err(resp) => Ok(resp),
             }
         }
-        ("GET", ["servers", server_id]) => {
+        ("POST", ["servers", server_id, "channels", channel_id, "typing"]) => {
             match require_auth(&event) {
                 Ok(claims) => {
-                    match servers::get_server(&state.db, server_id, &claims.sub).await {
-                        Ok(server) => json_response(200, &server),
+                    match typing::start_typing(&state.db, server_id, channel_id, &claims.sub, &claims.username).await {
+                        Ok(typing_user) => {
+                            if let Some(apigw) = &state.apigw {
+                                typing::broadcast_typing(
+                                    &state.db,
+                                    apigw,
+                                    server_id,
+                                    channel_id,
+                                    &claims.sub,
+                                    &claims.username,
+                                    Some(typing_user.expires_at),
+                                )
+                                .await;
+                            }
+                            json_response(200, &typing_user)
+                        }
                         Err((status, message)) => error_response(status, &message),
                     }
                 }
                 Err(resp) => Ok(resp),
             }
         }
-
-        // ============ Channel routes ============
-        ("GET", ["servers", server_id, "channels"]) => {
+        ("DELETE", ["servers", server_id, "channels", channel_id, "typing"]) => {
             match require_auth(&event) {
                 Ok(claims) => {
-                    // First check membership
-                    match servers::get_server(&state.db, server_id, &claims.sub).await {
-                        Ok(server) => json_response(200, &server.channels),
+                    match typing::stop_typing(&state.db, server_id, channel_id, &claims.sub).await {
+                        Ok(()) => {
+                            if let Some(apigw) = &state.apigw {
+                                typing::broadcast_typing(
+                                    &state.db,
+                                    apigw,
+                                    server_id,
+                                    channel_id,
+                                    &claims.sub,
+                                    &claims.username,
+                                    None,
+                                )
+                                .await;
+                            }
+                            cors_response(204, "")
+                        }
                         Err((status, message)) => error_response(status, &message),
                     }
                 }
                 Err(resp) => Ok(resp),
             }
         }
-        ("POST", ["servers", server_id, "channels"]) => {
+        ("POST", ["servers", server_id, "channels", channel_id, "follow"]) => {
             match require_auth(&event) {
                 Ok(claims) => {
-                    match servers::create_channel(&state.db, server_id, &claims.sub, &body).await {
-                        Ok(channel) => json_response(201, &channel),
+                    match follows::follow_channel(&state.db, server_id, channel_id, &claims.sub, &body).await {
+                        Ok(follow) => json_response(201, &follow),
                         Err((status, message)) => error_response(status, &message),
                     }
                 }
                 Err(resp) => Ok(resp),
             }
         }
-
-        // ============ Member routes ============
-        ("GET", ["servers", server_id, "members"]) => {
+        ("DELETE", ["servers", server_id, "channels", channel_id, "follow", target_channel_id]) => {
             match require_auth(&event) {
                 Ok(claims) => {
-                    match servers::list_members(&state.db, server_id, &claims.sub).await {
-                        Ok(members) => json_response(200, &members),
+                    match follows::unfollow_channel(&state.db, server_id, channel_id, target_channel_id, &claims.sub).await {
+                        Ok(()) => cors_response(204, ""),
                         Err((status, message)) => error_response(status, &message),
                     }
                 }
                 Err(resp) => Ok(resp),
             }
         }
-
-        // ============ Message routes ============
-        ("GET", ["servers", server_id, "channels", channel_id, "messages"]) => {
+        ("GET", ["servers", server_id, "reactions", "stats"]) => {
             match require_auth(&event) {
                 Ok(claims) => {
-                    // Parse query params for pagination
+                    match reactions::server_stats(&state.db, server_id, &claims.sub).await {
+                        Ok(stats) => json_response(200, &stats),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("GET", ["servers", server_id, "channels", channel_id, "messages", message_id, "reactions", emoji]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    let scope = reactions::ReactionScope::Channel { server_id, channel_id };
+                    let emoji = decode_segment(emoji);
                     let query_params = event.query_string_parameters();
                     let limit: usize = query_params
                         .first("limit")
                         .and_then(|v: &str| v.parse().ok())
                         .unwrap_or(50);
-                    let before: Option<i64> = query_params
-                        .first("before")
-                        .and_then(|v: &str| v.parse().ok());
+                    let cursor = query_params.first("cursor");
 
-                    match messages::list_messages(
+                    match reactions::list_message_reactions(
                         &state.db,
-                        server_id,
-                        channel_id,
+                        &scope,
+                        message_id,
+                        &emoji,
                         &claims.sub,
                         limit,
-                        before,
+                        cursor,
                     )
                     .await
                     {
@@ -217,25 +1324,53 @@ async fn handler(event: Request, state: Arc<AppState>) -> Result<Response<Body>,
                 Err(resp) => Ok(resp),
             }
         }
-        ("POST", ["servers", server_id, "channels", channel_id, "messages"]) => {
+        ("POST", ["servers", server_id, "channels", channel_id, "messages", message_id, "reactions", emoji, "toggle"]) => {
             match require_auth(&event) {
                 Ok(claims) => {
-                    match messages::create_message(
-                        &state.db,
-                        server_id,
-                        channel_id,
-                        &claims.sub,
-                        &claims.username,
-                        &body,
-                    )
-                    .await
-                    {
-                        Ok(message) => {
-                            // Broadcast to WebSocket subscribers (fire and forget)
+                    let scope = reactions::ReactionScope::Channel { server_id, channel_id };
+                    let emoji = decode_segment(emoji);
+                    match reactions::toggle_reaction(&state.db, &scope, message_id, &claims.sub, &emoji).await {
+                        Ok(response) => {
                             if let Some(apigw) = &state.apigw {
-                                messages::broadcast_message(&state.db, apigw, &message).await;
+                                reactions::broadcast_reaction_update(&state.db, apigw, &scope, &response.summary).await;
                             }
-                            json_response(201, &message)
+                            json_response(200, &response)
+                        }
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("POST", ["servers", server_id, "channels", channel_id, "messages", message_id, "reactions", emoji]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    let scope = reactions::ReactionScope::Channel { server_id, channel_id };
+                    let emoji = decode_segment(emoji);
+                    match reactions::add_reaction(&state.db, &scope, message_id, &claims.sub, &emoji).await {
+                        Ok(summary) => {
+                            if let Some(apigw) = &state.apigw {
+                                reactions::broadcast_reaction_update(&state.db, apigw, &scope, &summary).await;
+                            }
+                            json_response(200, &summary)
+                        }
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("DELETE", ["servers", server_id, "channels", channel_id, "messages", message_id, "reactions", emoji]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    let scope = reactions::ReactionScope::Channel { server_id, channel_id };
+                    let emoji = decode_segment(emoji);
+                    match reactions::remove_reaction(&state.db, &scope, message_id, &claims.sub, &emoji).await {
+                        Ok(summary) => {
+                            if let Some(apigw) = &state.apigw {
+                                reactions::broadcast_reaction_update(&state.db, apigw, &scope, &summary).await;
+                            }
+                            json_response(200, &summary)
                         }
                         Err((status, message)) => error_response(status, &message),
                     }
@@ -281,7 +1416,8 @@ async fn handler(event: Request, state: Arc<AppState>) -> Result<Response<Body>,
         ("GET", ["invites", code]) => {
             match require_auth(&event) {
                 Ok(_) => {
-                    match invites::get_invite_info(&state.db, code).await {
+                    let include_channels = event.query_string_parameters().first("include_channels") == Some("true");
+                    match invites::get_invite_preview(&state.db, code, include_channels).await {
                         Ok(info) => json_response(200, &info),
                         Err((status, message)) => error_response(status, &message),
                     }
@@ -343,6 +1479,25 @@ async fn handler(event: Request, state: Arc<AppState>) -> Result<Response<Body>,
                 Err(resp) => Ok(resp),
             }
         }
+        ("PATCH", ["servers", server_id, "passwords", password_id]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match invites::update_server_password(&state.db, server_id, password_id, &claims.sub, &body).await {
+                        Ok(password) => {
+                            // Don't return the hash to the client
+                            json_response(200, &serde_json::json!({
+                                "id": password.id,
+                                "server_id": password.server_id,
+                                "created_at": password.created_at,
+                                "expires_at": password.expires_at
+                            }))
+                        }
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
         ("DELETE", ["servers", server_id, "passwords", password_id]) => {
             match require_auth(&event) {
                 Ok(claims) => {
@@ -367,6 +1522,112 @@ async fn handler(event: Request, state: Arc<AppState>) -> Result<Response<Body>,
                 Err(resp) => Ok(resp),
             }
         }
+        ("POST", ["servers", server_id, "join"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match invites::join_open_server(&state.db, server_id, &claims.sub, &claims.username).await {
+                        Ok(server) => json_response(200, &server),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+
+        // ============ Forwarding route ============
+        ("POST", ["messages", "forward"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match forward::forward_message(&state.db, &claims.sub, &claims.username, &body).await {
+                        Ok(forwarded) => {
+                            if let Some(apigw) = &state.apigw {
+                                match &forwarded {
+                                    forward::ForwardedMessage::Channel { server_id, message } => {
+                                        messages::broadcast_message(&state.db, apigw, server_id, message).await;
+                                    }
+                                    forward::ForwardedMessage::Dm { message } => {
+                                        dms::broadcast_dm(&state.db, apigw, message).await;
+                                    }
+                                }
+                            }
+                            json_response(201, &forwarded)
+                        }
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+
+        // ============ Starred message routes ============
+        ("POST", ["messages", message_id, "star"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match starred::star_message(&state.db, message_id, &claims.sub, &body).await {
+                        Ok(starred) => json_response(201, &starred),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("DELETE", ["messages", message_id, "star"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match starred::unstar_message(&state.db, message_id, &claims.sub).await {
+                        Ok(()) => cors_response(204, ""),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("GET", ["starred"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match deadline::with_db_budget(starred::list_starred(&state.db, &claims.sub)).await {
+                        Ok(starred) => json_response(200, &starred),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+
+        // ============ Draft routes ============
+        ("GET", ["drafts"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match drafts::list_drafts(&state.db, &claims.sub).await {
+                        Ok(drafts) => json_response(200, &drafts),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("PUT", ["drafts", scope_id]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match drafts::put_draft(&state.db, &claims.sub, scope_id, &body).await {
+                        Ok(draft) => json_response(200, &draft),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("DELETE", ["drafts", scope_id]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match drafts::delete_draft(&state.db, &claims.sub, scope_id).await {
+                        Ok(()) => cors_response(204, ""),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
 
         // ============ User search route ============
         ("GET", ["users", "search"]) => {
@@ -374,8 +1635,45 @@ async fn handler(event: Request, state: Arc<AppState>) -> Result<Response<Body>,
                 Ok(claims) => {
                     let query_params = event.query_string_parameters();
                     let query = query_params.first("q").unwrap_or("");
-                    match dms::search_users(&state.db, query, &claims.sub).await {
-                        Ok(users) => json_response(200, &users),
+                    let limit = rate_limit::hit(
+                        &state.db,
+                        &format!("search:{}", claims.sub),
+                        dms::get_search_rate_limit(),
+                        60,
+                    )
+                    .await;
+
+                    let response = if limit.exceeded {
+                        let (status, message) = limit.error();
+                        error_response(status, &message)
+                    } else {
+                        match dms::search_users(&state.db, query, &claims.sub).await {
+                            Ok(users) => json_response(200, &users),
+                            Err((status, message)) => error_response(status, &message),
+                        }
+                    };
+                    with_rate_limit_headers(response, &limit)
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+
+        ("GET", ["users", user_id]) => {
+            match require_auth(&event) {
+                Ok(_) => {
+                    match auth::get_public_profile(&state.db, user_id).await {
+                        Ok(profile) => json_response(200, &profile),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("GET", ["users", user_id, "public-key"]) => {
+            match require_auth(&event) {
+                Ok(_) => {
+                    match auth::get_public_key(&state.db, user_id).await {
+                        Ok(key) => json_response(200, &key),
                         Err((status, message)) => error_response(status, &message),
                     }
                 }
@@ -455,9 +1753,75 @@ async fn handler(event: Request, state: Arc<AppState>) -> Result<Response<Body>,
             }
         }
 
-        // 404 for everything else
+        ("POST", ["dms", conversation_id, "messages", message_id, "reactions", emoji]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    let scope = reactions::ReactionScope::Dm { conversation_id };
+                    let emoji = decode_segment(emoji);
+                    match reactions::add_reaction(&state.db, &scope, message_id, &claims.sub, &emoji).await {
+                        Ok(summary) => {
+                            if let Some(apigw) = &state.apigw {
+                                reactions::broadcast_reaction_update(&state.db, apigw, &scope, &summary).await;
+                            }
+                            json_response(200, &summary)
+                        }
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("DELETE", ["dms", conversation_id, "messages", message_id, "reactions", emoji]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    let scope = reactions::ReactionScope::Dm { conversation_id };
+                    let emoji = decode_segment(emoji);
+                    match reactions::remove_reaction(&state.db, &scope, message_id, &claims.sub, &emoji).await {
+                        Ok(summary) => {
+                            if let Some(apigw) = &state.apigw {
+                                reactions::broadcast_reaction_update(&state.db, apigw, &scope, &summary).await;
+                            }
+                            json_response(200, &summary)
+                        }
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+
+        // ============ Instance admin routes ============
+        ("GET", ["admin", "users", user_id, "servers"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match admin::list_user_servers(&state.db, &claims.sub, user_id).await {
+                        Ok(memberships) => json_response(200, &memberships),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+        ("PUT", ["admin", "users", user_id, "admin"]) => {
+            match require_auth(&event) {
+                Ok(claims) => {
+                    match admin::set_instance_admin(&state.db, &claims.sub, user_id, &body).await {
+                        Ok(status) => json_response(200, &status),
+                        Err((status, message)) => error_response(status, &message),
+                    }
+                }
+                Err(resp) => Ok(resp),
+            }
+        }
+
+        // 405 for known paths with the wrong method, 404 for everything else
         _ => {
-            error_response(404, "not found")
+            let allowed = allowed_methods(&segments);
+            if allowed.is_empty() {
+                error_response(404, "not found")
+            } else {
+                method_not_allowed(&allowed)
+            }
         }
     }
 }
@@ -470,7 +1834,7 @@ async fn main() -> Result<(), Error> {
         .init();
 
     // Initialize AWS SDK
-    let config = aws_config::load_from_env().await;
+    let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
     let db = DynamoClient::new(&config);
 
     // Initialize API Gateway Management client for WebSocket broadcasts
@@ -495,3 +1859,41 @@ async fn main() -> Result<(), Error> {
     }))
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_the_context_stage() {
+        assert_eq!(strip_stage("/staging/servers/abc", Some("staging")), "/servers/abc");
+        assert_eq!(strip_stage("/staging", Some("staging")), "");
+    }
+
+    #[test]
+    fn leaves_paths_that_only_start_like_the_stage() {
+        assert_eq!(strip_stage("/stagingarea/servers", Some("staging")), "/stagingarea/servers");
+        assert_eq!(strip_stage("/servers/abc", Some("staging")), "/servers/abc");
+        assert_eq!(strip_stage("/servers/abc", Some("$default")), "/servers/abc");
+    }
+
+    #[test]
+    fn falls_back_to_known_stages_without_context() {
+        assert_eq!(strip_stage("/dev/servers/abc", None), "/servers/abc");
+        assert_eq!(strip_stage("/prod/health", None), "/health");
+        assert_eq!(strip_stage("/health", None), "/health");
+    }
+
+    #[test]
+    fn allowed_methods_for_static_and_param_paths() {
+        assert_eq!(allowed_methods(&["health"]), vec!["GET"]);
+        assert_eq!(allowed_methods(&["servers", "abc"]), vec!["GET", "PATCH"]);
+    }
+
+    #[test]
+    fn allowed_methods_empty_for_unknown_paths() {
+        assert!(allowed_methods(&["nope"]).is_empty());
+        assert!(allowed_methods(&["health", "extra"]).is_empty());
+        assert!(allowed_methods(&[]).is_empty());
+    }
+}
@@ -1,12 +1,23 @@
 use aws_sdk_apigatewaymanagement::Client as ApiGwClient;
-use aws_sdk_apigatewaymanagement::primitives::Blob;
-use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::types::{AttributeValue, DeleteRequest, WriteRequest};
 use aws_sdk_dynamodb::Client as DynamoClient;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::env;
 use uuid::Uuid;
 
+use crate::audit;
+use crate::auth;
+use crate::broadcast;
+use crate::clock;
+use crate::link_previews::LinkPreview;
+use crate::roles;
+use crate::sanitize;
+use crate::servers::{
+    batch_find_members, get_channel_record, get_server_record, list_channels, load_membership, next_message_seq, Channel, Member,
+    CHANNEL_TYPE_ANNOUNCEMENT, CHANNEL_TYPE_VOICE,
+};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub id: String,
@@ -15,9 +26,36 @@ pub struct Message {
     pub author_username: String,
     pub content: String,
     pub created_at: i64,
+    /// Per-channel sequence number, increasing by one per message, so
+    /// clients can dedupe REST and WebSocket copies and spot gaps. Absent on
+    /// messages from before sequencing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forwarded_from: Option<ForwardedFrom>,
+    /// Whether the author still belongs to the server; only filled in when
+    /// listing with `author_status=true`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author_is_member: Option<bool>,
+}
+
+/// Attribution for a message copied from elsewhere. Forwarding a forward
+/// keeps the original attribution.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForwardedFrom {
+    pub author_id: String,
+    pub author_username: String,
+    /// "channel", "dm" or "announcement"
+    pub source_type: String,
+    /// When the original message was sent (ms)
+    pub created_at: i64,
+    /// Set for mirrored announcements from a followed server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_name: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct CreateMessageRequest {
     pub content: String,
 }
@@ -27,6 +65,85 @@ pub struct MessagesResponse {
     pub messages: Vec<Message>,
     pub has_more: bool,
     pub next_cursor: Option<i64>,
+    /// Keyed by message id; only filled in when previews are requested
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub link_previews: HashMap<String, LinkPreview>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    /// One message object per line, built page by page
+    Ndjson,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonExport<'a> {
+    channel_id: &'a str,
+    exported_at: i64,
+    truncated: bool,
+    messages: &'a [Message],
+}
+
+/// A serialized channel export ready to be returned as a file download
+pub struct ChannelExport {
+    pub filename: String,
+    pub content_type: &'static str,
+    pub body: String,
+    /// The export stopped at a size cap before reaching the newest message
+    pub truncated: bool,
+}
+
+const MAX_MESSAGE_CHARS: usize = 2000;
+/// Hard cap when a server excludes code blocks from the length count
+const MAX_MESSAGE_BYTES_WITH_CODE: usize = 16 * 1024;
+
+/// Upper bound on messages gathered into a single export
+const MAX_EXPORT_MESSAGES: usize = 10_000;
+/// NDJSON exports stop before this many bytes, under Lambda's 6 MB
+/// response limit
+const MAX_NDJSON_EXPORT_BYTES: usize = 5 * 1024 * 1024;
+/// Upper bound on messages deleted by a single purge request
+const MAX_PURGE_MESSAGES: usize = 1_000;
+/// BatchWriteItem accepts at most 25 requests per call
+const BATCH_WRITE_MAX_ITEMS: usize = 25;
+
+const MIN_SEARCH_QUERY_CHARS: usize = 2;
+const MAX_SEARCH_QUERY_CHARS: usize = 100;
+/// Message search reads at most this many of the channel's newest messages
+const MAX_SEARCH_SCANNED: usize = 1_000;
+const MAX_MESSAGE_SEARCH_RESULTS: usize = 25;
+
+/// A message with its neighbours, oldest first
+#[derive(Debug, Serialize)]
+pub struct MessageContext {
+    pub target_id: String,
+    pub messages: Vec<Message>,
+    pub has_more_before: bool,
+    pub has_more_after: bool,
+}
+
+/// A search hit with where the query matched, as `[start, end)` byte
+/// offsets into `content`
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub message: Message,
+    pub matches: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MessageSearchResponse {
+    pub results: Vec<SearchHit>,
+    /// Messages examined; only the newest `MAX_SEARCH_SCANNED` are searched
+    pub scanned: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PurgeResult {
+    pub deleted: usize,
+    /// More messages remain; call again to continue
+    pub has_more: bool,
 }
 
 fn get_table(name: &str) -> String {
@@ -39,7 +156,7 @@ fn get_table(name: &str) -> String {
 }
 
 /// Verify that the channel exists and belongs to the given server
-async fn verify_channel(
+pub async fn verify_channel(
     db: &DynamoClient,
     server_id: &str,
     channel_id: &str,
@@ -61,24 +178,101 @@ async fn verify_channel(
 }
 
 /// Check if user is a member of the server
-async fn check_membership(
+pub async fn check_membership(
     db: &DynamoClient,
     server_id: &str,
     user_id: &str,
 ) -> Result<(), (u16, String)> {
-    let result = db
-        .get_item()
-        .table_name(get_table("MEMBERS_TABLE"))
-        .key("server_id", AttributeValue::S(server_id.to_string()))
-        .key("user_id", AttributeValue::S(user_id.to_string()))
-        .send()
-        .await
-        .map_err(|e| (500, format!("Database error: {}", e)))?;
+    load_membership(db, server_id, user_id).await.map(|_| ())
+}
 
-    if result.item().is_none() {
-        return Err((403, "You are not a member of this server".to_string()));
+/// Check the user is a member who may currently post, i.e. isn't timed out,
+/// and return their membership for the channel checks that follow
+pub async fn check_can_post(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+) -> Result<Member, (u16, String)> {
+    let member = load_membership(db, server_id, user_id).await?;
+
+    let remaining = member.timeout_until.unwrap_or(0) - clock::now_secs();
+    if remaining > 0 {
+        return Err((
+            403,
+            format!("You are timed out for another {} seconds", remaining),
+        ));
     }
 
+    Ok(member)
+}
+
+/// Only moderators (manage_messages) may post in announcement channels, and
+/// only channel managers (manage_channels) in read-only ones. `member` is the
+/// poster's membership from `check_can_post`.
+pub async fn check_channel_poster(
+    db: &DynamoClient,
+    channel: &Channel,
+    member: &Member,
+) -> Result<(), (u16, String)> {
+    if channel.read_only && !roles::member_has_permission(db, member, roles::MANAGE_CHANNELS).await? {
+        return Err((403, "This channel is read-only".to_string()));
+    }
+    if channel.channel_type == CHANNEL_TYPE_ANNOUNCEMENT
+        && !roles::member_has_permission(db, member, roles::MANAGE_MESSAGES).await?
+    {
+        return Err((403, "Only moderators can post in announcement channels".to_string()));
+    }
+    Ok(())
+}
+
+/// Get the caller's role in the server
+async fn get_member_role(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+) -> Result<String, (u16, String)> {
+    load_membership(db, server_id, user_id).await.map(|m| m.role)
+}
+
+/// Length of `content` with the inside of ```-fenced blocks left out. The
+/// fences themselves still count, and an unclosed fence counts as text so
+/// it can't be used to skip the limit.
+pub fn count_effective_length(content: &str) -> usize {
+    let segments: Vec<&str> = content.split("```").collect();
+    let fences = segments.len() - 1;
+    let closed = fences - fences % 2;
+
+    segments
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| i % 2 == 0 || *i > closed)
+        .map(|(_, segment)| segment.len())
+        .sum::<usize>()
+        + fences * 3
+}
+
+/// Enforce the message length limit. Servers that exclude code blocks still
+/// get an absolute cap on the whole message.
+fn check_content_length(content: &str, exclude_code: bool) -> Result<(), (u16, String)> {
+    if !exclude_code {
+        if content.len() > MAX_MESSAGE_CHARS {
+            return Err((400, format!("Message content cannot exceed {} characters", MAX_MESSAGE_CHARS)));
+        }
+        return Ok(());
+    }
+
+    if content.len() > MAX_MESSAGE_BYTES_WITH_CODE {
+        return Err((
+            400,
+            format!("Message content cannot exceed {} bytes", MAX_MESSAGE_BYTES_WITH_CODE),
+        ));
+    }
+    if count_effective_length(content) > MAX_MESSAGE_CHARS {
+        return Err((
+            400,
+            format!("Message content outside code blocks cannot exceed {} characters", MAX_MESSAGE_CHARS),
+        ));
+    }
     Ok(())
 }
 
@@ -91,24 +285,36 @@ pub async fn create_message(
     username: &str,
     body: &str,
 ) -> Result<Message, (u16, String)> {
-    // Verify membership
-    check_membership(db, server_id, user_id).await?;
+    // Verify membership and that the member isn't timed out
+    let member = check_can_post(db, server_id, user_id).await?;
 
-    // Verify channel exists in this server
-    verify_channel(db, server_id, channel_id).await?;
+    // Verify channel exists in this server and takes text
+    let channel = get_channel_record(db, server_id, channel_id).await?;
+    if channel.channel_type == CHANNEL_TYPE_VOICE && !channel.text_in_voice {
+        return Err((400, "Cannot post text to a voice channel".to_string()));
+    }
+    check_channel_poster(db, &channel, &member).await?;
 
     // Parse request
     let req: CreateMessageRequest = serde_json::from_str(body)
         .map_err(|e| (400, format!("Invalid request: {}", e)))?;
 
-    // Validate content
-    let content = req.content.trim();
-    if content.is_empty() {
+    // Validate content. Servers can opt out of trimming (e.g. for ASCII art),
+    // but whitespace-only messages are always rejected.
+    let server = get_server_record(db, server_id).await?;
+    let sanitized = sanitize::sanitize_content(&req.content)?;
+    let content = if server.trim_messages {
+        sanitized.trim()
+    } else {
+        sanitized.as_str()
+    };
+    if content.trim().is_empty() {
         return Err((400, "Message content cannot be empty".to_string()));
     }
-    if content.len() > 2000 {
-        return Err((400, "Message content cannot exceed 2000 characters".to_string()));
-    }
+    check_content_length(content, server.exclude_code_from_length)?;
+
+    let created_at = clock::now_millis();
+    let seq = next_message_seq(db, server_id, channel_id, created_at).await?;
 
     let message = Message {
         id: Uuid::new_v4().to_string(),
@@ -116,23 +322,40 @@ pub async fn create_message(
         author_id: user_id.to_string(),
         author_username: username.to_string(),
         content: content.to_string(),
-        created_at: chrono::Utc::now().timestamp_millis(),
+        created_at,
+        seq: Some(seq),
+        forwarded_from: None,
+        author_is_member: None,
     };
 
-    // Store in DynamoDB
-    db.put_item()
+    store_message(db, &message).await?;
+
+    Ok(message)
+}
+
+/// Write a new message row
+pub async fn store_message(db: &DynamoClient, message: &Message) -> Result<(), (u16, String)> {
+    let mut put = db
+        .put_item()
         .table_name(get_table("MESSAGES_TABLE"))
         .item("channel_id", AttributeValue::S(message.channel_id.clone()))
         .item("created_at", AttributeValue::N(message.created_at.to_string()))
         .item("id", AttributeValue::S(message.id.clone()))
         .item("author_id", AttributeValue::S(message.author_id.clone()))
         .item("author_username", AttributeValue::S(message.author_username.clone()))
-        .item("content", AttributeValue::S(message.content.clone()))
-        .send()
+        .item("content", AttributeValue::S(message.content.clone()));
+    if let Some(seq) = message.seq {
+        put = put.item("seq", AttributeValue::N(seq.to_string()));
+    }
+    if let Some(origin) = &message.forwarded_from {
+        put = put.item("forwarded_from", forwarded_from_attribute(origin));
+    }
+
+    put.send()
         .await
         .map_err(|e| (500, format!("Failed to save message: {}", e)))?;
 
-    Ok(message)
+    Ok(())
 }
 
 /// List messages in a channel with pagination
@@ -151,7 +374,7 @@ pub async fn list_messages(
     verify_channel(db, server_id, channel_id).await?;
 
     // Clamp limit
-    let limit = limit.min(100).max(1);
+    let limit = limit.clamp(1, 100);
 
     // Build query
     let mut query = db
@@ -198,9 +421,550 @@ pub async fn list_messages(
         messages,
         has_more,
         next_cursor,
+        link_previews: HashMap::new(),
+    })
+}
+
+/// Byte ranges where `query` occurs in `content`, ignoring ASCII case.
+/// Matches don't overlap, and always start and end on char boundaries.
+fn find_matches(content: &str, query: &str) -> Vec<(usize, usize)> {
+    let haystack = content.as_bytes();
+    let needle = query.as_bytes();
+    let mut matches = Vec::new();
+    if needle.is_empty() || needle.len() > haystack.len() {
+        return matches;
+    }
+
+    let mut i = 0;
+    while i + needle.len() <= haystack.len() {
+        if haystack[i..i + needle.len()].eq_ignore_ascii_case(needle) {
+            matches.push((i, i + needle.len()));
+            i += needle.len();
+        } else {
+            i += 1;
+        }
+    }
+    matches
+}
+
+/// Search the channel's recent messages for `query`. This is a stopgap until
+/// there's a real index: the newest messages are read and matched in memory,
+/// then ranked by number of matches, newest first among equals.
+pub async fn search_messages(
+    db: &DynamoClient,
+    server_id: &str,
+    channel_id: &str,
+    user_id: &str,
+    query: &str,
+) -> Result<MessageSearchResponse, (u16, String)> {
+    let query = query.trim();
+    let query_chars = query.chars().count();
+    if !(MIN_SEARCH_QUERY_CHARS..=MAX_SEARCH_QUERY_CHARS).contains(&query_chars) {
+        return Err((
+            400,
+            format!(
+                "Search query must be {}-{} characters",
+                MIN_SEARCH_QUERY_CHARS, MAX_SEARCH_QUERY_CHARS
+            ),
+        ));
+    }
+
+    check_membership(db, server_id, user_id).await?;
+    verify_channel(db, server_id, channel_id).await?;
+
+    let mut hits: Vec<SearchHit> = Vec::new();
+    let mut scanned = 0;
+    let mut start_key: Option<HashMap<String, AttributeValue>> = None;
+
+    loop {
+        let page_size = (MAX_SEARCH_SCANNED - scanned).min(100);
+        let result = db
+            .query()
+            .table_name(get_table("MESSAGES_TABLE"))
+            .key_condition_expression("channel_id = :cid")
+            .expression_attribute_values(":cid", AttributeValue::S(channel_id.to_string()))
+            .scan_index_forward(false)
+            .limit(page_size as i32)
+            .set_exclusive_start_key(start_key.take())
+            .send()
+            .await
+            .map_err(|e| (500, format!("Search failed: {}", e)))?;
+
+        scanned += result.items().len();
+        for message in result.items().iter().filter_map(parse_message) {
+            let matches = find_matches(&message.content, query);
+            if !matches.is_empty() {
+                hits.push(SearchHit { message, matches });
+            }
+        }
+
+        start_key = result.last_evaluated_key().cloned();
+        if start_key.is_none() || scanned >= MAX_SEARCH_SCANNED {
+            break;
+        }
+    }
+
+    hits.sort_by(|a, b| {
+        b.matches
+            .len()
+            .cmp(&a.matches.len())
+            .then(b.message.created_at.cmp(&a.message.created_at))
+    });
+    hits.truncate(MAX_MESSAGE_SEARCH_RESULTS);
+
+    Ok(MessageSearchResponse { results: hits, scanned })
+}
+
+/// Max pages read per request when filtering by author, so a channel where
+/// the author rarely posts can't turn one request into a full table walk
+const MAX_AUTHOR_FILTER_PAGES: usize = 10;
+
+/// List messages in a channel posted by a single author, newest first
+pub async fn list_messages_by_author(
+    db: &DynamoClient,
+    server_id: &str,
+    channel_id: &str,
+    author_id: &str,
+    user_id: &str,
+    limit: usize,
+    before: Option<i64>,
+) -> Result<MessagesResponse, (u16, String)> {
+    // Verify membership
+    check_membership(db, server_id, user_id).await?;
+
+    // Verify channel exists
+    verify_channel(db, server_id, channel_id).await?;
+
+    let limit = limit.clamp(1, 100);
+
+    let mut messages: Vec<Message> = Vec::new();
+    let mut start_key: Option<HashMap<String, AttributeValue>> = None;
+    let mut pages = 0;
+
+    // Filter expressions apply after the page limit, so keep reading pages
+    // until we have enough matches or run out of budget
+    loop {
+        let mut query = db
+            .query()
+            .table_name(get_table("MESSAGES_TABLE"))
+            .key_condition_expression(if before.is_some() {
+                "channel_id = :cid AND created_at < :before"
+            } else {
+                "channel_id = :cid"
+            })
+            .filter_expression("author_id = :aid")
+            .expression_attribute_values(":cid", AttributeValue::S(channel_id.to_string()))
+            .expression_attribute_values(":aid", AttributeValue::S(author_id.to_string()))
+            .scan_index_forward(false)
+            .limit(100)
+            .set_exclusive_start_key(start_key.take());
+
+        if let Some(before_ts) = before {
+            query = query.expression_attribute_values(":before", AttributeValue::N(before_ts.to_string()));
+        }
+
+        let result = query
+            .send()
+            .await
+            .map_err(|e| (500, format!("Failed to list messages: {}", e)))?;
+
+        messages.extend(result.items().iter().filter_map(parse_message));
+        start_key = result.last_evaluated_key().cloned();
+        pages += 1;
+
+        if messages.len() > limit || start_key.is_none() || pages >= MAX_AUTHOR_FILTER_PAGES {
+            break;
+        }
+    }
+
+    let has_more = messages.len() > limit || start_key.is_some();
+    let next_cursor = if messages.len() > limit {
+        messages.truncate(limit);
+        messages.last().map(|m| m.created_at)
+    } else {
+        // Resume from where the read budget ran out
+        start_key
+            .as_ref()
+            .and_then(|k| k.get("created_at")?.as_n().ok()?.parse().ok())
+    };
+
+    Ok(MessagesResponse {
+        messages,
+        has_more,
+        next_cursor,
+        link_previews: HashMap::new(),
     })
 }
 
+/// Export a channel's history (oldest first) as JSON or CSV
+pub async fn export_messages(
+    db: &DynamoClient,
+    server_id: &str,
+    channel_id: &str,
+    user_id: &str,
+    format: ExportFormat,
+) -> Result<ChannelExport, (u16, String)> {
+    let role = get_member_role(db, server_id, user_id).await?;
+    if role != "owner" && role != "admin" {
+        return Err((403, "Only owners and admins can export channels".to_string()));
+    }
+
+    verify_channel(db, server_id, channel_id).await?;
+
+    match format {
+        ExportFormat::Json => {
+            let (messages, truncated) = load_export_messages(db, channel_id).await?;
+            let body = serde_json::to_string(&JsonExport {
+                channel_id,
+                exported_at: clock::now_millis(),
+                truncated,
+                messages: &messages,
+            })
+            .map_err(|e| (500, format!("Failed to serialize export: {}", e)))?;
+
+            Ok(ChannelExport {
+                filename: format!("{}.json", channel_id),
+                content_type: "application/json",
+                body,
+                truncated,
+            })
+        }
+        ExportFormat::Csv => {
+            let (messages, truncated) = load_export_messages(db, channel_id).await?;
+            Ok(ChannelExport {
+                filename: format!("{}.csv", channel_id),
+                content_type: "text/csv; charset=utf-8",
+                body: messages_to_csv(&messages),
+                truncated,
+            })
+        }
+        ExportFormat::Ndjson => export_ndjson(db, channel_id).await,
+    }
+}
+
+/// Up to MAX_EXPORT_MESSAGES of the channel's history, oldest first, and
+/// whether there was more
+async fn load_export_messages(
+    db: &DynamoClient,
+    channel_id: &str,
+) -> Result<(Vec<Message>, bool), (u16, String)> {
+    let mut messages: Vec<Message> = Vec::new();
+    let mut start_key: Option<HashMap<String, AttributeValue>> = None;
+
+    loop {
+        let result = query_export_page(db, channel_id, start_key.take()).await?;
+
+        messages.extend(result.items().iter().filter_map(parse_message));
+        start_key = result.last_evaluated_key().cloned();
+
+        if start_key.is_none() || messages.len() >= MAX_EXPORT_MESSAGES {
+            break;
+        }
+    }
+
+    let truncated = messages.len() > MAX_EXPORT_MESSAGES || start_key.is_some();
+    messages.truncate(MAX_EXPORT_MESSAGES);
+    Ok((messages, truncated))
+}
+
+/// Serialize each page straight into the body so only one page of messages
+/// is held at a time, stopping at the byte cap
+async fn export_ndjson(db: &DynamoClient, channel_id: &str) -> Result<ChannelExport, (u16, String)> {
+    let mut body = String::new();
+    let mut truncated = false;
+    let mut start_key: Option<HashMap<String, AttributeValue>> = None;
+
+    'pages: loop {
+        let result = query_export_page(db, channel_id, start_key.take()).await?;
+
+        for message in result.items().iter().filter_map(parse_message) {
+            let line = serde_json::to_string(&message)
+                .map_err(|e| (500, format!("Failed to serialize export: {}", e)))?;
+            if body.len() + line.len() + 1 > MAX_NDJSON_EXPORT_BYTES {
+                truncated = true;
+                break 'pages;
+            }
+            body.push_str(&line);
+            body.push('\n');
+        }
+
+        start_key = result.last_evaluated_key().cloned();
+        if start_key.is_none() {
+            break;
+        }
+    }
+
+    Ok(ChannelExport {
+        filename: format!("{}.ndjson", channel_id),
+        content_type: "application/x-ndjson",
+        body,
+        truncated,
+    })
+}
+
+async fn query_export_page(
+    db: &DynamoClient,
+    channel_id: &str,
+    start_key: Option<HashMap<String, AttributeValue>>,
+) -> Result<aws_sdk_dynamodb::operation::query::QueryOutput, (u16, String)> {
+    db.query()
+        .table_name(get_table("MESSAGES_TABLE"))
+        .key_condition_expression("channel_id = :cid")
+        .expression_attribute_values(":cid", AttributeValue::S(channel_id.to_string()))
+        .scan_index_forward(true) // Oldest first
+        .limit(500)
+        .set_exclusive_start_key(start_key)
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to export messages: {}", e)))
+}
+
+fn messages_to_csv(messages: &[Message]) -> String {
+    let mut out = String::from("id,created_at,author_id,author_username,content\r\n");
+    for m in messages {
+        let row = [
+            csv_field(&m.id),
+            m.created_at.to_string(),
+            csv_field(&m.author_id),
+            csv_field(&m.author_username),
+            csv_field(&m.content),
+        ];
+        out.push_str(&row.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+/// Quote a CSV field (RFC 4180) if it contains a delimiter, quote, or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Load a message plus up to `radius` messages on either side of it, for
+/// deep links into channel history
+pub async fn get_message_context(
+    db: &DynamoClient,
+    server_id: &str,
+    channel_id: &str,
+    message_id: &str,
+    user_id: &str,
+    radius: usize,
+) -> Result<MessageContext, (u16, String)> {
+    check_membership(db, server_id, user_id).await?;
+    verify_channel(db, server_id, channel_id).await?;
+
+    let radius = radius.clamp(1, 50);
+    let target = find_message(db, channel_id, message_id)
+        .await?
+        .ok_or((404, "Message not found".to_string()))?;
+
+    let (mut before, has_more_before) =
+        query_around(db, channel_id, target.created_at, radius, false).await?;
+    let (after, has_more_after) =
+        query_around(db, channel_id, target.created_at, radius, true).await?;
+
+    // `before` comes back newest first
+    before.reverse();
+    let mut messages = before;
+    messages.push(target);
+    messages.extend(after);
+
+    Ok(MessageContext {
+        target_id: message_id.to_string(),
+        messages,
+        has_more_before,
+        has_more_after,
+    })
+}
+
+/// Up to `limit` messages strictly after (or before) `created_at`, nearest first
+async fn query_around(
+    db: &DynamoClient,
+    channel_id: &str,
+    created_at: i64,
+    limit: usize,
+    after: bool,
+) -> Result<(Vec<Message>, bool), (u16, String)> {
+    let result = db
+        .query()
+        .table_name(get_table("MESSAGES_TABLE"))
+        .key_condition_expression(if after {
+            "channel_id = :cid AND created_at > :ts"
+        } else {
+            "channel_id = :cid AND created_at < :ts"
+        })
+        .expression_attribute_values(":cid", AttributeValue::S(channel_id.to_string()))
+        .expression_attribute_values(":ts", AttributeValue::N(created_at.to_string()))
+        .scan_index_forward(after)
+        .limit((limit + 1) as i32) // One extra to check for more
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to load messages: {}", e)))?;
+
+    let mut messages: Vec<Message> = result.items().iter().filter_map(parse_message).collect();
+    let has_more = messages.len() > limit;
+    messages.truncate(limit);
+
+    Ok((messages, has_more))
+}
+
+/// Delete every message `target_user_id` authored in the server (owner only).
+/// Deletes at most MAX_PURGE_MESSAGES per call and reports `has_more` so the
+/// caller can repeat until the user's history is gone.
+pub async fn delete_user_messages(
+    db: &DynamoClient,
+    server_id: &str,
+    target_user_id: &str,
+    actor_id: &str,
+) -> Result<PurgeResult, (u16, String)> {
+    let role = get_member_role(db, server_id, actor_id).await?;
+    if role != "owner" {
+        return Err((403, "Only the server owner can purge messages".to_string()));
+    }
+
+    let mut deleted = 0;
+    let mut has_more = false;
+
+    'channels: for channel in list_channels(db, server_id).await? {
+        let mut start_key: Option<HashMap<String, AttributeValue>> = None;
+
+        loop {
+            let result = db
+                .query()
+                .table_name(get_table("MESSAGES_TABLE"))
+                .key_condition_expression("channel_id = :cid")
+                .filter_expression("author_id = :uid")
+                .expression_attribute_values(":cid", AttributeValue::S(channel.id.clone()))
+                .expression_attribute_values(":uid", AttributeValue::S(target_user_id.to_string()))
+                .projection_expression("channel_id, created_at")
+                .set_exclusive_start_key(start_key.take())
+                .send()
+                .await
+                .map_err(|e| (500, format!("Failed to find messages: {}", e)))?;
+
+            let mut keys: Vec<HashMap<String, AttributeValue>> = result.items().to_vec();
+            let remaining = MAX_PURGE_MESSAGES - deleted;
+            if keys.len() > remaining {
+                keys.truncate(remaining);
+                has_more = true;
+            }
+
+            deleted += keys.len();
+            batch_delete_messages(db, keys).await?;
+
+            start_key = result.last_evaluated_key().cloned();
+            if deleted >= MAX_PURGE_MESSAGES {
+                // Stopping at the cap; anything unread may still hold matches
+                has_more = has_more || start_key.is_some();
+                break 'channels;
+            }
+            if start_key.is_none() {
+                break;
+            }
+        }
+    }
+
+    audit::record(
+        db,
+        server_id,
+        actor_id,
+        "purge_messages",
+        Some(target_user_id),
+        serde_json::json!({ "deleted": deleted, "has_more": has_more }),
+    )
+    .await;
+
+    Ok(PurgeResult { deleted, has_more })
+}
+
+/// Delete message rows by key, retrying anything DynamoDB leaves unprocessed
+async fn batch_delete_messages(
+    db: &DynamoClient,
+    keys: Vec<HashMap<String, AttributeValue>>,
+) -> Result<(), (u16, String)> {
+    let table = get_table("MESSAGES_TABLE");
+
+    for chunk in keys.chunks(BATCH_WRITE_MAX_ITEMS) {
+        let mut requests = chunk
+            .iter()
+            .map(|key| {
+                DeleteRequest::builder()
+                    .set_key(Some(key.clone()))
+                    .build()
+                    .map(|delete| WriteRequest::builder().delete_request(delete).build())
+                    .map_err(|e| (500, format!("Failed to build delete request: {}", e)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        while !requests.is_empty() {
+            let result = db
+                .batch_write_item()
+                .request_items(&table, requests)
+                .send()
+                .await
+                .map_err(|e| (500, format!("Failed to delete messages: {}", e)))?;
+
+            requests = result
+                .unprocessed_items()
+                .and_then(|u| u.get(&table))
+                .cloned()
+                .unwrap_or_default();
+        }
+    }
+
+    Ok(())
+}
+
+/// Look up a message by id via the id-index, returning it only if it belongs
+/// to the given channel
+pub async fn find_message(
+    db: &DynamoClient,
+    channel_id: &str,
+    message_id: &str,
+) -> Result<Option<Message>, (u16, String)> {
+    let result = db
+        .query()
+        .table_name(get_table("MESSAGES_TABLE"))
+        .index_name("id-index")
+        .key_condition_expression("id = :mid")
+        .expression_attribute_values(":mid", AttributeValue::S(message_id.to_string()))
+        .limit(1)
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+    Ok(result
+        .items()
+        .first()
+        .and_then(parse_message)
+        .filter(|m| m.channel_id == channel_id))
+}
+
+/// Set `author_is_member` on each message. Authors who left the server or
+/// whose account no longer exists are flagged false; their messages stay.
+pub async fn flag_ex_members(
+    db: &DynamoClient,
+    server_id: &str,
+    messages: &mut [Message],
+) -> Result<(), (u16, String)> {
+    let mut author_ids: Vec<String> = messages.iter().map(|m| m.author_id.clone()).collect();
+    author_ids.sort();
+    author_ids.dedup();
+
+    let members = batch_find_members(db, server_id, &author_ids).await?;
+    let member_ids: Vec<String> = author_ids.into_iter().filter(|id| members.contains(id)).collect();
+    let existing = auth::batch_get_usernames(db, &member_ids).await?;
+
+    for message in messages.iter_mut() {
+        message.author_is_member = Some(existing.contains_key(&message.author_id));
+    }
+    Ok(())
+}
+
 fn parse_message(item: &HashMap<String, AttributeValue>) -> Option<Message> {
     Some(Message {
         id: item.get("id")?.as_s().ok()?.clone(),
@@ -209,95 +973,224 @@ fn parse_message(item: &HashMap<String, AttributeValue>) -> Option<Message> {
         author_username: item.get("author_username")?.as_s().ok()?.clone(),
         content: item.get("content")?.as_s().ok()?.clone(),
         created_at: item.get("created_at")?.as_n().ok()?.parse().ok()?,
+        seq: item.get("seq").and_then(|v| v.as_n().ok()).and_then(|n| n.parse().ok()),
+        forwarded_from: parse_forwarded_from(item),
+        author_is_member: None,
     })
 }
 
+/// `forwarded_from` is stored as a JSON string, shared with DM rows
+pub fn forwarded_from_attribute(origin: &ForwardedFrom) -> AttributeValue {
+    AttributeValue::S(serde_json::to_string(origin).unwrap_or_default())
+}
+
+pub fn parse_forwarded_from(item: &HashMap<String, AttributeValue>) -> Option<ForwardedFrom> {
+    item.get("forwarded_from")
+        .and_then(|v| v.as_s().ok())
+        .and_then(|raw| serde_json::from_str(raw).ok())
+}
+
 /// Broadcast a message to all WebSocket connections subscribed to the channel
 pub async fn broadcast_message(
     db: &DynamoClient,
     apigw: &ApiGwClient,
+    server_id: &str,
     message: &Message,
 ) {
-    // Find all connections subscribed to this channel
-    let scan_result = db
-        .scan()
-        .table_name(get_table("CONNECTIONS_TABLE"))
-        .filter_expression("contains(channels, :channel_id)")
-        .expression_attribute_values(
-            ":channel_id",
-            AttributeValue::S(message.channel_id.clone()),
-        )
-        .send()
-        .await;
-
-    let connections = match scan_result {
-        Ok(result) => result.items().to_vec(),
+    let payload = match broadcast::build_payload(
+        "message_created",
+        Some("new_message"),
+        Some(server_id),
+        Some(&message.channel_id),
+        message,
+    ) {
+        Ok(b) => b,
         Err(e) => {
-            tracing::error!(error = %e, "Failed to scan connections");
+            tracing::error!(error = %e, "Failed to serialize message");
             return;
         }
     };
 
-    if connections.is_empty() {
-        tracing::debug!(channel_id = %message.channel_id, "No subscribers for channel");
-        return;
+    let num_recipients = broadcast::send_to_subscribers(db, apigw, &message.channel_id, &payload).await;
+
+    tracing::info!(
+        channel_id = %message.channel_id,
+        recipients = num_recipients,
+        "Broadcast complete"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_field_leaves_plain_values_alone() {
+        assert_eq!(csv_field("hello world"), "hello world");
+        assert_eq!(csv_field(""), "");
     }
 
-    // Prepare broadcast payload
-    let payload = serde_json::json!({
-        "type": "new_message",
-        "message": message
-    });
-    let payload_bytes = match serde_json::to_vec(&payload) {
-        Ok(b) => b,
-        Err(e) => {
-            tracing::error!(error = %e, "Failed to serialize message");
-            return;
-        }
-    };
+    #[test]
+    fn csv_field_quotes_commas_and_line_breaks() {
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("line one\nline two"), "\"line one\nline two\"");
+        assert_eq!(csv_field("cr\rhere"), "\"cr\rhere\"");
+    }
 
-    let num_recipients = connections.len();
+    #[test]
+    fn csv_field_doubles_embedded_quotes() {
+        assert_eq!(csv_field(r#"say "hi""#), r#""say ""hi""""#);
+        assert_eq!(csv_field(r#"a "b", c"#), r#""a ""b"", c""#);
+    }
 
-    // Send to each connection
-    for conn in &connections {
-        let connection_id = match conn.get("connection_id").and_then(|v| v.as_s().ok()) {
-            Some(id) => id.clone(),
-            None => continue,
-        };
+    #[test]
+    fn effective_length_skips_closed_code_blocks() {
+        // Fences count, the code between them doesn't
+        assert_eq!(count_effective_length("hi ```let x = 1;``` bye"), "hi ".len() + 6 + " bye".len());
+        assert_eq!(count_effective_length("```a``````b```"), 12);
+        assert_eq!(count_effective_length("no code here"), 12);
+    }
 
-        let result = apigw
-            .post_to_connection()
-            .connection_id(&connection_id)
-            .data(Blob::new(payload_bytes.clone()))
-            .send()
-            .await;
+    #[test]
+    fn effective_length_counts_an_unclosed_fence_as_text() {
+        let content = "```".to_string() + &"x".repeat(5000);
+        assert_eq!(count_effective_length(&content), content.len());
 
-        match result {
-            Ok(_) => {
-                tracing::debug!(connection_id = %connection_id, "Message sent");
-            }
-            Err(e) => {
-                // Check if connection is stale (GoneException)
-                let err_str = e.to_string();
-                if err_str.contains("Gone") || err_str.contains("410") {
-                    tracing::info!(connection_id = %connection_id, "Stale connection, removing");
-                    // Delete stale connection
-                    let _ = db
-                        .delete_item()
-                        .table_name(get_table("CONNECTIONS_TABLE"))
-                        .key("connection_id", AttributeValue::S(connection_id))
-                        .send()
-                        .await;
-                } else {
-                    tracing::warn!(connection_id = %connection_id, error = %e, "Failed to send message");
-                }
+        // A closed block followed by a dangling fence: only the closed block is skipped
+        let content = "a```code```b```tail";
+        assert_eq!(count_effective_length(content), "a".len() + "b".len() + "tail".len() + 9);
+    }
+
+    #[test]
+    fn find_matches_is_ascii_case_insensitive() {
+        assert_eq!(find_matches("Hello hello HELLO", "hello"), vec![(0, 5), (6, 11), (12, 17)]);
+    }
+
+    #[test]
+    fn find_matches_does_not_overlap() {
+        assert_eq!(find_matches("aaaa", "aa"), vec![(0, 2), (2, 4)]);
+    }
+
+    #[test]
+    fn find_matches_handles_empty_and_oversized_queries() {
+        assert!(find_matches("text", "").is_empty());
+        assert!(find_matches("ab", "abc").is_empty());
+        assert!(find_matches("text", "zzz").is_empty());
+    }
+
+    #[test]
+    fn find_matches_returns_byte_offsets_around_multibyte_text() {
+        let content = "café Cafe";
+        assert_eq!(find_matches(content, "cafe"), vec![(6, 10)]);
+        assert_eq!(&content[6..10], "Cafe");
+    }
+
+    fn member_db(timeout_until: Option<i64>) -> DynamoClient {
+        use aws_sdk_dynamodb::operation::get_item::GetItemOutput;
+        use aws_smithy_mocks::{mock, mock_client, RuleMode};
+
+        let rule = mock!(aws_sdk_dynamodb::Client::get_item).then_output(move || {
+            let mut output = GetItemOutput::builder()
+                .item("server_id", AttributeValue::S("server-1".to_string()))
+                .item("user_id", AttributeValue::S("user-1".to_string()))
+                .item("username", AttributeValue::S("ada".to_string()))
+                .item("role", AttributeValue::S("member".to_string()))
+                .item("joined_at", AttributeValue::N("0".to_string()));
+            if let Some(until) = timeout_until {
+                output = output.item("timeout_until", AttributeValue::N(until.to_string()));
             }
+            output.build()
+        });
+        mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&rule])
+    }
+
+    #[tokio::test]
+    async fn timed_out_member_cannot_post_but_can_read() {
+        let _clock = crate::clock::set_clock(crate::clock::FixedClock(1_700_000_000_000));
+        let db = member_db(Some(1_700_000_120));
+
+        let err = check_can_post(&db, "server-1", "user-1").await.unwrap_err();
+        assert_eq!(err, (403, "You are timed out for another 120 seconds".to_string()));
+        assert!(check_membership(&db, "server-1", "user-1").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn expired_timeout_no_longer_blocks_posting() {
+        let _clock = crate::clock::set_clock(crate::clock::FixedClock(1_700_000_000_000));
+        assert!(check_can_post(&member_db(Some(1_700_000_000)), "server-1", "user-1").await.is_ok());
+        assert!(check_can_post(&member_db(None), "server-1", "user-1").await.is_ok());
+    }
+
+    fn poster(role: &str) -> Member {
+        Member {
+            server_id: "server-1".to_string(),
+            user_id: "user-1".to_string(),
+            username: "ada".to_string(),
+            role: role.to_string(),
+            joined_at: 0,
+            role_ids: Vec::new(),
+            timeout_until: None,
         }
     }
 
-    tracing::info!(
-        channel_id = %message.channel_id,
-        recipients = num_recipients,
-        "Broadcast complete"
-    );
+    fn read_only_channel(channel_type: &str) -> Channel {
+        Channel {
+            id: "chan-1".to_string(),
+            server_id: "server-1".to_string(),
+            name: "rules".to_string(),
+            channel_type: channel_type.to_string(),
+            created_at: 0,
+            text_in_voice: false,
+            last_message_at: None,
+            position: None,
+            read_only: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn channel_checks_reuse_the_loaded_membership() {
+        use aws_sdk_dynamodb::operation::get_item::GetItemOutput;
+        use aws_smithy_mocks::{mock, mock_client, RuleMode};
+
+        let reads = mock!(aws_sdk_dynamodb::Client::get_item).then_output(|| GetItemOutput::builder().build());
+        let db = mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&reads]);
+
+        let denied = check_channel_poster(&db, &read_only_channel("text"), &poster("member")).await;
+        assert_eq!(denied.unwrap_err().0, 403);
+        assert!(check_channel_poster(&db, &read_only_channel("text"), &poster("admin")).await.is_ok());
+        assert!(check_channel_poster(&db, &read_only_channel(CHANNEL_TYPE_ANNOUNCEMENT), &poster("owner")).await.is_ok());
+        assert_eq!(reads.num_calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn custom_role_with_manage_channels_may_post_in_read_only_channels() {
+        use aws_sdk_dynamodb::operation::query::QueryOutput;
+        use aws_smithy_mocks::{mock, mock_client, RuleMode};
+
+        let role = |id: &str, permission: &str| {
+            HashMap::from([
+                ("id".to_string(), AttributeValue::S(id.to_string())),
+                ("server_id".to_string(), AttributeValue::S("server-1".to_string())),
+                ("name".to_string(), AttributeValue::S(id.to_string())),
+                ("permissions".to_string(), AttributeValue::Ss(vec![permission.to_string()])),
+                ("created_at".to_string(), AttributeValue::N("0".to_string())),
+            ])
+        };
+        let roles = mock!(aws_sdk_dynamodb::Client::query).then_output(move || {
+            QueryOutput::builder()
+                .items(role("channel-mods", roles::MANAGE_CHANNELS))
+                .items(role("helpers", roles::MANAGE_INVITES))
+                .build()
+        });
+        let db = mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&roles]);
+
+        let mut manager = poster("member");
+        manager.role_ids = vec!["channel-mods".to_string()];
+        assert!(check_channel_poster(&db, &read_only_channel("text"), &manager).await.is_ok());
+
+        let mut helper = poster("member");
+        helper.role_ids = vec!["helpers".to_string()];
+        let denied = check_channel_poster(&db, &read_only_channel("text"), &helper).await;
+        assert_eq!(denied.unwrap_err(), (403, "This channel is read-only".to_string()));
+    }
 }
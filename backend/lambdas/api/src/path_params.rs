@@ -0,0 +1,72 @@
+use uuid::Uuid;
+
+use crate::invites;
+
+// Dynamic path segments are checked against the format their ids are
+// generated in before any handler runs, so an obviously bad id is a 400
+// rather than a wasted DynamoDB lookup and a 404. Params are recognised by
+// their name in the router's route patterns.
+
+/// Ids are always written as lowercase hyphenated v4 UUIDs
+fn is_uuid(value: &str) -> bool {
+    value.len() == 36 && Uuid::try_parse(value).is_ok()
+}
+
+/// DM conversations are keyed `<user_id>_<user_id>`
+fn is_conversation_id(value: &str) -> bool {
+    value
+        .split_once('_')
+        .is_some_and(|(a, b)| is_uuid(a) && is_uuid(b))
+}
+
+/// Whether `value` is acceptable for the route param `name` (without the
+/// leading `:`)
+pub fn is_valid(name: &str, value: &str) -> bool {
+    match name {
+        "code" => invites::is_valid_code(value),
+        "conversation_id" => is_conversation_id(value),
+        // A draft is kept per channel or per conversation
+        "scope_id" => is_uuid(value) || is_conversation_id(value),
+        "emoji" => !value.is_empty(),
+        _ if name.ends_with("_id") => is_uuid(value),
+        _ => !value.is_empty(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER_A: &str = "6f1c2a4e-8b3d-4f7a-9c21-0d5e6b7a8c9f";
+    const USER_B: &str = "0a9b8c7d-6e5f-4a3b-8c2d-1e0f9a8b7c6d";
+
+    #[test]
+    fn accepts_well_formed_ids() {
+        assert!(is_valid("server_id", USER_A));
+        assert!(is_valid("conversation_id", &format!("{}_{}", USER_A, USER_B)));
+        assert!(is_valid("scope_id", USER_A));
+        assert!(is_valid("scope_id", &format!("{}_{}", USER_A, USER_B)));
+        assert!(is_valid("code", "AbC23xyz"));
+        assert!(is_valid("emoji", "👍"));
+    }
+
+    #[test]
+    fn rejects_malformed_ids() {
+        assert!(!is_valid("server_id", ""));
+        assert!(!is_valid("server_id", "not-a-uuid"));
+        assert!(!is_valid("server_id", &USER_A.replace('-', "")));
+        assert!(!is_valid("server_id", &format!("{}_{}", USER_A, USER_B)));
+        assert!(!is_valid("conversation_id", USER_A));
+        assert!(!is_valid("conversation_id", &format!("{}_oops", USER_A)));
+        assert!(!is_valid("scope_id", "general"));
+    }
+
+    #[test]
+    fn rejects_invite_codes_outside_the_charset() {
+        assert!(!is_valid("code", ""));
+        assert!(!is_valid("code", "AbC0xyz1"));
+        assert!(!is_valid("code", "abc-def"));
+        assert!(!is_valid("code", &"a".repeat(17)));
+        assert!(!is_valid("emoji", ""));
+    }
+}
@@ -0,0 +1,104 @@
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+
+use crate::clock;
+use crate::db;
+use crate::servers;
+
+// Presence rows are written by the WebSocket lambda: one per user, counting
+// open connections and stamping `last_seen` on connect, disconnect and ping.
+// This side only reads them.
+
+#[derive(Debug, Serialize)]
+pub struct MemberPresence {
+    pub user_id: String,
+    pub username: String,
+    pub online: bool,
+    /// Unix seconds of the last connect, disconnect or ping; absent for
+    /// members who never connected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<i64>,
+}
+
+struct PresenceRow {
+    connections: i64,
+    last_seen: i64,
+}
+
+fn get_table(name: &str) -> String {
+    env::var(name).unwrap_or_else(|_| {
+        format!(
+            "agorusta-{}-dev",
+            name.to_lowercase().replace("_table", "s")
+        )
+    })
+}
+
+/// Same setting as the WebSocket lambda's connection idle TTL. An open
+/// connection pings within this window, so a count left above zero with no
+/// activity for longer is a $disconnect that never arrived.
+fn connection_ttl_seconds() -> i64 {
+    env::var("CONNECTION_IDLE_TTL_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(86400)
+}
+
+async fn batch_get_presence(
+    db: &DynamoClient,
+    user_ids: &[String],
+) -> Result<HashMap<String, PresenceRow>, (u16, String)> {
+    let keys = user_ids
+        .iter()
+        .map(|id| HashMap::from([("user_id".to_string(), AttributeValue::S(id.clone()))]))
+        .collect();
+    let items = db::batch_get(db, &get_table("PRESENCE_TABLE"), keys, None, "presence").await?;
+
+    let mut rows = HashMap::with_capacity(items.len());
+    for item in items {
+        let number = |key: &str| item.get(key).and_then(|v| v.as_n().ok()).and_then(|n| n.parse().ok());
+        let Some(user_id) = item.get("user_id").and_then(|v| v.as_s().ok()) else {
+            continue;
+        };
+        rows.insert(
+            user_id.clone(),
+            PresenceRow {
+                connections: number("connections").unwrap_or(0),
+                last_seen: number("last_seen").unwrap_or(0),
+            },
+        );
+    }
+
+    Ok(rows)
+}
+
+/// Every member of the server with whether they're online now
+pub async fn list_server_presence(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+) -> Result<Vec<MemberPresence>, (u16, String)> {
+    let members = servers::list_members(db, server_id, user_id, false).await?;
+    let user_ids: Vec<String> = members.iter().map(|m| m.user_id.clone()).collect();
+    let rows = batch_get_presence(db, &user_ids).await?;
+
+    let stale_before = clock::now_secs() - connection_ttl_seconds();
+    let presence = members
+        .into_iter()
+        .map(|m| {
+            let row = rows.get(&m.user_id);
+            MemberPresence {
+                online: row.is_some_and(|r| r.connections > 0 && r.last_seen >= stale_before),
+                last_seen: row.map(|r| r.last_seen).filter(|t| *t > 0),
+                user_id: m.user_id,
+                username: m.username,
+            }
+        })
+        .collect();
+
+    Ok(presence)
+}
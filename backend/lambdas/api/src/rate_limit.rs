@@ -0,0 +1,95 @@
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use std::env;
+
+use crate::clock;
+
+/// Counter state for one bucket's current window
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitState {
+    pub limit: u32,
+    pub remaining: u32,
+    /// Unix seconds when the current window ends
+    pub reset_at: i64,
+    pub exceeded: bool,
+}
+
+impl RateLimitState {
+    pub fn retry_after(&self) -> i64 {
+        (self.reset_at - clock::now_secs()).max(0)
+    }
+
+    pub fn error(&self) -> (u16, String) {
+        (
+            429,
+            format!("Too many requests, try again in {} seconds", self.retry_after()),
+        )
+    }
+}
+
+fn get_table(name: &str) -> String {
+    env::var(name).unwrap_or_else(|_| {
+        format!(
+            "agorusta-{}-dev",
+            name.to_lowercase().replace("_table", "s")
+        )
+    })
+}
+
+/// Record a hit against `bucket` (e.g. `search:<user_id>`) using a fixed
+/// window counter and return the bucket's state afterwards. The hit is
+/// over the limit once more than `limit` hits land in the same
+/// `window_secs` window. Counters expire via TTL.
+///
+/// If DynamoDB is unavailable the limiter fails open: a broken counter
+/// shouldn't take the endpoint down with it.
+pub async fn hit(db: &DynamoClient, bucket: &str, limit: u32, window_secs: i64) -> RateLimitState {
+    let now = clock::now_secs();
+    let window_start = now - now.rem_euclid(window_secs);
+    let reset_at = window_start + window_secs;
+
+    let result = db
+        .update_item()
+        .table_name(get_table("RATE_LIMITS_TABLE"))
+        .key("key", AttributeValue::S(format!("{}#{}", bucket, window_start)))
+        .update_expression("ADD #c :one SET #ttl = if_not_exists(#ttl, :ttl)")
+        .expression_attribute_names("#c", "count")
+        .expression_attribute_names("#ttl", "ttl")
+        .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+        .expression_attribute_values(":ttl", AttributeValue::N(reset_at.to_string()))
+        .return_values(ReturnValue::UpdatedNew)
+        .send()
+        .await;
+
+    let count: u32 = match result {
+        Ok(output) => output
+            .attributes()
+            .and_then(|attrs| attrs.get("count")?.as_n().ok()?.parse().ok())
+            .unwrap_or(1),
+        Err(e) => {
+            tracing::warn!(bucket = %bucket, error = %e, "Rate limit check failed, allowing request");
+            0
+        }
+    };
+
+    RateLimitState {
+        limit,
+        remaining: limit.saturating_sub(count),
+        reset_at,
+        exceeded: count > limit,
+    }
+}
+
+/// Like `hit`, but returns 429 when the bucket is over its limit
+pub async fn check(
+    db: &DynamoClient,
+    bucket: &str,
+    limit: u32,
+    window_secs: i64,
+) -> Result<RateLimitState, (u16, String)> {
+    let state = hit(db, bucket, limit, window_secs).await;
+    if state.exceeded {
+        return Err(state.error());
+    }
+    Ok(state)
+}
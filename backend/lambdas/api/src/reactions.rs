@@ -0,0 +1,585 @@
+use aws_sdk_apigatewaymanagement::Client as ApiGwClient;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+
+use crate::auth;
+use crate::broadcast;
+use crate::clock;
+use crate::cursor;
+use crate::dms::{find_dm_message, verify_participant};
+use crate::messages::{check_membership, find_message, verify_channel, Message};
+use crate::servers::list_channels;
+
+// ============ Types ============
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReactionSummary {
+    pub message_id: String,
+    pub reactions: Vec<ReactionCount>,
+}
+
+/// Result of a toggle: whether the caller now has the reaction, and the
+/// emoji's new count
+#[derive(Debug, Serialize)]
+pub struct ToggleReactionResponse {
+    pub reacted: bool,
+    pub count: usize,
+    pub summary: ReactionSummary,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TopMessage {
+    pub message: Message,
+    pub reaction_count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TopMessagesResponse {
+    pub messages: Vec<TopMessage>,
+    /// The scan cap was hit, so counts may be incomplete
+    pub approximate: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReactionStats {
+    pub server_id: String,
+    pub emoji: Vec<ReactionCount>,
+    /// The scan cap was hit, so counts may be incomplete
+    pub approximate: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Reactor {
+    pub user_id: String,
+    /// None if the user record is missing
+    pub username: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReactorsResponse {
+    pub message_id: String,
+    pub emoji: String,
+    pub users: Vec<Reactor>,
+    /// Pass as `cursor` to get the next page
+    pub next_cursor: Option<String>,
+}
+
+/// Reaction rows read per leaderboard/stats request
+const MAX_AGGREGATED_REACTIONS: usize = 5_000;
+const MAX_TOP_MESSAGES: usize = 10;
+/// Rounds of put-then-delete before a toggle gives up under contention
+const MAX_TOGGLE_ATTEMPTS: usize = 3;
+
+/// Where a reacted-to message lives. Channel and DM messages share the
+/// reactions table, keyed by message id.
+pub enum ReactionScope<'a> {
+    Channel {
+        server_id: &'a str,
+        channel_id: &'a str,
+    },
+    Dm {
+        conversation_id: &'a str,
+    },
+}
+
+impl ReactionScope<'_> {
+    fn scope_id(&self) -> &str {
+        match self {
+            ReactionScope::Channel { channel_id, .. } => channel_id,
+            ReactionScope::Dm { conversation_id } => conversation_id,
+        }
+    }
+
+    fn server_id(&self) -> Option<&str> {
+        match self {
+            ReactionScope::Channel { server_id, .. } => Some(server_id),
+            ReactionScope::Dm { .. } => None,
+        }
+    }
+}
+
+// ============ Helpers ============
+
+fn get_table(name: &str) -> String {
+    env::var(name).unwrap_or_else(|_| {
+        format!(
+            "agorusta-{}-dev",
+            name.to_lowercase().replace("_table", "s")
+        )
+    })
+}
+
+fn reaction_key(emoji: &str, user_id: &str) -> String {
+    format!("{}#{}", emoji, user_id)
+}
+
+fn validate_emoji(emoji: &str) -> Result<(), (u16, String)> {
+    if emoji.is_empty() || emoji.chars().count() > 32 {
+        return Err((400, "Emoji must be 1-32 characters".to_string()));
+    }
+    if emoji.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err((400, "Emoji cannot contain whitespace".to_string()));
+    }
+    // `#` separates the emoji from the user id in the sort key, so an emoji
+    // containing it would match another emoji's begins_with prefix
+    if emoji.contains('#') {
+        return Err((400, "Emoji cannot contain '#'".to_string()));
+    }
+    Ok(())
+}
+
+/// Check the caller can see the message and that it exists in the scope
+async fn authorize(
+    db: &DynamoClient,
+    scope: &ReactionScope<'_>,
+    message_id: &str,
+    user_id: &str,
+) -> Result<(), (u16, String)> {
+    let found = match scope {
+        ReactionScope::Channel {
+            server_id,
+            channel_id,
+        } => {
+            check_membership(db, server_id, user_id).await?;
+            verify_channel(db, server_id, channel_id).await?;
+            find_message(db, channel_id, message_id).await?.is_some()
+        }
+        ReactionScope::Dm { conversation_id } => {
+            verify_participant(db, conversation_id, user_id).await?;
+            find_dm_message(db, conversation_id, message_id).await?.is_some()
+        }
+    };
+
+    if !found {
+        return Err((404, "Message not found".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Count reactions on a message, grouped by emoji
+pub async fn summarize(db: &DynamoClient, message_id: &str) -> Result<ReactionSummary, (u16, String)> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    let mut start_key: Option<HashMap<String, AttributeValue>> = None;
+
+    loop {
+        let result = db
+            .query()
+            .table_name(get_table("REACTIONS_TABLE"))
+            .key_condition_expression("message_id = :mid")
+            .expression_attribute_values(":mid", AttributeValue::S(message_id.to_string()))
+            .projection_expression("emoji")
+            .set_exclusive_start_key(start_key.take())
+            .send()
+            .await
+            .map_err(|e| (500, format!("Failed to load reactions: {}", e)))?;
+
+        for item in result.items() {
+            if let Some(emoji) = item.get("emoji").and_then(|v| v.as_s().ok()) {
+                *counts.entry(emoji.clone()).or_insert(0) += 1;
+            }
+        }
+
+        start_key = result.last_evaluated_key().cloned();
+        if start_key.is_none() {
+            break;
+        }
+    }
+
+    Ok(ReactionSummary {
+        message_id: message_id.to_string(),
+        reactions: counts
+            .into_iter()
+            .map(|(emoji, count)| ReactionCount { emoji, count })
+            .collect(),
+    })
+}
+
+/// Users who reacted to a message with `emoji`, ordered by user id. `cursor`
+/// is the `next_cursor` of the previous page.
+pub async fn list_message_reactions(
+    db: &DynamoClient,
+    scope: &ReactionScope<'_>,
+    message_id: &str,
+    emoji: &str,
+    user_id: &str,
+    limit: usize,
+    cursor: Option<&str>,
+) -> Result<ReactorsResponse, (u16, String)> {
+    validate_emoji(emoji)?;
+    authorize(db, scope, message_id, user_id).await?;
+
+    let limit = limit.clamp(1, 100);
+    let cursor_scope = format!("reactions:{}:{}", message_id, emoji);
+    let start_key = cursor
+        .map(|c| cursor::decode_cursor(&cursor_scope, c))
+        .transpose()?
+        .map(|last_user_id| {
+            HashMap::from([
+                ("message_id".to_string(), AttributeValue::S(message_id.to_string())),
+                (
+                    "reaction_key".to_string(),
+                    AttributeValue::S(reaction_key(emoji, &last_user_id)),
+                ),
+            ])
+        });
+
+    let result = db
+        .query()
+        .table_name(get_table("REACTIONS_TABLE"))
+        .key_condition_expression("message_id = :mid AND begins_with(reaction_key, :prefix)")
+        .expression_attribute_values(":mid", AttributeValue::S(message_id.to_string()))
+        .expression_attribute_values(":prefix", AttributeValue::S(format!("{}#", emoji)))
+        .projection_expression("user_id")
+        .set_exclusive_start_key(start_key)
+        .limit(limit as i32)
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to load reactions: {}", e)))?;
+
+    let user_ids: Vec<String> = result
+        .items()
+        .iter()
+        .filter_map(|item| item.get("user_id")?.as_s().ok().cloned())
+        .collect();
+    let usernames = auth::batch_get_usernames(db, &user_ids).await?;
+
+    let next_cursor = result
+        .last_evaluated_key()
+        .and(user_ids.last())
+        .map(|last_user_id| cursor::encode_cursor(&cursor_scope, last_user_id))
+        .transpose()?;
+
+    Ok(ReactorsResponse {
+        message_id: message_id.to_string(),
+        emoji: emoji.to_string(),
+        users: user_ids
+            .into_iter()
+            .map(|id| Reactor {
+                username: usernames.get(&id).cloned(),
+                user_id: id,
+            })
+            .collect(),
+        next_cursor,
+    })
+}
+
+/// Read (message_id, emoji) pairs for a channel or conversation from the
+/// scope index, stopping once `budget` rows have been read. Returns the rows
+/// and whether the budget ran out first.
+async fn scan_scope(
+    db: &DynamoClient,
+    scope_id: &str,
+    budget: usize,
+) -> Result<(Vec<(String, String)>, bool), (u16, String)> {
+    let mut rows = Vec::new();
+    let mut start_key: Option<HashMap<String, AttributeValue>> = None;
+
+    loop {
+        let result = db
+            .query()
+            .table_name(get_table("REACTIONS_TABLE"))
+            .index_name("scope-index")
+            .key_condition_expression("scope_id = :sid")
+            .expression_attribute_values(":sid", AttributeValue::S(scope_id.to_string()))
+            .limit((budget - rows.len()).min(1000) as i32)
+            .set_exclusive_start_key(start_key.take())
+            .send()
+            .await
+            .map_err(|e| (500, format!("Failed to load reactions: {}", e)))?;
+
+        rows.extend(result.items().iter().filter_map(|item| {
+            Some((
+                item.get("message_id")?.as_s().ok()?.clone(),
+                item.get("emoji")?.as_s().ok()?.clone(),
+            ))
+        }));
+
+        start_key = result.last_evaluated_key().cloned();
+        if start_key.is_none() {
+            return Ok((rows, false));
+        }
+        if rows.len() >= budget {
+            return Ok((rows, true));
+        }
+    }
+}
+
+/// Channel messages ranked by total reactions, most reacted first
+pub async fn top_messages(
+    db: &DynamoClient,
+    server_id: &str,
+    channel_id: &str,
+    user_id: &str,
+) -> Result<TopMessagesResponse, (u16, String)> {
+    check_membership(db, server_id, user_id).await?;
+    verify_channel(db, server_id, channel_id).await?;
+
+    let (rows, approximate) = scan_scope(db, channel_id, MAX_AGGREGATED_REACTIONS).await?;
+
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for (message_id, _) in rows {
+        *counts.entry(message_id).or_insert(0) += 1;
+    }
+    let mut ranked: Vec<(String, usize)> = counts.into_iter().collect();
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    let mut messages = Vec::new();
+    for (message_id, reaction_count) in ranked {
+        if messages.len() >= MAX_TOP_MESSAGES {
+            break;
+        }
+        // Reactions can outlive a deleted message; skip those
+        if let Some(message) = find_message(db, channel_id, &message_id).await? {
+            messages.push(TopMessage {
+                message,
+                reaction_count,
+            });
+        }
+    }
+
+    Ok(TopMessagesResponse {
+        messages,
+        approximate,
+    })
+}
+
+/// Per-emoji usage across every channel in the server, most used first
+pub async fn server_stats(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+) -> Result<ReactionStats, (u16, String)> {
+    check_membership(db, server_id, user_id).await?;
+
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut read = 0;
+    let mut approximate = false;
+
+    for channel in list_channels(db, server_id).await? {
+        if read >= MAX_AGGREGATED_REACTIONS {
+            approximate = true;
+            break;
+        }
+        let (rows, capped) = scan_scope(db, &channel.id, MAX_AGGREGATED_REACTIONS - read).await?;
+        read += rows.len();
+        approximate |= capped;
+        for (_, emoji) in rows {
+            *counts.entry(emoji).or_insert(0) += 1;
+        }
+    }
+
+    let mut emoji: Vec<ReactionCount> = counts
+        .into_iter()
+        .map(|(emoji, count)| ReactionCount { emoji, count })
+        .collect();
+    emoji.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.emoji.cmp(&b.emoji)));
+
+    Ok(ReactionStats {
+        server_id: server_id.to_string(),
+        emoji,
+        approximate,
+    })
+}
+
+// ============ Reactions ============
+
+/// Insert the caller's reaction row. Returns false if it was already there.
+async fn put_reaction(
+    db: &DynamoClient,
+    scope: &ReactionScope<'_>,
+    message_id: &str,
+    user_id: &str,
+    emoji: &str,
+) -> Result<bool, (u16, String)> {
+    let result = db
+        .put_item()
+        .table_name(get_table("REACTIONS_TABLE"))
+        .item("message_id", AttributeValue::S(message_id.to_string()))
+        .item("reaction_key", AttributeValue::S(reaction_key(emoji, user_id)))
+        .item("emoji", AttributeValue::S(emoji.to_string()))
+        .item("user_id", AttributeValue::S(user_id.to_string()))
+        .item("scope_id", AttributeValue::S(scope.scope_id().to_string()))
+        .item(
+            "created_at",
+            AttributeValue::N(clock::now_millis().to_string()),
+        )
+        .condition_expression("attribute_not_exists(reaction_key)")
+        .send()
+        .await;
+
+    match result {
+        Ok(_) => Ok(true),
+        Err(e) => {
+            let already_reacted = e
+                .as_service_error()
+                .map(|se| se.is_conditional_check_failed_exception())
+                .unwrap_or(false);
+            if already_reacted {
+                Ok(false)
+            } else {
+                Err((500, format!("Failed to add reaction: {}", e)))
+            }
+        }
+    }
+}
+
+/// Delete the caller's reaction row only if it exists. Returns false if it
+/// was already gone.
+async fn delete_reaction(
+    db: &DynamoClient,
+    message_id: &str,
+    user_id: &str,
+    emoji: &str,
+) -> Result<bool, (u16, String)> {
+    let result = db
+        .delete_item()
+        .table_name(get_table("REACTIONS_TABLE"))
+        .key("message_id", AttributeValue::S(message_id.to_string()))
+        .key("reaction_key", AttributeValue::S(reaction_key(emoji, user_id)))
+        .condition_expression("attribute_exists(reaction_key)")
+        .send()
+        .await;
+
+    match result {
+        Ok(_) => Ok(true),
+        Err(e) => {
+            let missing = e
+                .as_service_error()
+                .map(|se| se.is_conditional_check_failed_exception())
+                .unwrap_or(false);
+            if missing {
+                Ok(false)
+            } else {
+                Err((500, format!("Failed to remove reaction: {}", e)))
+            }
+        }
+    }
+}
+
+/// Add the caller's reaction. Reacting twice with the same emoji is a no-op.
+pub async fn add_reaction(
+    db: &DynamoClient,
+    scope: &ReactionScope<'_>,
+    message_id: &str,
+    user_id: &str,
+    emoji: &str,
+) -> Result<ReactionSummary, (u16, String)> {
+    validate_emoji(emoji)?;
+    authorize(db, scope, message_id, user_id).await?;
+
+    put_reaction(db, scope, message_id, user_id, emoji).await?;
+
+    summarize(db, message_id).await
+}
+
+/// Remove the caller's reaction. Removing one that doesn't exist is a no-op.
+pub async fn remove_reaction(
+    db: &DynamoClient,
+    scope: &ReactionScope<'_>,
+    message_id: &str,
+    user_id: &str,
+    emoji: &str,
+) -> Result<ReactionSummary, (u16, String)> {
+    validate_emoji(emoji)?;
+    authorize(db, scope, message_id, user_id).await?;
+
+    delete_reaction(db, message_id, user_id, emoji).await?;
+
+    summarize(db, message_id).await
+}
+
+/// Add the caller's reaction if absent, otherwise remove it. Both writes are
+/// conditional, so a concurrent add/remove just sends the toggle round again.
+pub async fn toggle_reaction(
+    db: &DynamoClient,
+    scope: &ReactionScope<'_>,
+    message_id: &str,
+    user_id: &str,
+    emoji: &str,
+) -> Result<ToggleReactionResponse, (u16, String)> {
+    validate_emoji(emoji)?;
+    authorize(db, scope, message_id, user_id).await?;
+
+    let mut reacted = None;
+    for _ in 0..MAX_TOGGLE_ATTEMPTS {
+        if put_reaction(db, scope, message_id, user_id, emoji).await? {
+            reacted = Some(true);
+            break;
+        }
+        if delete_reaction(db, message_id, user_id, emoji).await? {
+            reacted = Some(false);
+            break;
+        }
+    }
+    let reacted = reacted.ok_or((409, "Reaction changed concurrently; try again".to_string()))?;
+
+    let summary = summarize(db, message_id).await?;
+    let count = summary
+        .reactions
+        .iter()
+        .find(|r| r.emoji == emoji)
+        .map_or(0, |r| r.count);
+
+    Ok(ToggleReactionResponse { reacted, count, summary })
+}
+
+/// Push updated reaction counts to everyone subscribed to the channel or
+/// conversation
+pub async fn broadcast_reaction_update(
+    db: &DynamoClient,
+    apigw: &ApiGwClient,
+    scope: &ReactionScope<'_>,
+    summary: &ReactionSummary,
+) {
+    let payload = match broadcast::build_payload(
+        "reaction_update",
+        None,
+        scope.server_id(),
+        Some(scope.scope_id()),
+        summary,
+    ) {
+        Ok(b) => b,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to serialize reaction update");
+            return;
+        }
+    };
+
+    broadcast::send_to_subscribers(db, apigw, scope.scope_id(), &payload).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_unicode_and_custom_emoji() {
+        assert!(validate_emoji("👍").is_ok());
+        assert!(validate_emoji("👨‍👩‍👧").is_ok());
+        assert!(validate_emoji(":party_parrot:").is_ok());
+    }
+
+    #[test]
+    fn rejects_emoji_containing_the_key_separator() {
+        assert_eq!(validate_emoji("a#b").unwrap_err().0, 400);
+        assert_eq!(validate_emoji("#").unwrap_err().0, 400);
+    }
+
+    #[test]
+    fn rejects_empty_long_and_whitespace_emoji() {
+        assert!(validate_emoji("").is_err());
+        assert!(validate_emoji(&"a".repeat(33)).is_err());
+        assert!(validate_emoji("a b").is_err());
+        assert!(validate_emoji("a\u{0}").is_err());
+    }
+}
@@ -0,0 +1,469 @@
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use uuid::Uuid;
+
+use crate::clock;
+use crate::limits;
+use crate::servers::{load_membership, Member};
+
+// ============ Types ============
+
+/// Permissions a custom role can grant. Owners and admins implicitly hold all
+/// of them.
+pub const MANAGE_SERVER: &str = "manage_server";
+pub const MANAGE_CHANNELS: &str = "manage_channels";
+pub const MANAGE_INVITES: &str = "manage_invites";
+pub const MANAGE_MESSAGES: &str = "manage_messages";
+
+const ALL_PERMISSIONS: &[&str] = &[MANAGE_SERVER, MANAGE_CHANNELS, MANAGE_INVITES, MANAGE_MESSAGES];
+
+const MAX_ROLES_PER_SERVER: usize = 50;
+/// Entries accepted in a permissions list; duplicates are dropped afterwards
+const MAX_PERMISSIONS_LISTED: usize = 32;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Role {
+    pub id: String,
+    pub server_id: String,
+    pub name: String,
+    pub color: Option<String>,
+    pub permissions: Vec<String>,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreateRoleRequest {
+    pub name: String,
+    pub color: Option<String>,
+    #[serde(default)]
+    pub permissions: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateRoleRequest {
+    pub name: Option<String>,
+    pub color: Option<String>,
+    pub permissions: Option<Vec<String>>,
+}
+
+// ============ Helpers ============
+
+fn get_table(name: &str) -> String {
+    env::var(name).unwrap_or_else(|_| {
+        format!(
+            "agorusta-{}-dev",
+            name.to_lowercase().replace("_table", "s")
+        )
+    })
+}
+
+/// `#rrggbb` hex color
+pub fn is_hex_color(value: &str) -> bool {
+    value.len() == 7
+        && value.starts_with('#')
+        && value[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn validate_name(name: &str) -> Result<String, (u16, String)> {
+    let name = name.trim();
+    if name.is_empty() || name.len() > 32 {
+        return Err((400, "Role name must be 1-32 characters".to_string()));
+    }
+    if name == "owner" || name == "admin" || name == "member" {
+        return Err((400, "Role name is reserved".to_string()));
+    }
+    Ok(name.to_string())
+}
+
+fn validate_color(color: &str) -> Result<(), (u16, String)> {
+    if !is_hex_color(color) {
+        return Err((400, "Color must be a hex color like #5865f2".to_string()));
+    }
+    Ok(())
+}
+
+fn validate_permissions(permissions: &[String]) -> Result<Vec<String>, (u16, String)> {
+    limits::bounded_vec("permissions", permissions, MAX_PERMISSIONS_LISTED)?;
+
+    let mut perms: Vec<String> = Vec::new();
+    for p in permissions {
+        if !ALL_PERMISSIONS.contains(&p.as_str()) {
+            return Err((400, format!("Unknown permission: {}", p)));
+        }
+        if !perms.contains(p) {
+            perms.push(p.clone());
+        }
+    }
+    Ok(perms)
+}
+
+async fn require_owner(db: &DynamoClient, server_id: &str, user_id: &str) -> Result<(), (u16, String)> {
+    let member = load_membership(db, server_id, user_id).await?;
+
+    if member.role != "owner" {
+        return Err((403, "Only the server owner can manage roles".to_string()));
+    }
+    Ok(())
+}
+
+async fn get_role(db: &DynamoClient, server_id: &str, role_id: &str) -> Result<Role, (u16, String)> {
+    let result = db
+        .get_item()
+        .table_name(get_table("ROLES_TABLE"))
+        .key("server_id", AttributeValue::S(server_id.to_string()))
+        .key("id", AttributeValue::S(role_id.to_string()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+    result
+        .item()
+        .and_then(parse_role)
+        .ok_or((404, "Role not found".to_string()))
+}
+
+async fn query_roles(db: &DynamoClient, server_id: &str) -> Result<Vec<Role>, (u16, String)> {
+    let result = db
+        .query()
+        .table_name(get_table("ROLES_TABLE"))
+        .key_condition_expression("server_id = :sid")
+        .expression_attribute_values(":sid", AttributeValue::S(server_id.to_string()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to list roles: {}", e)))?;
+
+    Ok(result.items().iter().filter_map(parse_role).collect())
+}
+
+fn parse_role(item: &HashMap<String, AttributeValue>) -> Option<Role> {
+    Some(Role {
+        id: item.get("id")?.as_s().ok()?.clone(),
+        server_id: item.get("server_id")?.as_s().ok()?.clone(),
+        name: item.get("name")?.as_s().ok()?.clone(),
+        color: item.get("color").and_then(|v| v.as_s().ok().cloned()),
+        permissions: item
+            .get("permissions")
+            .and_then(|v| v.as_ss().ok().cloned())
+            .unwrap_or_default(),
+        created_at: item.get("created_at")?.as_n().ok()?.parse().ok()?,
+    })
+}
+
+/// Role ids assigned to a member row
+pub fn parse_role_ids(item: &HashMap<String, AttributeValue>) -> Vec<String> {
+    item.get("role_ids")
+        .and_then(|v| v.as_ss().ok().cloned())
+        .unwrap_or_default()
+}
+
+// ============ Permissions ============
+
+/// Check whether a member holds a permission, either through the owner/admin
+/// base role or the union of their custom roles. Non-members get a 403.
+pub async fn has_permission(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+    permission: &str,
+) -> Result<bool, (u16, String)> {
+    let member = load_membership(db, server_id, user_id).await?;
+    member_has_permission(db, &member, permission).await
+}
+
+/// As `has_permission`, for a membership the caller has already loaded
+pub async fn member_has_permission(
+    db: &DynamoClient,
+    member: &Member,
+    permission: &str,
+) -> Result<bool, (u16, String)> {
+    if member.role == "owner" || member.role == "admin" {
+        return Ok(true);
+    }
+
+    let role_ids: HashSet<&String> = member.role_ids.iter().collect();
+    if role_ids.is_empty() {
+        return Ok(false);
+    }
+
+    let granted = query_roles(db, &member.server_id)
+        .await?
+        .iter()
+        .filter(|r| role_ids.contains(&r.id))
+        .any(|r| r.permissions.iter().any(|p| p == permission));
+
+    Ok(granted)
+}
+
+// ============ Role CRUD ============
+
+pub async fn list_roles(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+) -> Result<Vec<Role>, (u16, String)> {
+    load_membership(db, server_id, user_id).await?;
+
+    let mut roles = query_roles(db, server_id).await?;
+    roles.sort_by_key(|r| r.created_at);
+    Ok(roles)
+}
+
+pub async fn create_role(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+    body: &str,
+) -> Result<Role, (u16, String)> {
+    require_owner(db, server_id, user_id).await?;
+
+    let req: CreateRoleRequest = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request: {}", e)))?;
+
+    let name = validate_name(&req.name)?;
+    if let Some(color) = &req.color {
+        validate_color(color)?;
+    }
+    let permissions = validate_permissions(&req.permissions)?;
+
+    let existing = query_roles(db, server_id).await?;
+    if existing.len() >= MAX_ROLES_PER_SERVER {
+        return Err((409, format!("A server can have at most {} roles", MAX_ROLES_PER_SERVER)));
+    }
+    if existing.iter().any(|r| r.name.eq_ignore_ascii_case(&name)) {
+        return Err((409, "A role with this name already exists".to_string()));
+    }
+
+    let role = Role {
+        id: Uuid::new_v4().to_string(),
+        server_id: server_id.to_string(),
+        name,
+        color: req.color,
+        permissions,
+        created_at: clock::now_secs(),
+    };
+
+    let mut put = db
+        .put_item()
+        .table_name(get_table("ROLES_TABLE"))
+        .item("server_id", AttributeValue::S(role.server_id.clone()))
+        .item("id", AttributeValue::S(role.id.clone()))
+        .item("name", AttributeValue::S(role.name.clone()))
+        .item("created_at", AttributeValue::N(role.created_at.to_string()));
+
+    if let Some(color) = &role.color {
+        put = put.item("color", AttributeValue::S(color.clone()));
+    }
+    // DynamoDB rejects empty sets, so an empty permission list is just omitted
+    if !role.permissions.is_empty() {
+        put = put.item("permissions", AttributeValue::Ss(role.permissions.clone()));
+    }
+
+    put.send()
+        .await
+        .map_err(|e| (500, format!("Failed to create role: {}", e)))?;
+
+    Ok(role)
+}
+
+pub async fn update_role(
+    db: &DynamoClient,
+    server_id: &str,
+    role_id: &str,
+    user_id: &str,
+    body: &str,
+) -> Result<Role, (u16, String)> {
+    require_owner(db, server_id, user_id).await?;
+
+    let req: UpdateRoleRequest = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request: {}", e)))?;
+
+    let mut role = get_role(db, server_id, role_id).await?;
+
+    if let Some(name) = &req.name {
+        role.name = validate_name(name)?;
+    }
+    if let Some(color) = req.color {
+        validate_color(&color)?;
+        role.color = Some(color);
+    }
+    if let Some(permissions) = &req.permissions {
+        role.permissions = validate_permissions(permissions)?;
+    }
+
+    let mut update = db
+        .update_item()
+        .table_name(get_table("ROLES_TABLE"))
+        .key("server_id", AttributeValue::S(server_id.to_string()))
+        .key("id", AttributeValue::S(role_id.to_string()))
+        .expression_attribute_names("#n", "name")
+        .expression_attribute_values(":name", AttributeValue::S(role.name.clone()));
+
+    let mut set = vec!["#n = :name"];
+    if let Some(color) = &role.color {
+        set.push("color = :color");
+        update = update.expression_attribute_values(":color", AttributeValue::S(color.clone()));
+    }
+
+    let expression = if role.permissions.is_empty() {
+        format!("SET {} REMOVE permissions", set.join(", "))
+    } else {
+        set.push("permissions = :perms");
+        update = update.expression_attribute_values(":perms", AttributeValue::Ss(role.permissions.clone()));
+        format!("SET {}", set.join(", "))
+    };
+
+    update
+        .update_expression(expression)
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to update role: {}", e)))?;
+
+    Ok(role)
+}
+
+pub async fn delete_role(
+    db: &DynamoClient,
+    server_id: &str,
+    role_id: &str,
+    user_id: &str,
+) -> Result<(), (u16, String)> {
+    require_owner(db, server_id, user_id).await?;
+    get_role(db, server_id, role_id).await?;
+
+    db.delete_item()
+        .table_name(get_table("ROLES_TABLE"))
+        .key("server_id", AttributeValue::S(server_id.to_string()))
+        .key("id", AttributeValue::S(role_id.to_string()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to delete role: {}", e)))?;
+
+    // Best-effort: strip the role from anyone who had it
+    let members = db
+        .query()
+        .table_name(get_table("MEMBERS_TABLE"))
+        .key_condition_expression("server_id = :sid")
+        .filter_expression("contains(role_ids, :rid)")
+        .expression_attribute_values(":sid", AttributeValue::S(server_id.to_string()))
+        .expression_attribute_values(":rid", AttributeValue::S(role_id.to_string()))
+        .send()
+        .await;
+
+    if let Ok(result) = members {
+        for item in result.items() {
+            if let Some(member_id) = item.get("user_id").and_then(|v| v.as_s().ok()) {
+                let _ = unassign(db, server_id, member_id, role_id).await;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// ============ Assignment ============
+
+pub async fn assign_role(
+    db: &DynamoClient,
+    server_id: &str,
+    target_user_id: &str,
+    role_id: &str,
+    user_id: &str,
+) -> Result<Vec<String>, (u16, String)> {
+    require_owner(db, server_id, user_id).await?;
+    get_role(db, server_id, role_id).await?;
+
+    let result = db
+        .update_item()
+        .table_name(get_table("MEMBERS_TABLE"))
+        .key("server_id", AttributeValue::S(server_id.to_string()))
+        .key("user_id", AttributeValue::S(target_user_id.to_string()))
+        .update_expression("ADD role_ids :rid")
+        .condition_expression("attribute_exists(user_id)")
+        .expression_attribute_values(":rid", AttributeValue::Ss(vec![role_id.to_string()]))
+        .return_values(aws_sdk_dynamodb::types::ReturnValue::AllNew)
+        .send()
+        .await
+        .map_err(|e| {
+            let missing = e
+                .as_service_error()
+                .map(|se| se.is_conditional_check_failed_exception())
+                .unwrap_or(false);
+            if missing {
+                (404, "Member not found".to_string())
+            } else {
+                (500, format!("Failed to assign role: {}", e))
+            }
+        })?;
+
+    Ok(result.attributes().map(parse_role_ids).unwrap_or_default())
+}
+
+pub async fn unassign_role(
+    db: &DynamoClient,
+    server_id: &str,
+    target_user_id: &str,
+    role_id: &str,
+    user_id: &str,
+) -> Result<Vec<String>, (u16, String)> {
+    require_owner(db, server_id, user_id).await?;
+    unassign(db, server_id, target_user_id, role_id).await
+}
+
+async fn unassign(
+    db: &DynamoClient,
+    server_id: &str,
+    target_user_id: &str,
+    role_id: &str,
+) -> Result<Vec<String>, (u16, String)> {
+    let result = db
+        .update_item()
+        .table_name(get_table("MEMBERS_TABLE"))
+        .key("server_id", AttributeValue::S(server_id.to_string()))
+        .key("user_id", AttributeValue::S(target_user_id.to_string()))
+        .update_expression("DELETE role_ids :rid")
+        .condition_expression("attribute_exists(user_id)")
+        .expression_attribute_values(":rid", AttributeValue::Ss(vec![role_id.to_string()]))
+        .return_values(aws_sdk_dynamodb::types::ReturnValue::AllNew)
+        .send()
+        .await
+        .map_err(|e| {
+            let missing = e
+                .as_service_error()
+                .map(|se| se.is_conditional_check_failed_exception())
+                .unwrap_or(false);
+            if missing {
+                (404, "Member not found".to_string())
+            } else {
+                (500, format!("Failed to remove role: {}", e))
+            }
+        })?;
+
+    Ok(result.attributes().map(parse_role_ids).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_six_digit_hex_colors() {
+        assert!(is_hex_color("#5865f2"));
+        assert!(is_hex_color("#ABCDEF"));
+        assert!(is_hex_color("#000000"));
+    }
+
+    #[test]
+    fn rejects_other_color_forms() {
+        assert!(!is_hex_color("5865f2"));
+        assert!(!is_hex_color("#fff"));
+        assert!(!is_hex_color("#5865f2ff"));
+        assert!(!is_hex_color("#5865g2"));
+        assert!(!is_hex_color("#ééé"));
+        assert!(!is_hex_color(""));
+    }
+}
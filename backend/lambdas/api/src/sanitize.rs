@@ -0,0 +1,108 @@
+use std::env;
+
+// Message text is always valid UTF-8 by the time it gets here (request
+// bodies are parsed into `String`), but valid UTF-8 can still carry
+// characters that break clients or disguise text: NULs and other control
+// characters, bidi overrides that reorder what's displayed, and invisible
+// zero-width characters. Newlines and tabs are kept. Zero-width joiners are
+// needed for emoji sequences and some scripts, so only runs of them are
+// cut down to one.
+//
+// `CONTROL_CHAR_POLICY` picks what happens to the rest: `strip` (the
+// default) drops them, `reject` refuses the message with a 400.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Policy {
+    Strip,
+    Reject,
+}
+
+fn get_policy() -> Policy {
+    match env::var("CONTROL_CHAR_POLICY").as_deref() {
+        Ok("reject") => Policy::Reject,
+        _ => Policy::Strip,
+    }
+}
+
+/// Bidi embeddings, overrides and isolates, plus the invisible marks that
+/// steer them
+fn is_bidi_control(c: char) -> bool {
+    matches!(
+        c,
+        '\u{061C}' | '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}'
+    )
+}
+
+/// Invisible characters with no legitimate use in chat text
+fn is_invisible(c: char) -> bool {
+    matches!(c, '\u{200B}' | '\u{2060}' | '\u{FEFF}')
+}
+
+fn is_joiner(c: char) -> bool {
+    matches!(c, '\u{200C}' | '\u{200D}')
+}
+
+fn is_disallowed(c: char) -> bool {
+    (c.is_control() && !matches!(c, '\n' | '\r' | '\t')) || is_bidi_control(c) || is_invisible(c)
+}
+
+/// Apply the control character policy to message content, returning the
+/// text to store
+pub fn sanitize_content(content: &str) -> Result<String, (u16, String)> {
+    apply_policy(content, get_policy())
+}
+
+fn apply_policy(content: &str, policy: Policy) -> Result<String, (u16, String)> {
+    let mut out = String::with_capacity(content.len());
+    let mut prev_joiner = false;
+
+    for c in content.chars() {
+        let drop = is_disallowed(c) || (is_joiner(c) && prev_joiner);
+        if drop {
+            if policy == Policy::Reject {
+                return Err((400, "Message content contains disallowed control characters".to_string()));
+            }
+            continue;
+        }
+        prev_joiner = is_joiner(c);
+        out.push(c);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_bidi_override() {
+        // RLO would display "txt.exe" as "exe.txt"
+        assert_eq!(sanitize_content("invoice\u{202E}txt.exe").unwrap(), "invoicetxt.exe");
+        assert_eq!(sanitize_content("a\u{2066}b\u{2069}c").unwrap(), "abc");
+    }
+
+    #[test]
+    fn strips_nul_and_invisible_characters() {
+        assert_eq!(sanitize_content("a\u{0}b\u{200B}c\u{FEFF}").unwrap(), "abc");
+    }
+
+    #[test]
+    fn keeps_whitespace_and_single_joiners() {
+        assert_eq!(sanitize_content("line\n\tindented\r\n").unwrap(), "line\n\tindented\r\n");
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        assert_eq!(sanitize_content(family).unwrap(), family);
+    }
+
+    #[test]
+    fn collapses_runs_of_joiners() {
+        assert_eq!(sanitize_content("a\u{200D}\u{200D}\u{200C}b").unwrap(), "a\u{200D}b");
+    }
+
+    #[test]
+    fn reject_policy_refuses_bidi_override() {
+        let (status, _) = apply_policy("invoice\u{202E}txt.exe", Policy::Reject).unwrap_err();
+        assert_eq!(status, 400);
+        assert_eq!(apply_policy("plain text\n", Policy::Reject).unwrap(), "plain text\n");
+    }
+}
@@ -1,25 +1,84 @@
 use aws_sdk_dynamodb::Client as DynamoClient;
-use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue, TransactWriteItem, Update};
+use futures::stream::{self, StreamExt};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::env;
 use uuid::Uuid;
 
+use crate::audit;
+use crate::clock;
+use crate::db;
+use crate::limits;
+use crate::roles;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Server {
     pub id: String,
     pub name: String,
     pub owner_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub icon_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub banner_url: Option<String>,
+    /// `#rrggbb` accent color for the server theme
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accent_color: Option<String>,
     pub created_at: i64,
+    /// Trim leading/trailing whitespace from posted messages
+    #[serde(default = "default_true")]
+    pub trim_messages: bool,
+    /// "invite" (invite or password required) or "open" (anyone may join)
+    #[serde(default = "default_join_policy")]
+    pub join_policy: String,
+    /// Reject invites whose creator is no longer a member
+    #[serde(default)]
+    pub invalidate_invites_on_creator_leave: bool,
+    /// Don't count fenced code blocks toward the message length limit
+    #[serde(default)]
+    pub exclude_code_from_length: bool,
+    /// Sent to new members as a DM from the owner; `{server}` and `{user}`
+    /// are filled in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub welcome_message: Option<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_join_policy() -> String {
+    JOIN_POLICY_INVITE.to_string()
 }
 
+pub const JOIN_POLICY_INVITE: &str = "invite";
+pub const JOIN_POLICY_OPEN: &str = "open";
+
+pub const CHANNEL_TYPE_TEXT: &str = "text";
+pub const CHANNEL_TYPE_VOICE: &str = "voice";
+/// Text channel only moderators post in; other servers can follow it
+pub const CHANNEL_TYPE_ANNOUNCEMENT: &str = "announcement";
+const CHANNEL_TYPES: &[&str] = &[CHANNEL_TYPE_TEXT, CHANNEL_TYPE_VOICE, CHANNEL_TYPE_ANNOUNCEMENT];
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Channel {
     pub id: String,
     pub server_id: String,
     pub name: String,
-    pub channel_type: String, // "text" or "voice"
+    pub channel_type: String, // "text", "voice" or "announcement"
     pub created_at: i64,
+    /// Voice channels accept text messages only when this is set
+    #[serde(default)]
+    pub text_in_voice: bool,
+    /// Millisecond timestamp of the newest message, for activity ordering
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_message_at: Option<i64>,
+    /// Sidebar slot set by a reorder; unset until the first one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<u32>,
+    /// Only owners and admins may post
+    #[serde(default)]
+    pub read_only: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -29,22 +88,92 @@ pub struct Member {
     pub username: String,
     pub role: String, // "owner", "admin", "member"
     pub joined_at: i64,
+    /// Custom role ids assigned on top of the base role
+    #[serde(default)]
+    pub role_ids: Vec<String>,
+    /// Unix seconds; the member can't post until then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_until: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimeoutRequest {
+    pub duration_secs: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemberTimeout {
+    pub server_id: String,
+    pub user_id: String,
+    pub timeout_until: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BanRequest {
+    pub user_id: String,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Ban {
+    pub server_id: String,
+    pub user_id: String,
+    pub banned_by: String,
+    pub banned_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct CreateServerRequest {
     pub name: String,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateServerRequest {
+    pub trim_messages: Option<bool>,
+    /// Empty string clears the banner
+    pub banner_url: Option<String>,
+    /// Empty string clears the accent color
+    pub accent_color: Option<String>,
+    pub join_policy: Option<String>,
+    pub invalidate_invites_on_creator_leave: Option<bool>,
+    pub exclude_code_from_length: Option<bool>,
+    /// Empty string turns the welcome message off
+    pub welcome_message: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct CreateChannelRequest {
     pub name: String,
     #[serde(default = "default_channel_type")]
     pub channel_type: String,
+    #[serde(default)]
+    pub text_in_voice: bool,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateChannelRequest {
+    pub read_only: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReorderChannelsRequest {
+    /// Every channel id in the server, in the new order
+    pub channel_ids: Vec<String>,
 }
 
 fn default_channel_type() -> String {
-    "text".to_string()
+    CHANNEL_TYPE_TEXT.to_string()
 }
 
 #[derive(Debug, Serialize)]
@@ -55,6 +184,36 @@ pub struct ServerWithChannels {
     pub member_count: usize,
 }
 
+/// One entry in the client sidebar
+#[derive(Debug, Serialize)]
+pub struct ServerSummary {
+    pub id: String,
+    pub name: String,
+    pub icon_url: Option<String>,
+    pub member_count: usize,
+    pub my_role: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OwnerIntegrity {
+    pub server_id: String,
+    pub owner_id: String,
+    pub owner_count: usize,
+    pub owner_member_ids: Vec<String>,
+    pub anomalies: Vec<String>,
+    pub healthy: bool,
+}
+
+const MAX_AUTOCOMPLETE_RESULTS: usize = 10;
+/// TransactWriteItems takes at most 100 actions
+const MAX_REORDER_CHANNELS: usize = 100;
+const MIN_TIMEOUT_SECS: i64 = 60;
+const MAX_WELCOME_MESSAGE_CHARS: usize = 1000;
+const MAX_TIMEOUT_SECS: i64 = 28 * 24 * 60 * 60;
+const MAX_BAN_REASON_CHARS: usize = 512;
+/// Concurrent member-count queries when building server summaries
+const SUMMARY_CONCURRENCY: usize = 8;
+
 fn get_table(name: &str) -> String {
     env::var(name).unwrap_or_else(|_| format!("agorusta-{}-dev", name.to_lowercase().replace("_table", "s")))
 }
@@ -94,14 +253,21 @@ pub async fn create_server(
     }
 
     let server_id = Uuid::new_v4().to_string();
-    let now = chrono::Utc::now().timestamp();
+    let now = clock::now_secs();
 
     let server = Server {
         id: server_id.clone(),
         name: server_name,
         owner_id: user_id.to_string(),
         icon_url: None,
+        banner_url: None,
+        accent_color: None,
         created_at: now,
+        trim_messages: true,
+        join_policy: default_join_policy(),
+        invalidate_invites_on_creator_leave: false,
+        exclude_code_from_length: false,
+        welcome_message: None,
     };
 
     // Create the server
@@ -122,6 +288,8 @@ pub async fn create_server(
         username: username.to_string(),
         role: "owner".to_string(),
         joined_at: now,
+        role_ids: vec![],
+        timeout_until: None,
     };
 
     db.put_item()
@@ -140,8 +308,12 @@ pub async fn create_server(
         id: Uuid::new_v4().to_string(),
         server_id: server_id.clone(),
         name: "general".to_string(),
-        channel_type: "text".to_string(),
+        channel_type: CHANNEL_TYPE_TEXT.to_string(),
         created_at: now,
+        text_in_voice: false,
+        last_message_at: None,
+        position: None,
+        read_only: false,
     };
 
     db.put_item()
@@ -166,41 +338,19 @@ pub async fn list_user_servers(
     db: &DynamoClient,
     user_id: &str,
 ) -> Result<Vec<Server>, (u16, String)> {
-    // Get all memberships for this user
-    let memberships = db
-        .query()
-        .table_name(get_table("MEMBERS_TABLE"))
-        .index_name("user-servers-index")
-        .key_condition_expression("user_id = :uid")
-        .expression_attribute_values(":uid", AttributeValue::S(user_id.to_string()))
-        .send()
-        .await
-        .map_err(|e| (500, format!("Failed to list memberships: {}", e)))?;
-
-    let server_ids: Vec<String> = memberships
-        .items()
-        .iter()
-        .filter_map(|item| item.get("server_id")?.as_s().ok().cloned())
-        .collect();
-
+    let server_ids: Vec<String> = user_server_ids(db, user_id).await?.into_iter().collect();
     if server_ids.is_empty() {
         return Ok(vec![]);
     }
 
-    // Fetch each server (could batch this with BatchGetItem for optimization)
-    let mut servers = Vec::new();
-    for server_id in server_ids {
-        if let Ok(result) = db
-            .get_item()
-            .table_name(get_table("SERVERS_TABLE"))
-            .key("id", AttributeValue::S(server_id))
-            .send()
-            .await
-        {
-            if let Some(item) = result.item() {
-                if let Some(server) = parse_server(item) {
-                    servers.push(server);
-                }
+    // Up to 100 servers per round trip instead of one get per membership.
+    // A failed batch only drops its own servers from the list.
+    let mut servers = Vec::with_capacity(server_ids.len());
+    for chunk in server_ids.chunks(db::BATCH_GET_MAX_KEYS) {
+        match batch_get_servers(db, chunk).await {
+            Ok(batch) => servers.extend(batch),
+            Err((_, e)) => {
+                tracing::warn!(user_id = %user_id, servers = chunk.len(), error = %e, "Failed to load a batch of servers");
             }
         }
     }
@@ -208,49 +358,279 @@ pub async fn list_user_servers(
     Ok(servers)
 }
 
+/// Ids of every server the user belongs to
+async fn user_server_ids(db: &DynamoClient, user_id: &str) -> Result<HashSet<String>, (u16, String)> {
+    let mut server_ids = HashSet::new();
+    let mut start_key: Option<HashMap<String, AttributeValue>> = None;
+
+    loop {
+        let result = db
+            .query()
+            .table_name(get_table("MEMBERS_TABLE"))
+            .index_name("user-servers-index")
+            .key_condition_expression("user_id = :uid")
+            .expression_attribute_values(":uid", AttributeValue::S(user_id.to_string()))
+            .projection_expression("server_id")
+            .set_exclusive_start_key(start_key.take())
+            .send()
+            .await
+            .map_err(|e| (500, format!("Failed to list memberships: {}", e)))?;
+
+        server_ids.extend(
+            result
+                .items()
+                .iter()
+                .filter_map(|item| item.get("server_id")?.as_s().ok().cloned()),
+        );
+
+        start_key = result.last_evaluated_key().cloned();
+        if start_key.is_none() {
+            break;
+        }
+    }
+
+    Ok(server_ids)
+}
+
+/// The user's role in each server they belong to. The user-servers index
+/// projects all attributes, so roles come along.
+async fn user_server_roles(db: &DynamoClient, user_id: &str) -> Result<HashMap<String, String>, (u16, String)> {
+    let mut roles_by_server = HashMap::new();
+    let mut start_key: Option<HashMap<String, AttributeValue>> = None;
+
+    loop {
+        let result = db
+            .query()
+            .table_name(get_table("MEMBERS_TABLE"))
+            .index_name("user-servers-index")
+            .key_condition_expression("user_id = :uid")
+            .expression_attribute_values(":uid", AttributeValue::S(user_id.to_string()))
+            .projection_expression("server_id, #role")
+            .expression_attribute_names("#role", "role")
+            .set_exclusive_start_key(start_key.take())
+            .send()
+            .await
+            .map_err(|e| (500, format!("Failed to list memberships: {}", e)))?;
+
+        roles_by_server.extend(result.items().iter().filter_map(|item| {
+            Some((
+                item.get("server_id")?.as_s().ok()?.clone(),
+                item.get("role")?.as_s().ok()?.clone(),
+            ))
+        }));
+
+        start_key = result.last_evaluated_key().cloned();
+        if start_key.is_none() {
+            break;
+        }
+    }
+
+    Ok(roles_by_server)
+}
+
+/// Whether two users are members of at least one common server
+pub async fn share_a_server(db: &DynamoClient, user_a: &str, user_b: &str) -> Result<bool, (u16, String)> {
+    let (a, b) = futures::join!(user_server_ids(db, user_a), user_server_ids(db, user_b));
+    Ok(!a?.is_disjoint(&b?))
+}
+
+/// Sidebar summaries for every server the user belongs to, in one call.
+/// Servers are batch-fetched and member counts run with bounded concurrency.
+pub async fn list_server_summaries(
+    db: &DynamoClient,
+    user_id: &str,
+) -> Result<Vec<ServerSummary>, (u16, String)> {
+    let roles_by_server = user_server_roles(db, user_id).await?;
+
+    if roles_by_server.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let server_ids: Vec<String> = roles_by_server.keys().cloned().collect();
+    let servers = batch_get_servers(db, &server_ids).await?;
+
+    let fetched_ids: Vec<String> = servers.iter().map(|s| s.id.clone()).collect();
+    let counts: HashMap<String, usize> = stream::iter(fetched_ids)
+        .map(|server_id| async move {
+            let count = count_members(db, &server_id, false).await;
+            (server_id, count)
+        })
+        .buffer_unordered(SUMMARY_CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .map(|(server_id, count)| count.map(|c| (server_id, c)))
+        .collect::<Result<_, _>>()?;
+
+    let mut summaries: Vec<ServerSummary> = servers
+        .into_iter()
+        .map(|server| ServerSummary {
+            member_count: counts.get(&server.id).copied().unwrap_or(0),
+            my_role: roles_by_server.get(&server.id).cloned().unwrap_or_default(),
+            id: server.id,
+            name: server.name,
+            icon_url: server.icon_url,
+        })
+        .collect();
+    summaries.sort_by_key(|s| s.name.to_lowercase());
+
+    Ok(summaries)
+}
+
+/// `consistent` makes every read strongly consistent, for callers that
+/// need to see a write they just made (a join, a new channel). Those reads
+/// cost twice as much, so it's off unless asked for.
 pub async fn get_server(
     db: &DynamoClient,
     server_id: &str,
     user_id: &str,
+    consistent: bool,
 ) -> Result<ServerWithChannels, (u16, String)> {
     // Check membership
-    check_membership(db, server_id, user_id).await?;
+    read_membership(db, server_id, user_id, consistent)
+        .await?
+        .ok_or((403, "You are not a member of this server".to_string()))?;
 
     // Get server
-    let result = db
-        .get_item()
-        .table_name(get_table("SERVERS_TABLE"))
-        .key("id", AttributeValue::S(server_id.to_string()))
-        .send()
-        .await
-        .map_err(|e| (500, format!("Database error: {}", e)))?;
+    let server = read_server_record(db, server_id, consistent).await?;
 
-    let server = result
-        .item()
-        .and_then(parse_server)
-        .ok_or((404, "Server not found".to_string()))?;
-
-    // Get channels
-    let channels = list_channels(db, server_id).await?;
+    // Get channels, putting back a default one if the server has lost them all
+    let mut channels = query_channels(db, server_id, consistent).await?;
+    if channels.is_empty() {
+        match ensure_default_channel(db, server_id).await {
+            Ok(Some(channel)) => channels.push(channel),
+            Ok(None) => {}
+            Err((_, e)) => {
+                tracing::warn!(server_id = %server_id, error = %e, "Failed to restore default channel");
+            }
+        }
+    }
 
     // Get member count
-    let members = db
-        .query()
-        .table_name(get_table("MEMBERS_TABLE"))
-        .key_condition_expression("server_id = :sid")
-        .expression_attribute_values(":sid", AttributeValue::S(server_id.to_string()))
-        .select(aws_sdk_dynamodb::types::Select::Count)
-        .send()
-        .await
-        .map_err(|e| (500, format!("Failed to count members: {}", e)))?;
+    let member_count = count_members(db, server_id, consistent).await?;
 
     Ok(ServerWithChannels {
         server,
         channels,
-        member_count: members.count() as usize,
+        member_count,
     })
 }
 
+/// Update server settings (owner/admin). Only fields present in the body
+/// are changed.
+pub async fn update_server(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+    body: &str,
+) -> Result<Server, (u16, String)> {
+    if !roles::has_permission(db, server_id, user_id, roles::MANAGE_SERVER).await? {
+        return Err((403, "You don't have permission to update the server".to_string()));
+    }
+
+    let req: UpdateServerRequest = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request: {}", e)))?;
+
+    let mut updates: Vec<(&str, AttributeValue)> = Vec::new();
+    let mut removals: Vec<&str> = Vec::new();
+    if let Some(trim) = req.trim_messages {
+        updates.push(("trim_messages", AttributeValue::Bool(trim)));
+    }
+    if let Some(invalidate) = req.invalidate_invites_on_creator_leave {
+        updates.push(("invalidate_invites_on_creator_leave", AttributeValue::Bool(invalidate)));
+    }
+    if let Some(exclude) = req.exclude_code_from_length {
+        updates.push(("exclude_code_from_length", AttributeValue::Bool(exclude)));
+    }
+    if let Some(banner_url) = req.banner_url.as_deref().map(str::trim) {
+        if banner_url.is_empty() {
+            removals.push("banner_url");
+        } else {
+            validate_image_url(banner_url)?;
+            updates.push(("banner_url", AttributeValue::S(banner_url.to_string())));
+        }
+    }
+    if let Some(welcome) = req.welcome_message.as_deref().map(str::trim) {
+        if welcome.is_empty() {
+            removals.push("welcome_message");
+        } else {
+            if welcome.chars().count() > MAX_WELCOME_MESSAGE_CHARS {
+                return Err((
+                    400,
+                    format!("Welcome message must be at most {} characters", MAX_WELCOME_MESSAGE_CHARS),
+                ));
+            }
+            updates.push(("welcome_message", AttributeValue::S(welcome.to_string())));
+        }
+    }
+    if let Some(accent_color) = req.accent_color.as_deref().map(str::trim) {
+        if accent_color.is_empty() {
+            removals.push("accent_color");
+        } else {
+            if !roles::is_hex_color(accent_color) {
+                return Err((400, "Accent color must be a hex color like #5865f2".to_string()));
+            }
+            updates.push(("accent_color", AttributeValue::S(accent_color.to_lowercase())));
+        }
+    }
+
+    if let Some(policy) = req.join_policy {
+        if policy != JOIN_POLICY_INVITE && policy != JOIN_POLICY_OPEN {
+            return Err((400, "Join policy must be invite or open".to_string()));
+        }
+        updates.push(("join_policy", AttributeValue::S(policy)));
+    }
+
+    if updates.is_empty() && removals.is_empty() {
+        return get_server_record(db, server_id).await;
+    }
+
+    let mut update = db
+        .update_item()
+        .table_name(get_table("SERVERS_TABLE"))
+        .key("id", AttributeValue::S(server_id.to_string()))
+        .condition_expression("attribute_exists(id)");
+
+    let mut assignments = Vec::new();
+    for (i, (field, value)) in updates.into_iter().enumerate() {
+        assignments.push(format!("#f{} = :v{}", i, i));
+        update = update
+            .expression_attribute_names(format!("#f{}", i), field)
+            .expression_attribute_values(format!(":v{}", i), value);
+    }
+    let mut removed = Vec::new();
+    for (i, field) in removals.into_iter().enumerate() {
+        removed.push(format!("#r{}", i));
+        update = update.expression_attribute_names(format!("#r{}", i), field);
+    }
+
+    let mut expression = Vec::new();
+    if !assignments.is_empty() {
+        expression.push(format!("SET {}", assignments.join(", ")));
+    }
+    if !removed.is_empty() {
+        expression.push(format!("REMOVE {}", removed.join(", ")));
+    }
+
+    update
+        .update_expression(expression.join(" "))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to update server: {}", e)))?;
+
+    get_server_record(db, server_id).await
+}
+
+fn validate_image_url(url: &str) -> Result<(), (u16, String)> {
+    if url.len() > 2048 {
+        return Err((400, "Image URL cannot exceed 2048 characters".to_string()));
+    }
+    if !url.starts_with("https://") {
+        return Err((400, "Image URL must use https".to_string()));
+    }
+    Ok(())
+}
+
 // ============ Channels ============
 
 pub async fn create_channel(
@@ -259,10 +639,9 @@ pub async fn create_channel(
     user_id: &str,
     body: &str,
 ) -> Result<Channel, (u16, String)> {
-    // Check if user is owner or admin
-    let role = get_member_role(db, server_id, user_id).await?;
-    if role != "owner" && role != "admin" {
-        return Err((403, "Only owners and admins can create channels".to_string()));
+    // Check if user can manage channels (owner, admin, or a custom role)
+    if !roles::has_permission(db, server_id, user_id, roles::MANAGE_CHANNELS).await? {
+        return Err((403, "You don't have permission to create channels".to_string()));
     }
 
     let req: CreateChannelRequest = serde_json::from_str(body)
@@ -271,13 +650,23 @@ pub async fn create_channel(
     if req.name.trim().is_empty() || req.name.len() > 100 {
         return Err((400, "Channel name must be 1-100 characters".to_string()));
     }
+    if !CHANNEL_TYPES.contains(&req.channel_type.as_str()) {
+        return Err((
+            400,
+            format!("channel_type must be one of: {}", CHANNEL_TYPES.join(", ")),
+        ));
+    }
 
     let channel = Channel {
         id: Uuid::new_v4().to_string(),
         server_id: server_id.to_string(),
         name: req.name.trim().to_lowercase().replace(' ', "-"),
         channel_type: req.channel_type,
-        created_at: chrono::Utc::now().timestamp(),
+        created_at: clock::now_secs(),
+        text_in_voice: req.text_in_voice,
+        last_message_at: None,
+        position: None,
+        read_only: req.read_only,
     };
 
     db.put_item()
@@ -287,6 +676,8 @@ pub async fn create_channel(
         .item("name", AttributeValue::S(channel.name.clone()))
         .item("channel_type", AttributeValue::S(channel.channel_type.clone()))
         .item("created_at", AttributeValue::N(channel.created_at.to_string()))
+        .item("text_in_voice", AttributeValue::Bool(channel.text_in_voice))
+        .item("read_only", AttributeValue::Bool(channel.read_only))
         .send()
         .await
         .map_err(|e| (500, format!("Failed to create channel: {}", e)))?;
@@ -294,15 +685,146 @@ pub async fn create_channel(
     Ok(channel)
 }
 
+/// Change a channel's settings (manage_channels). Only fields present in the
+/// body are changed.
+pub async fn update_channel(
+    db: &DynamoClient,
+    server_id: &str,
+    channel_id: &str,
+    user_id: &str,
+    body: &str,
+) -> Result<Channel, (u16, String)> {
+    if !roles::has_permission(db, server_id, user_id, roles::MANAGE_CHANNELS).await? {
+        return Err((403, "You don't have permission to update channels".to_string()));
+    }
+
+    let req: UpdateChannelRequest = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request: {}", e)))?;
+
+    let Some(read_only) = req.read_only else {
+        return get_channel_record(db, server_id, channel_id).await;
+    };
+
+    let result = db
+        .update_item()
+        .table_name(get_table("CHANNELS_TABLE"))
+        .key("server_id", AttributeValue::S(server_id.to_string()))
+        .key("id", AttributeValue::S(channel_id.to_string()))
+        .update_expression("SET read_only = :ro")
+        .condition_expression("attribute_exists(id)")
+        .expression_attribute_values(":ro", AttributeValue::Bool(read_only))
+        .send()
+        .await;
+
+    if let Err(e) = result {
+        let missing = e
+            .as_service_error()
+            .map(|se| se.is_conditional_check_failed_exception())
+            .unwrap_or(false);
+        if missing {
+            return Err((404, "Channel not found".to_string()));
+        }
+        return Err((500, format!("Failed to update channel: {}", e)));
+    }
+
+    get_channel_record(db, server_id, channel_id).await
+}
+
+/// Id of the repaired "general" channel: a v5 UUID of the server id, so it
+/// is deterministic and still passes the `:channel_id` format check
+fn default_channel_id(server_id: &str) -> String {
+    Uuid::new_v5(&Uuid::NAMESPACE_OID, format!("{}/general", server_id).as_bytes()).to_string()
+}
+
+/// Create a "general" channel if the server has no channels at all, and
+/// return it. The id is derived from the server id and the write is
+/// conditional, so repairs racing each other end up with the same single
+/// channel. Returns None when the server already has channels.
+pub async fn ensure_default_channel(
+    db: &DynamoClient,
+    server_id: &str,
+) -> Result<Option<Channel>, (u16, String)> {
+    if !list_channels(db, server_id).await?.is_empty() {
+        return Ok(None);
+    }
+
+    let channel = Channel {
+        id: default_channel_id(server_id),
+        server_id: server_id.to_string(),
+        name: "general".to_string(),
+        channel_type: CHANNEL_TYPE_TEXT.to_string(),
+        created_at: clock::now_secs(),
+        text_in_voice: false,
+        last_message_at: None,
+        position: None,
+        read_only: false,
+    };
+
+    let result = db
+        .put_item()
+        .table_name(get_table("CHANNELS_TABLE"))
+        .item("server_id", AttributeValue::S(channel.server_id.clone()))
+        .item("id", AttributeValue::S(channel.id.clone()))
+        .item("name", AttributeValue::S(channel.name.clone()))
+        .item("channel_type", AttributeValue::S(channel.channel_type.clone()))
+        .item("created_at", AttributeValue::N(channel.created_at.to_string()))
+        .condition_expression("attribute_not_exists(id)")
+        .send()
+        .await;
+
+    match result {
+        Ok(_) => {
+            tracing::info!(server_id = %server_id, channel_id = %channel.id, "Restored default channel");
+            Ok(Some(channel))
+        }
+        Err(e) => {
+            let exists = e
+                .as_service_error()
+                .map(|se| se.is_conditional_check_failed_exception())
+                .unwrap_or(false);
+            if exists {
+                // A concurrent repair got there first
+                get_channel_record(db, server_id, &channel.id).await.map(Some)
+            } else {
+                Err((500, format!("Failed to create channel: {}", e)))
+            }
+        }
+    }
+}
+
+/// Owner-triggered `ensure_default_channel`, returning the server's channels
+pub async fn repair_channels(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+) -> Result<Vec<Channel>, (u16, String)> {
+    let role = get_member_role(db, server_id, user_id).await?;
+    if role != "owner" {
+        return Err((403, "Only the server owner can repair channels".to_string()));
+    }
+
+    ensure_default_channel(db, server_id).await?;
+    list_channels(db, server_id).await
+}
+
 pub async fn list_channels(
     db: &DynamoClient,
     server_id: &str,
+) -> Result<Vec<Channel>, (u16, String)> {
+    query_channels(db, server_id, false).await
+}
+
+async fn query_channels(
+    db: &DynamoClient,
+    server_id: &str,
+    consistent: bool,
 ) -> Result<Vec<Channel>, (u16, String)> {
     let result = db
         .query()
         .table_name(get_table("CHANNELS_TABLE"))
         .key_condition_expression("server_id = :sid")
         .expression_attribute_values(":sid", AttributeValue::S(server_id.to_string()))
+        .consistent_read(consistent)
         .send()
         .await
         .map_err(|e| (500, format!("Failed to list channels: {}", e)))?;
@@ -316,84 +838,789 @@ pub async fn list_channels(
     Ok(channels)
 }
 
-// ============ Members ============
+/// Order channels for the sidebar. `created` is oldest first; `position`
+/// follows the last reorder, with unplaced channels after by age;
+/// `activity` puts the most recently posted-in channels first, with
+/// channels that never had a message last. No sort keeps the table order.
+pub fn sort_channels(channels: &mut [Channel], sort: Option<&str>) -> Result<(), (u16, String)> {
+    match sort {
+        None => {}
+        Some("created") => channels.sort_by_key(|c| c.created_at),
+        Some("position") => channels.sort_by_key(|c| (c.position.is_none(), c.position, c.created_at)),
+        Some("activity") => channels.sort_by_key(|c| {
+            (std::cmp::Reverse(c.last_message_at), c.created_at)
+        }),
+        Some(other) => {
+            return Err((
+                400,
+                format!("Invalid sort '{}': expected created, position or activity", other),
+            ))
+        }
+    }
+    Ok(())
+}
 
-pub async fn list_members(
+/// `channel_ids` must be a permutation of the server's channel ids
+fn check_reorder(channel_ids: &[String], channels: &[Channel]) -> Result<(), (u16, String)> {
+    let requested: HashSet<&str> = channel_ids.iter().map(String::as_str).collect();
+    if requested.len() != channel_ids.len() {
+        return Err((400, "channel_ids contains duplicates".to_string()));
+    }
+    let current: HashSet<&str> = channels.iter().map(|c| c.id.as_str()).collect();
+    if requested != current {
+        return Err((
+            400,
+            "channel_ids must list every channel in the server exactly once".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Set every channel's position from `channel_ids`, which must list each of
+/// the server's channels exactly once. Applied in one transaction so a
+/// failure leaves the previous order intact.
+pub async fn reorder_channels(
     db: &DynamoClient,
     server_id: &str,
     user_id: &str,
-) -> Result<Vec<Member>, (u16, String)> {
-    // Check membership
-    check_membership(db, server_id, user_id).await?;
+    body: &str,
+) -> Result<Vec<Channel>, (u16, String)> {
+    if !roles::has_permission(db, server_id, user_id, roles::MANAGE_CHANNELS).await? {
+        return Err((403, "You don't have permission to reorder channels".to_string()));
+    }
 
-    let result = db
-        .query()
-        .table_name(get_table("MEMBERS_TABLE"))
-        .key_condition_expression("server_id = :sid")
-        .expression_attribute_values(":sid", AttributeValue::S(server_id.to_string()))
+    let req: ReorderChannelsRequest = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request: {}", e)))?;
+
+    limits::bounded_vec("channel_ids", &req.channel_ids, MAX_REORDER_CHANNELS)?;
+
+    let mut channels = list_channels(db, server_id).await?;
+    check_reorder(&req.channel_ids, &channels)?;
+
+    let table = get_table("CHANNELS_TABLE");
+    let items = req
+        .channel_ids
+        .iter()
+        .enumerate()
+        .map(|(position, channel_id)| {
+            let update = Update::builder()
+                .table_name(&table)
+                .key("server_id", AttributeValue::S(server_id.to_string()))
+                .key("id", AttributeValue::S(channel_id.clone()))
+                .update_expression("SET #pos = :pos")
+                .condition_expression("attribute_exists(id)")
+                .expression_attribute_names("#pos", "position")
+                .expression_attribute_values(":pos", AttributeValue::N(position.to_string()))
+                .build()
+                .map_err(|e| (500, format!("Failed to build reorder: {}", e)))?;
+            Ok(TransactWriteItem::builder().update(update).build())
+        })
+        .collect::<Result<Vec<_>, (u16, String)>>()?;
+
+    db.transact_write_items()
+        .set_transact_items(Some(items))
         .send()
         .await
-        .map_err(|e| (500, format!("Failed to list members: {}", e)))?;
+        .map_err(|e| {
+            // A channel deleted mid-reorder fails its condition
+            let conflict = e
+                .as_service_error()
+                .map(|se| se.is_transaction_canceled_exception())
+                .unwrap_or(false);
+            if conflict {
+                (409, "Channels changed during reorder; fetch them and retry".to_string())
+            } else {
+                (500, format!("Failed to reorder channels: {}", e))
+            }
+        })?;
 
-    let members: Vec<Member> = result
-        .items()
+    let positions: HashMap<&str, u32> = req
+        .channel_ids
         .iter()
-        .filter_map(parse_member)
+        .enumerate()
+        .map(|(position, id)| (id.as_str(), position as u32))
         .collect();
+    for channel in channels.iter_mut() {
+        channel.position = positions.get(channel.id.as_str()).copied();
+    }
+    channels.sort_by_key(|c| c.position);
 
-    Ok(members)
+    Ok(channels)
 }
 
-// ============ Helpers ============
-
-async fn check_membership(
+/// Claim the next sequence number for a message in a channel and record
+/// the channel's activity, in one atomic update. Numbering starts at 1 for
+/// channels that predate sequence numbers. The condition stops a deleted
+/// channel from being recreated as a stub.
+pub async fn next_message_seq(
     db: &DynamoClient,
     server_id: &str,
-    user_id: &str,
+    channel_id: &str,
+    at: i64,
+) -> Result<u64, (u16, String)> {
+    let result = db
+        .update_item()
+        .table_name(get_table("CHANNELS_TABLE"))
+        .key("server_id", AttributeValue::S(server_id.to_string()))
+        .key("id", AttributeValue::S(channel_id.to_string()))
+        .update_expression("ADD message_seq :one SET last_message_at = :at")
+        .condition_expression("attribute_exists(id)")
+        .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+        .expression_attribute_values(":at", AttributeValue::N(at.to_string()))
+        .return_values(ReturnValue::UpdatedNew)
+        .send()
+        .await
+        .map_err(|e| {
+            let missing = e
+                .as_service_error()
+                .map(|se| se.is_conditional_check_failed_exception())
+                .unwrap_or(false);
+            if missing {
+                (404, "Channel not found".to_string())
+            } else {
+                (500, format!("Failed to assign message sequence: {}", e))
+            }
+        })?;
+
+    result
+        .attributes()
+        .and_then(|attrs| attrs.get("message_seq")?.as_n().ok()?.parse().ok())
+        .ok_or((500, "Failed to assign message sequence".to_string()))
+}
+
+// ============ Members ============
+
+/// `consistent` is as for `get_server`
+pub async fn list_members(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+    consistent: bool,
+) -> Result<Vec<Member>, (u16, String)> {
+    // Check membership
+    read_membership(db, server_id, user_id, consistent)
+        .await?
+        .ok_or((403, "You are not a member of this server".to_string()))?;
+
+    query_members(db, server_id, consistent).await
+}
+
+/// Members whose username starts with `prefix` (case-insensitive), for
+/// `@` autocomplete. Shortest names first so exact matches surface.
+pub async fn autocomplete_members(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+    prefix: &str,
+) -> Result<Vec<Member>, (u16, String)> {
+    check_membership(db, server_id, user_id).await?;
+
+    let prefix = prefix.trim().to_lowercase();
+    let mut matches: Vec<Member> = query_members(db, server_id, false)
+        .await?
+        .into_iter()
+        .filter(|m| m.username.to_lowercase().starts_with(&prefix))
+        .collect();
+
+    matches.sort_by_key(|m| (m.username.len(), m.username.to_lowercase()));
+    matches.truncate(MAX_AUTOCOMPLETE_RESULTS);
+    Ok(matches)
+}
+
+/// Check `actor_id` may apply `action` (e.g. "kick") to `target_user_id`:
+/// owners and admins can act on members, and only the owner on admins. The
+/// owner is untouchable. Returns the target's membership, if they have one.
+async fn authorize_moderation(
+    db: &DynamoClient,
+    server_id: &str,
+    target_user_id: &str,
+    actor_id: &str,
+    action: &str,
+) -> Result<Option<Member>, (u16, String)> {
+    let actor_role = authorize_moderator(db, server_id, actor_id, action).await?;
+    authorize_moderation_target(db, server_id, target_user_id, actor_id, &actor_role, action).await
+}
+
+/// The actor half of `authorize_moderation`, for when the target isn't known
+/// until the body is parsed. Returns the actor's role.
+async fn authorize_moderator(
+    db: &DynamoClient,
+    server_id: &str,
+    actor_id: &str,
+    action: &str,
+) -> Result<String, (u16, String)> {
+    let actor_role = get_member_role(db, server_id, actor_id).await?;
+    if actor_role != "owner" && actor_role != "admin" {
+        return Err((403, format!("Only the owner or an admin can {} members", action)));
+    }
+    Ok(actor_role)
+}
+
+async fn authorize_moderation_target(
+    db: &DynamoClient,
+    server_id: &str,
+    target_user_id: &str,
+    actor_id: &str,
+    actor_role: &str,
+    action: &str,
+) -> Result<Option<Member>, (u16, String)> {
+    if target_user_id == actor_id {
+        return Err((400, format!("You cannot {} yourself", action)));
+    }
+
+    let target = find_membership(db, server_id, target_user_id).await?;
+    if let Some(target) = &target {
+        if target.role == "owner" || (target.role == "admin" && actor_role != "owner") {
+            return Err((403, format!("You cannot {} this member", action)));
+        }
+    }
+
+    Ok(target)
+}
+
+fn check_timeout_duration(duration_secs: i64) -> Result<(), (u16, String)> {
+    if !(MIN_TIMEOUT_SECS..=MAX_TIMEOUT_SECS).contains(&duration_secs) {
+        return Err((
+            400,
+            format!(
+                "duration_secs must be between {} and {}",
+                MIN_TIMEOUT_SECS, MAX_TIMEOUT_SECS
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Stop a member posting for `duration_secs`. They can still read.
+pub async fn set_member_timeout(
+    db: &DynamoClient,
+    server_id: &str,
+    target_user_id: &str,
+    actor_id: &str,
+    body: &str,
+) -> Result<MemberTimeout, (u16, String)> {
+    authorize_moderation(db, server_id, target_user_id, actor_id, "time out")
+        .await?
+        .ok_or((404, "Member not found".to_string()))?;
+
+    let req: TimeoutRequest = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request: {}", e)))?;
+
+    check_timeout_duration(req.duration_secs)?;
+
+    let timeout_until = clock::now_secs() + req.duration_secs;
+    db.update_item()
+        .table_name(get_table("MEMBERS_TABLE"))
+        .key("server_id", AttributeValue::S(server_id.to_string()))
+        .key("user_id", AttributeValue::S(target_user_id.to_string()))
+        .update_expression("SET timeout_until = :until")
+        .condition_expression("attribute_exists(user_id)")
+        .expression_attribute_values(":until", AttributeValue::N(timeout_until.to_string()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to time out member: {}", e)))?;
+
+    audit::record(
+        db,
+        server_id,
+        actor_id,
+        "member_timeout",
+        Some(target_user_id),
+        serde_json::json!({ "duration_secs": req.duration_secs, "timeout_until": timeout_until }),
+    )
+    .await;
+
+    Ok(MemberTimeout {
+        server_id: server_id.to_string(),
+        user_id: target_user_id.to_string(),
+        timeout_until: Some(timeout_until),
+    })
+}
+
+/// Lift a timeout early
+pub async fn clear_member_timeout(
+    db: &DynamoClient,
+    server_id: &str,
+    target_user_id: &str,
+    actor_id: &str,
+) -> Result<MemberTimeout, (u16, String)> {
+    authorize_moderation(db, server_id, target_user_id, actor_id, "time out")
+        .await?
+        .ok_or((404, "Member not found".to_string()))?;
+
+    db.update_item()
+        .table_name(get_table("MEMBERS_TABLE"))
+        .key("server_id", AttributeValue::S(server_id.to_string()))
+        .key("user_id", AttributeValue::S(target_user_id.to_string()))
+        .update_expression("REMOVE timeout_until")
+        .condition_expression("attribute_exists(user_id)")
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to clear timeout: {}", e)))?;
+
+    audit::record(
+        db,
+        server_id,
+        actor_id,
+        "member_timeout_cleared",
+        Some(target_user_id),
+        serde_json::json!({}),
+    )
+    .await;
+
+    Ok(MemberTimeout {
+        server_id: server_id.to_string(),
+        user_id: target_user_id.to_string(),
+        timeout_until: None,
+    })
+}
+
+/// Delete a member row unless it has become the owner's since it was read
+async fn remove_member_row(db: &DynamoClient, server_id: &str, user_id: &str) -> Result<(), (u16, String)> {
+    let result = db
+        .delete_item()
+        .table_name(get_table("MEMBERS_TABLE"))
+        .key("server_id", AttributeValue::S(server_id.to_string()))
+        .key("user_id", AttributeValue::S(user_id.to_string()))
+        .condition_expression("attribute_exists(user_id) AND #role <> :owner")
+        .expression_attribute_names("#role", "role")
+        .expression_attribute_values(":owner", AttributeValue::S("owner".to_string()))
+        .send()
+        .await;
+
+    if let Err(e) = result {
+        let conflict = e
+            .as_service_error()
+            .map(|se| se.is_conditional_check_failed_exception())
+            .unwrap_or(false);
+        if conflict {
+            return Err((404, "Member not found".to_string()));
+        }
+        return Err((500, format!("Failed to remove member: {}", e)));
+    }
+
+    Ok(())
+}
+
+/// Remove a member from the server. They can rejoin with a valid invite.
+pub async fn kick_member(
+    db: &DynamoClient,
+    server_id: &str,
+    target_user_id: &str,
+    requester_id: &str,
 ) -> Result<(), (u16, String)> {
+    authorize_moderation(db, server_id, target_user_id, requester_id, "kick")
+        .await?
+        .ok_or((404, "Member not found".to_string()))?;
+
+    remove_member_row(db, server_id, target_user_id).await?;
+
+    audit::record(
+        db,
+        server_id,
+        requester_id,
+        "member_kicked",
+        Some(target_user_id),
+        serde_json::json!({}),
+    )
+    .await;
+
+    Ok(())
+}
+
+/// Ban a user from the server, removing them if they're a member. Users who
+/// aren't members can be banned too, to keep them from joining.
+pub async fn ban_member(
+    db: &DynamoClient,
+    server_id: &str,
+    requester_id: &str,
+    body: &str,
+) -> Result<Ban, (u16, String)> {
+    let actor_role = authorize_moderator(db, server_id, requester_id, "ban").await?;
+
+    let req: BanRequest = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request: {}", e)))?;
+    if req.reason.as_ref().is_some_and(|r| r.chars().count() > MAX_BAN_REASON_CHARS) {
+        return Err((
+            400,
+            format!("Ban reason must be at most {} characters", MAX_BAN_REASON_CHARS),
+        ));
+    }
+
+    let target =
+        authorize_moderation_target(db, server_id, &req.user_id, requester_id, &actor_role, "ban").await?;
+
+    let ban = Ban {
+        server_id: server_id.to_string(),
+        user_id: req.user_id,
+        banned_by: requester_id.to_string(),
+        banned_at: clock::now_secs(),
+        reason: req.reason,
+    };
+
+    // Write the ban before removing the member so they can't rejoin in
+    // between
+    let mut put = db
+        .put_item()
+        .table_name(get_table("BANS_TABLE"))
+        .item("server_id", AttributeValue::S(ban.server_id.clone()))
+        .item("user_id", AttributeValue::S(ban.user_id.clone()))
+        .item("banned_by", AttributeValue::S(ban.banned_by.clone()))
+        .item("banned_at", AttributeValue::N(ban.banned_at.to_string()));
+    if let Some(reason) = &ban.reason {
+        put = put.item("reason", AttributeValue::S(reason.clone()));
+    }
+    put.send()
+        .await
+        .map_err(|e| (500, format!("Failed to ban user: {}", e)))?;
+
+    if target.is_some() {
+        match remove_member_row(db, server_id, &ban.user_id).await {
+            // Left on their own in the meantime
+            Ok(()) | Err((404, _)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    audit::record(
+        db,
+        server_id,
+        requester_id,
+        "member_banned",
+        Some(&ban.user_id),
+        serde_json::json!({ "reason": ban.reason, "was_member": target.is_some() }),
+    )
+    .await;
+
+    Ok(ban)
+}
+
+/// Whether `user_id` is banned from the server
+pub async fn is_banned(db: &DynamoClient, server_id: &str, user_id: &str) -> Result<bool, (u16, String)> {
     let result = db
         .get_item()
-        .table_name(get_table("MEMBERS_TABLE"))
+        .table_name(get_table("BANS_TABLE"))
         .key("server_id", AttributeValue::S(server_id.to_string()))
         .key("user_id", AttributeValue::S(user_id.to_string()))
+        .projection_expression("user_id")
         .send()
         .await
         .map_err(|e| (500, format!("Database error: {}", e)))?;
 
-    if result.item().is_none() {
-        return Err((403, "You are not a member of this server".to_string()));
+    Ok(result.item().is_some())
+}
+
+// ============ Integrity ============
+
+/// Check that the server has exactly one owner member and that it matches
+/// the server record's `owner_id`.
+pub async fn verify_single_owner(
+    db: &DynamoClient,
+    server_id: &str,
+) -> Result<OwnerIntegrity, (u16, String)> {
+    let server = get_server_record(db, server_id).await?;
+    let members = query_members(db, server_id, false).await?;
+
+    let owner_member_ids: Vec<String> = members
+        .iter()
+        .filter(|m| m.role == "owner")
+        .map(|m| m.user_id.clone())
+        .collect();
+
+    let mut anomalies = Vec::new();
+    match owner_member_ids.len() {
+        0 => anomalies.push("Server has no owner member".to_string()),
+        1 => {}
+        n => anomalies.push(format!("Server has {} owner members", n)),
+    }
+    if !owner_member_ids.contains(&server.owner_id) {
+        anomalies.push("Server owner_id does not match an owner member".to_string());
     }
 
-    Ok(())
+    if !anomalies.is_empty() {
+        tracing::warn!(server_id = %server_id, anomalies = ?anomalies, "Owner invariant violated");
+    }
+
+    Ok(OwnerIntegrity {
+        server_id: server_id.to_string(),
+        owner_id: server.owner_id,
+        owner_count: owner_member_ids.len(),
+        owner_member_ids,
+        healthy: anomalies.is_empty(),
+        anomalies,
+    })
 }
 
-async fn get_member_role(
+pub async fn get_integrity(
     db: &DynamoClient,
     server_id: &str,
     user_id: &str,
-) -> Result<String, (u16, String)> {
+) -> Result<OwnerIntegrity, (u16, String)> {
+    let role = get_member_role(db, server_id, user_id).await?;
+    if role != "owner" && role != "admin" {
+        return Err((403, "Only owners and admins can check server integrity".to_string()));
+    }
+
+    verify_single_owner(db, server_id).await
+}
+
+/// Restore an owner when none exists by promoting the earliest-joined member
+/// and pointing the server record at them. A server that already has an
+/// owner is left untouched.
+pub async fn repair_owner(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+) -> Result<OwnerIntegrity, (u16, String)> {
+    let role = get_member_role(db, server_id, user_id).await?;
+    if role != "owner" && role != "admin" {
+        return Err((403, "Only owners and admins can repair a server".to_string()));
+    }
+
+    let integrity = verify_single_owner(db, server_id).await?;
+    if integrity.owner_count > 0 {
+        return Ok(integrity);
+    }
+
+    let members = query_members(db, server_id, false).await?;
+    let new_owner = members
+        .iter()
+        .min_by_key(|m| m.joined_at)
+        .ok_or((409, "Server has no members to promote".to_string()))?;
+
+    db.update_item()
+        .table_name(get_table("MEMBERS_TABLE"))
+        .key("server_id", AttributeValue::S(server_id.to_string()))
+        .key("user_id", AttributeValue::S(new_owner.user_id.clone()))
+        .update_expression("SET #r = :owner")
+        .expression_attribute_names("#r", "role")
+        .expression_attribute_values(":owner", AttributeValue::S("owner".to_string()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to promote member: {}", e)))?;
+
+    db.update_item()
+        .table_name(get_table("SERVERS_TABLE"))
+        .key("id", AttributeValue::S(server_id.to_string()))
+        .update_expression("SET owner_id = :uid")
+        .expression_attribute_values(":uid", AttributeValue::S(new_owner.user_id.clone()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to update server owner: {}", e)))?;
+
+    tracing::info!(server_id = %server_id, new_owner = %new_owner.user_id, "Promoted member to owner");
+
+    verify_single_owner(db, server_id).await
+}
+
+// ============ Helpers ============
+
+pub async fn get_server_record(db: &DynamoClient, server_id: &str) -> Result<Server, (u16, String)> {
+    read_server_record(db, server_id, false).await
+}
+
+async fn read_server_record(db: &DynamoClient, server_id: &str, consistent: bool) -> Result<Server, (u16, String)> {
     let result = db
         .get_item()
-        .table_name(get_table("MEMBERS_TABLE"))
+        .table_name(get_table("SERVERS_TABLE"))
+        .key("id", AttributeValue::S(server_id.to_string()))
+        .consistent_read(consistent)
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+    result
+        .item()
+        .and_then(parse_server)
+        .ok_or((404, "Server not found".to_string()))
+}
+
+pub async fn get_channel_record(
+    db: &DynamoClient,
+    server_id: &str,
+    channel_id: &str,
+) -> Result<Channel, (u16, String)> {
+    let result = db
+        .get_item()
+        .table_name(get_table("CHANNELS_TABLE"))
         .key("server_id", AttributeValue::S(server_id.to_string()))
-        .key("user_id", AttributeValue::S(user_id.to_string()))
+        .key("id", AttributeValue::S(channel_id.to_string()))
         .send()
         .await
         .map_err(|e| (500, format!("Database error: {}", e)))?;
 
     result
         .item()
-        .and_then(|item| item.get("role")?.as_s().ok().cloned())
+        .and_then(parse_channel)
+        .ok_or((404, "Channel not found".to_string()))
+}
+
+/// Fetch servers by id with BatchGetItem. Missing servers are skipped.
+pub async fn batch_get_servers(db: &DynamoClient, server_ids: &[String]) -> Result<Vec<Server>, (u16, String)> {
+    let keys = server_ids
+        .iter()
+        .map(|id| HashMap::from([("id".to_string(), AttributeValue::S(id.clone()))]))
+        .collect();
+    let items = db::batch_get(db, &get_table("SERVERS_TABLE"), keys, None, "servers").await?;
+    Ok(items.iter().filter_map(parse_server).collect())
+}
+
+/// Which of `user_ids` are members of the server, via BatchGetItem
+pub async fn batch_find_members(
+    db: &DynamoClient,
+    server_id: &str,
+    user_ids: &[String],
+) -> Result<HashSet<String>, (u16, String)> {
+    let keys = user_ids
+        .iter()
+        .map(|id| {
+            HashMap::from([
+                ("server_id".to_string(), AttributeValue::S(server_id.to_string())),
+                ("user_id".to_string(), AttributeValue::S(id.clone())),
+            ])
+        })
+        .collect();
+    let items = db::batch_get(db, &get_table("MEMBERS_TABLE"), keys, Some("user_id"), "members").await?;
+    Ok(items
+        .iter()
+        .filter_map(|item| item.get("user_id")?.as_s().ok().cloned())
+        .collect())
+}
+
+async fn count_members(db: &DynamoClient, server_id: &str, consistent: bool) -> Result<usize, (u16, String)> {
+    let mut count = 0;
+    let mut start_key = None;
+
+    loop {
+        let result = db
+            .query()
+            .table_name(get_table("MEMBERS_TABLE"))
+            .key_condition_expression("server_id = :sid")
+            .expression_attribute_values(":sid", AttributeValue::S(server_id.to_string()))
+            .select(aws_sdk_dynamodb::types::Select::Count)
+            .consistent_read(consistent)
+            .set_exclusive_start_key(start_key)
+            .send()
+            .await
+            .map_err(|e| (500, format!("Failed to count members: {}", e)))?;
+
+        count += result.count() as usize;
+        start_key = result.last_evaluated_key().cloned();
+        if start_key.is_none() {
+            return Ok(count);
+        }
+    }
+}
+
+async fn query_members(db: &DynamoClient, server_id: &str, consistent: bool) -> Result<Vec<Member>, (u16, String)> {
+    let mut members = Vec::new();
+    let mut start_key = None;
+
+    loop {
+        let result = db
+            .query()
+            .table_name(get_table("MEMBERS_TABLE"))
+            .key_condition_expression("server_id = :sid")
+            .expression_attribute_values(":sid", AttributeValue::S(server_id.to_string()))
+            .consistent_read(consistent)
+            .set_exclusive_start_key(start_key)
+            .send()
+            .await
+            .map_err(|e| (500, format!("Failed to list members: {}", e)))?;
+
+        members.extend(result.items().iter().filter_map(parse_member));
+        start_key = result.last_evaluated_key().cloned();
+        if start_key.is_none() {
+            return Ok(members);
+        }
+    }
+}
+
+/// Read a user's membership row in one go (role, custom roles, timeout).
+/// `None` if they aren't a member.
+pub async fn find_membership(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+) -> Result<Option<Member>, (u16, String)> {
+    read_membership(db, server_id, user_id, false).await
+}
+
+async fn read_membership(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+    consistent: bool,
+) -> Result<Option<Member>, (u16, String)> {
+    let result = db
+        .get_item()
+        .table_name(get_table("MEMBERS_TABLE"))
+        .key("server_id", AttributeValue::S(server_id.to_string()))
+        .key("user_id", AttributeValue::S(user_id.to_string()))
+        .consistent_read(consistent)
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+    Ok(result.item().and_then(parse_member))
+}
+
+/// Like `find_membership`, but non-members get a 403
+pub async fn load_membership(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+) -> Result<Member, (u16, String)> {
+    find_membership(db, server_id, user_id)
+        .await?
         .ok_or((403, "You are not a member of this server".to_string()))
 }
 
+async fn check_membership(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+) -> Result<(), (u16, String)> {
+    load_membership(db, server_id, user_id).await.map(|_| ())
+}
+
+async fn get_member_role(
+    db: &DynamoClient,
+    server_id: &str,
+    user_id: &str,
+) -> Result<String, (u16, String)> {
+    load_membership(db, server_id, user_id).await.map(|m| m.role)
+}
+
 fn parse_server(item: &std::collections::HashMap<String, AttributeValue>) -> Option<Server> {
     Some(Server {
         id: item.get("id")?.as_s().ok()?.clone(),
         name: item.get("name")?.as_s().ok()?.clone(),
         owner_id: item.get("owner_id")?.as_s().ok()?.clone(),
         icon_url: item.get("icon_url").and_then(|v| v.as_s().ok().cloned()),
+        banner_url: item.get("banner_url").and_then(|v| v.as_s().ok().cloned()),
+        accent_color: item.get("accent_color").and_then(|v| v.as_s().ok().cloned()),
         created_at: item.get("created_at")?.as_n().ok()?.parse().ok()?,
+        trim_messages: item
+            .get("trim_messages")
+            .and_then(|v| v.as_bool().ok())
+            .copied()
+            .unwrap_or(true),
+        join_policy: item
+            .get("join_policy")
+            .and_then(|v| v.as_s().ok().cloned())
+            .unwrap_or_else(default_join_policy),
+        invalidate_invites_on_creator_leave: item
+            .get("invalidate_invites_on_creator_leave")
+            .and_then(|v| v.as_bool().ok())
+            .copied()
+            .unwrap_or(false),
+        exclude_code_from_length: item
+            .get("exclude_code_from_length")
+            .and_then(|v| v.as_bool().ok())
+            .copied()
+            .unwrap_or(false),
+        welcome_message: item.get("welcome_message").and_then(|v| v.as_s().ok().cloned()),
     })
 }
 
@@ -404,6 +1631,24 @@ fn parse_channel(item: &std::collections::HashMap<String, AttributeValue>) -> Op
         name: item.get("name")?.as_s().ok()?.clone(),
         channel_type: item.get("channel_type")?.as_s().ok()?.clone(),
         created_at: item.get("created_at")?.as_n().ok()?.parse().ok()?,
+        text_in_voice: item
+            .get("text_in_voice")
+            .and_then(|v| v.as_bool().ok())
+            .copied()
+            .unwrap_or(false),
+        last_message_at: item
+            .get("last_message_at")
+            .and_then(|v| v.as_n().ok())
+            .and_then(|n| n.parse().ok()),
+        position: item
+            .get("position")
+            .and_then(|v| v.as_n().ok())
+            .and_then(|n| n.parse().ok()),
+        read_only: item
+            .get("read_only")
+            .and_then(|v| v.as_bool().ok())
+            .copied()
+            .unwrap_or(false),
     })
 }
 
@@ -414,5 +1659,226 @@ fn parse_member(item: &std::collections::HashMap<String, AttributeValue>) -> Opt
         username: item.get("username")?.as_s().ok()?.clone(),
         role: item.get("role")?.as_s().ok()?.clone(),
         joined_at: item.get("joined_at")?.as_n().ok()?.parse().ok()?,
+        role_ids: roles::parse_role_ids(item),
+        timeout_until: item
+            .get("timeout_until")
+            .and_then(|v| v.as_n().ok())
+            .and_then(|n| n.parse().ok()),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_dynamodb::operation::batch_get_item::{BatchGetItemError, BatchGetItemOutput};
+    use aws_sdk_dynamodb::operation::query::QueryOutput;
+    use aws_sdk_dynamodb::types::error::ResourceNotFoundException;
+    use aws_smithy_mocks::{mock, mock_client, MockResponse, RuleMode};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    const MEMBERSHIPS: usize = 250;
+
+    fn server_ids() -> Vec<String> {
+        (0..MEMBERSHIPS).map(|i| format!("server-{}", i)).collect()
+    }
+
+    fn memberships_rule() -> aws_smithy_mocks::Rule {
+        mock!(aws_sdk_dynamodb::Client::query).then_output(|| {
+            let items = server_ids()
+                .into_iter()
+                .map(|id| HashMap::from([("server_id".to_string(), AttributeValue::S(id))]))
+                .collect();
+            QueryOutput::builder().set_items(Some(items)).build()
+        })
+    }
+
+    /// The requested servers' ids, in request order
+    fn requested_ids(input: &aws_sdk_dynamodb::operation::batch_get_item::BatchGetItemInput) -> Vec<String> {
+        input
+            .request_items()
+            .and_then(|r| r.values().next())
+            .map(|k| k.keys().iter().filter_map(|key| key.get("id")?.as_s().ok().cloned()).collect())
+            .unwrap_or_default()
+    }
+
+    fn servers_output(ids: &[String]) -> BatchGetItemOutput {
+        let items = ids
+            .iter()
+            .map(|id| {
+                HashMap::from([
+                    ("id".to_string(), AttributeValue::S(id.clone())),
+                    ("name".to_string(), AttributeValue::S(format!("name of {}", id))),
+                    ("owner_id".to_string(), AttributeValue::S("owner".to_string())),
+                    ("created_at".to_string(), AttributeValue::N("1".to_string())),
+                ])
+            })
+            .collect();
+        BatchGetItemOutput::builder()
+            .responses(get_table("SERVERS_TABLE"), items)
+            .build()
+    }
+
+    #[tokio::test]
+    async fn list_user_servers_batches_in_chunks_of_100() {
+        let chunk_sizes = Arc::new(Mutex::new(Vec::new()));
+        let recorded = chunk_sizes.clone();
+        let batch_rule = mock!(aws_sdk_dynamodb::Client::batch_get_item).then_compute_output(move |input| {
+            let ids = requested_ids(input);
+            recorded.lock().unwrap().push(ids.len());
+            servers_output(&ids)
+        });
+        let memberships = memberships_rule();
+        let db = mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&memberships, &batch_rule]);
+
+        let servers = list_user_servers(&db, "user").await.unwrap();
+
+        assert_eq!(servers.len(), MEMBERSHIPS);
+        let ids: HashSet<String> = servers.into_iter().map(|s| s.id).collect();
+        assert_eq!(ids, server_ids().into_iter().collect());
+        assert_eq!(*chunk_sizes.lock().unwrap(), vec![100, 100, 50]);
+    }
+
+    #[tokio::test]
+    async fn list_user_servers_skips_a_failed_batch() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let batch_rule = mock!(aws_sdk_dynamodb::Client::batch_get_item).then_compute_response(move |input| {
+            if counter.fetch_add(1, Ordering::SeqCst) == 1 {
+                MockResponse::Error(BatchGetItemError::ResourceNotFoundException(
+                    ResourceNotFoundException::builder().message("gone").build(),
+                ))
+            } else {
+                MockResponse::Output(servers_output(&requested_ids(input)))
+            }
+        });
+        let memberships = memberships_rule();
+        let db = mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&memberships, &batch_rule]);
+
+        let servers = list_user_servers(&db, "user").await.unwrap();
+
+        // The second chunk of 100 failed; the other two still load
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(servers.len(), MEMBERSHIPS - 100);
+    }
+
+    #[test]
+    fn repaired_default_channel_id_is_a_valid_channel_id() {
+        let server_id = "6f1c2a4e-8b3d-4f7a-9c21-0d5e6b7a8c9f";
+        let id = default_channel_id(server_id);
+        assert!(crate::path_params::is_valid("channel_id", &id));
+        assert_eq!(id, default_channel_id(server_id));
+        assert_ne!(id, default_channel_id("0a9b8c7d-6e5f-4a3b-8c2d-1e0f9a8b7c6d"));
+    }
+
+    fn channel(id: &str) -> Channel {
+        Channel {
+            id: id.to_string(),
+            server_id: "server-1".to_string(),
+            name: id.to_string(),
+            channel_type: CHANNEL_TYPE_TEXT.to_string(),
+            created_at: 0,
+            text_in_voice: false,
+            last_message_at: None,
+            position: None,
+            read_only: false,
+        }
+    }
+
+    fn ids(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn reorder_accepts_any_permutation() {
+        let channels = vec![channel("a"), channel("b"), channel("c")];
+        assert!(check_reorder(&ids(&["c", "a", "b"]), &channels).is_ok());
+        assert!(check_reorder(&ids(&["a", "b", "c"]), &channels).is_ok());
+    }
+
+    #[test]
+    fn reorder_rejects_duplicates_missing_and_unknown_ids() {
+        let channels = vec![channel("a"), channel("b"), channel("c")];
+        let duplicate = check_reorder(&ids(&["a", "a", "b", "c"]), &channels).unwrap_err();
+        assert_eq!(duplicate, (400, "channel_ids contains duplicates".to_string()));
+        assert_eq!(check_reorder(&ids(&["a", "b"]), &channels).unwrap_err().0, 400);
+        assert_eq!(check_reorder(&ids(&["a", "b", "c", "d"]), &channels).unwrap_err().0, 400);
+        assert_eq!(check_reorder(&ids(&["a", "b", "d"]), &channels).unwrap_err().0, 400);
+    }
+
+    #[test]
+    fn timeout_duration_bounds_are_inclusive() {
+        assert!(check_timeout_duration(MIN_TIMEOUT_SECS).is_ok());
+        assert!(check_timeout_duration(MAX_TIMEOUT_SECS).is_ok());
+        assert_eq!(check_timeout_duration(MIN_TIMEOUT_SECS - 1).unwrap_err().0, 400);
+        assert_eq!(check_timeout_duration(MAX_TIMEOUT_SECS + 1).unwrap_err().0, 400);
+        assert_eq!(check_timeout_duration(0).unwrap_err().0, 400);
+        assert_eq!(check_timeout_duration(-60).unwrap_err().0, 400);
+    }
+
+    #[tokio::test]
+    async fn ban_checks_the_requester_before_reading_the_body() {
+        use aws_sdk_dynamodb::operation::get_item::GetItemOutput;
+        use aws_sdk_dynamodb::operation::put_item::PutItemOutput;
+
+        let membership = mock!(aws_sdk_dynamodb::Client::get_item).then_output(|| {
+            GetItemOutput::builder()
+                .set_item(Some(HashMap::from([
+                    ("server_id".to_string(), AttributeValue::S("server-1".to_string())),
+                    ("user_id".to_string(), AttributeValue::S("user-1".to_string())),
+                    ("username".to_string(), AttributeValue::S("user".to_string())),
+                    ("role".to_string(), AttributeValue::S("member".to_string())),
+                    ("joined_at".to_string(), AttributeValue::N("0".to_string())),
+                ])))
+                .build()
+        });
+        let puts = mock!(aws_sdk_dynamodb::Client::put_item).then_output(|| PutItemOutput::builder().build());
+        let db = mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&membership, &puts]);
+
+        let too_long = format!(r#"{{"user_id":"user-2","reason":"{}"}}"#, "x".repeat(MAX_BAN_REASON_CHARS + 1));
+        for body in ["not json", too_long.as_str()] {
+            let err = ban_member(&db, "server-1", "user-1", body).await.unwrap_err();
+            assert_eq!(err, (403, "Only the owner or an admin can ban members".to_string()));
+        }
+        assert_eq!(puts.num_calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn summaries_follow_every_page_of_memberships() {
+        let membership = |id: &str, role: &str| {
+            HashMap::from([
+                ("server_id".to_string(), AttributeValue::S(id.to_string())),
+                ("role".to_string(), AttributeValue::S(role.to_string())),
+            ])
+        };
+        let queries = mock!(aws_sdk_dynamodb::Client::query).then_compute_output(move |input| {
+            if input.index_name() != Some("user-servers-index") {
+                return QueryOutput::builder().count(3).build();
+            }
+            match input.exclusive_start_key() {
+                None => QueryOutput::builder()
+                    .items(membership("server-a", "owner"))
+                    .items(membership("server-b", "member"))
+                    .last_evaluated_key("server_id", AttributeValue::S("server-b".to_string()))
+                    .build(),
+                Some(_) => QueryOutput::builder().items(membership("server-c", "admin")).build(),
+            }
+        });
+        let batches =
+            mock!(aws_sdk_dynamodb::Client::batch_get_item).then_compute_output(|input| servers_output(&requested_ids(input)));
+        let db = mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&queries, &batches]);
+
+        let summaries = list_server_summaries(&db, "user").await.unwrap();
+
+        let roles: Vec<(String, String, usize)> =
+            summaries.into_iter().map(|s| (s.id, s.my_role, s.member_count)).collect();
+        assert_eq!(
+            roles,
+            vec![
+                ("server-a".to_string(), "owner".to_string(), 3),
+                ("server-b".to_string(), "member".to_string(), 3),
+                ("server-c".to_string(), "admin".to_string(), 3),
+            ]
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use uuid::Uuid;
+
+use crate::auth::{self, Claims};
+use crate::clock;
+
+// Every login or registration opens a session, and its id is embedded in the
+// access token as `sid`. Revoking a session deletes its row, and tokens whose
+// session row is gone are refused. Tokens issued before sessions existed have
+// no `sid` and are accepted until they expire.
+
+#[derive(Debug, Serialize)]
+pub struct Session {
+    pub id: String,
+    pub created_at: i64,
+    pub last_used_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// The session the request was made with
+    pub current: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RevokeSessionsResponse {
+    pub revoked: usize,
+}
+
+/// Only write `last_used_at` when it's at least this stale, so an active
+/// client doesn't cost a write per request
+const LAST_USED_RESOLUTION_SECS: i64 = 300;
+const MAX_USER_AGENT_CHARS: usize = 256;
+
+fn get_table(name: &str) -> String {
+    env::var(name).unwrap_or_else(|_| {
+        format!(
+            "agorusta-{}-dev",
+            name.to_lowercase().replace("_table", "s")
+        )
+    })
+}
+
+/// Open a session for a fresh login and return its id. The row expires with
+/// the login's refresh token.
+pub async fn create_session(
+    db: &DynamoClient,
+    user_id: &str,
+    ip: Option<&str>,
+    user_agent: Option<&str>,
+) -> Result<String, (u16, String)> {
+    let session_id = Uuid::new_v4().to_string();
+    let now = clock::now_secs();
+
+    let mut put = db
+        .put_item()
+        .table_name(get_table("SESSIONS_TABLE"))
+        .item("user_id", AttributeValue::S(user_id.to_string()))
+        .item("session_id", AttributeValue::S(session_id.clone()))
+        .item("created_at", AttributeValue::N(now.to_string()))
+        .item("last_used_at", AttributeValue::N(now.to_string()))
+        .item("ttl", AttributeValue::N((now + auth::get_refresh_token_ttl()).to_string()));
+    if let Some(ip) = ip {
+        put = put.item("ip", AttributeValue::S(ip.to_string()));
+    }
+    if let Some(user_agent) = user_agent {
+        let user_agent: String = user_agent.chars().take(MAX_USER_AGENT_CHARS).collect();
+        put = put.item("user_agent", AttributeValue::S(user_agent));
+    }
+
+    put.send()
+        .await
+        .map_err(|e| (500, format!("Failed to create session: {}", e)))?;
+
+    Ok(session_id)
+}
+
+/// Refuse a token whose session was revoked, and note that the session is
+/// still in use
+pub async fn check_session(db: &DynamoClient, claims: &Claims) -> Result<(), (u16, String)> {
+    let Some(session_id) = &claims.sid else {
+        return Ok(());
+    };
+
+    let result = db
+        .get_item()
+        .table_name(get_table("SESSIONS_TABLE"))
+        .key("user_id", AttributeValue::S(claims.sub.clone()))
+        .key("session_id", AttributeValue::S(session_id.clone()))
+        .projection_expression("last_used_at")
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+    let item = result
+        .item()
+        .ok_or((401, "Session has been revoked".to_string()))?;
+
+    let last_used_at: i64 = item
+        .get("last_used_at")
+        .and_then(|v| v.as_n().ok())
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0);
+    let now = clock::now_secs();
+    if now - last_used_at >= LAST_USED_RESOLUTION_SECS {
+        let result = db
+            .update_item()
+            .table_name(get_table("SESSIONS_TABLE"))
+            .key("user_id", AttributeValue::S(claims.sub.clone()))
+            .key("session_id", AttributeValue::S(session_id.clone()))
+            .update_expression("SET last_used_at = :now")
+            .condition_expression("attribute_exists(session_id)")
+            .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+            .send()
+            .await;
+        if let Err(e) = result {
+            tracing::warn!(error = %e, "Failed to update session last_used_at");
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the session is still open, i.e. not revoked or expired
+pub async fn session_exists(db: &DynamoClient, user_id: &str, session_id: &str) -> Result<bool, (u16, String)> {
+    let result = db
+        .get_item()
+        .table_name(get_table("SESSIONS_TABLE"))
+        .key("user_id", AttributeValue::S(user_id.to_string()))
+        .key("session_id", AttributeValue::S(session_id.to_string()))
+        .projection_expression("#ttl")
+        .expression_attribute_names("#ttl", "ttl")
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+    let Some(item) = result.item() else {
+        return Ok(false);
+    };
+    let expires_at = item
+        .get("ttl")
+        .and_then(|v| v.as_n().ok())
+        .and_then(|n| n.parse().ok());
+    Ok(!clock::is_expired(expires_at, clock::now_secs()))
+}
+
+async fn query_sessions(db: &DynamoClient, user_id: &str) -> Result<Vec<HashMap<String, AttributeValue>>, (u16, String)> {
+    let mut items = Vec::new();
+    let mut start_key: Option<HashMap<String, AttributeValue>> = None;
+
+    loop {
+        let result = db
+            .query()
+            .table_name(get_table("SESSIONS_TABLE"))
+            .key_condition_expression("user_id = :uid")
+            .expression_attribute_values(":uid", AttributeValue::S(user_id.to_string()))
+            .set_exclusive_start_key(start_key.take())
+            .send()
+            .await
+            .map_err(|e| (500, format!("Failed to list sessions: {}", e)))?;
+
+        items.extend(result.items().iter().cloned());
+
+        start_key = result.last_evaluated_key().cloned();
+        if start_key.is_none() {
+            break;
+        }
+    }
+
+    // TTL deletion lags; hide sessions whose token has already expired
+    let now = clock::now_secs();
+    items.retain(|item| {
+        item.get("ttl")
+            .and_then(|v| v.as_n().ok())
+            .and_then(|n| n.parse::<i64>().ok())
+            .is_none_or(|ttl| ttl > now)
+    });
+    Ok(items)
+}
+
+/// The caller's active sessions, most recently used first
+pub async fn list_sessions(db: &DynamoClient, claims: &Claims) -> Result<Vec<Session>, (u16, String)> {
+    let mut sessions: Vec<Session> = query_sessions(db, &claims.sub)
+        .await?
+        .iter()
+        .filter_map(|item| parse_session(item, claims.sid.as_deref()))
+        .collect();
+    sessions.sort_by_key(|s| std::cmp::Reverse(s.last_used_at));
+    Ok(sessions)
+}
+
+/// End one of the caller's sessions. Its tokens stop working immediately.
+pub async fn revoke_session(db: &DynamoClient, user_id: &str, session_id: &str) -> Result<(), (u16, String)> {
+    let result = db
+        .delete_item()
+        .table_name(get_table("SESSIONS_TABLE"))
+        .key("user_id", AttributeValue::S(user_id.to_string()))
+        .key("session_id", AttributeValue::S(session_id.to_string()))
+        .condition_expression("attribute_exists(session_id)")
+        .send()
+        .await;
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let missing = e
+                .as_service_error()
+                .map(|se| se.is_conditional_check_failed_exception())
+                .unwrap_or(false);
+            if missing {
+                Err((404, "Session not found".to_string()))
+            } else {
+                Err((500, format!("Failed to revoke session: {}", e)))
+            }
+        }
+    }
+}
+
+/// End every session except the one making the request
+pub async fn revoke_other_sessions(
+    db: &DynamoClient,
+    claims: &Claims,
+) -> Result<RevokeSessionsResponse, (u16, String)> {
+    let mut revoked = 0;
+    for item in query_sessions(db, &claims.sub).await? {
+        let Some(session_id) = item.get("session_id").and_then(|v| v.as_s().ok()) else {
+            continue;
+        };
+        if Some(session_id.as_str()) == claims.sid.as_deref() {
+            continue;
+        }
+        match revoke_session(db, &claims.sub, session_id).await {
+            Ok(()) => revoked += 1,
+            // Already gone, e.g. revoked concurrently
+            Err((404, _)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(RevokeSessionsResponse { revoked })
+}
+
+/// End all of a user's sessions, e.g. after their password was reset
+pub async fn revoke_all_sessions(db: &DynamoClient, user_id: &str) -> Result<usize, (u16, String)> {
+    let mut revoked = 0;
+    for item in query_sessions(db, user_id).await? {
+        let Some(session_id) = item.get("session_id").and_then(|v| v.as_s().ok()) else {
+            continue;
+        };
+        match revoke_session(db, user_id, session_id).await {
+            Ok(()) => revoked += 1,
+            Err((404, _)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(revoked)
+}
+
+fn parse_session(item: &HashMap<String, AttributeValue>, current_sid: Option<&str>) -> Option<Session> {
+    let id = item.get("session_id")?.as_s().ok()?.clone();
+    Some(Session {
+        current: current_sid == Some(id.as_str()),
+        id,
+        created_at: item.get("created_at")?.as_n().ok()?.parse().ok()?,
+        last_used_at: item.get("last_used_at")?.as_n().ok()?.parse().ok()?,
+        ip: item.get("ip").and_then(|v| v.as_s().ok().cloned()),
+        user_agent: item.get("user_agent").and_then(|v| v.as_s().ok().cloned()),
+    })
+}
@@ -0,0 +1,286 @@
+use aws_sdk_dynamodb::types::{AttributeValue, Select};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::env;
+
+use crate::clock;
+use crate::dms::{self, DirectMessage};
+use crate::messages::{self, Message};
+use crate::servers::get_channel_record;
+
+// Starred messages are a private saved list per user, across channels and
+// DMs. Like highlights, rows only reference the message and content is read
+// on listing. Access is re-checked then too, so stars in a server the user
+// has left or a conversation they're no longer in just drop out.
+
+/// Where the starred message lives, sent when starring
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum StarRequest {
+    Channel { server_id: String, channel_id: String },
+    Dm { conversation_id: String },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StarredMessage {
+    Channel {
+        server_id: String,
+        message: Message,
+        starred_at: i64,
+    },
+    Dm {
+        message: DirectMessage,
+        starred_at: i64,
+    },
+}
+
+impl StarredMessage {
+    fn starred_at(&self) -> i64 {
+        match self {
+            StarredMessage::Channel { starred_at, .. } | StarredMessage::Dm { starred_at, .. } => *starred_at,
+        }
+    }
+}
+
+/// Stored reference to a starred message
+struct StarRef {
+    location: StarRequest,
+    message_id: String,
+    starred_at: i64,
+}
+
+const MAX_STARRED_PER_USER: usize = 200;
+/// Access checks and message lookups in flight at once when listing
+const HYDRATE_CONCURRENCY: usize = 8;
+
+fn get_table(name: &str) -> String {
+    env::var(name).unwrap_or_else(|_| {
+        format!(
+            "agorusta-{}-dev",
+            name.to_lowercase().replace("_table", "s")
+        )
+    })
+}
+
+async fn count_starred(db: &DynamoClient, user_id: &str) -> Result<usize, (u16, String)> {
+    let result = db
+        .query()
+        .table_name(get_table("STARRED_TABLE"))
+        .key_condition_expression("user_id = :uid")
+        .expression_attribute_values(":uid", AttributeValue::S(user_id.to_string()))
+        .select(Select::Count)
+        .send()
+        .await
+        .map_err(|e| (500, format!("Database error: {}", e)))?;
+
+    Ok(result.count() as usize)
+}
+
+/// Star a message the user can read. Starring it again is a no-op.
+pub async fn star_message(
+    db: &DynamoClient,
+    message_id: &str,
+    user_id: &str,
+    body: &str,
+) -> Result<StarredMessage, (u16, String)> {
+    let req: StarRequest = serde_json::from_str(body)
+        .map_err(|e| (400, format!("Invalid request: {}", e)))?;
+
+    let not_found = || (404, "Message not found".to_string());
+    let starred_at = clock::now_secs();
+    let starred = match &req {
+        StarRequest::Channel { server_id, channel_id } => {
+            messages::check_membership(db, server_id, user_id).await?;
+            get_channel_record(db, server_id, channel_id).await?;
+            let message = messages::find_message(db, channel_id, message_id)
+                .await?
+                .ok_or_else(not_found)?;
+            StarredMessage::Channel { server_id: server_id.clone(), message, starred_at }
+        }
+        StarRequest::Dm { conversation_id } => {
+            dms::verify_participant(db, conversation_id, user_id).await?;
+            let message = dms::find_dm_message(db, conversation_id, message_id)
+                .await?
+                .ok_or_else(not_found)?;
+            StarredMessage::Dm { message, starred_at }
+        }
+    };
+
+    if count_starred(db, user_id).await? >= MAX_STARRED_PER_USER {
+        return Err((
+            400,
+            format!("You cannot star more than {} messages", MAX_STARRED_PER_USER),
+        ));
+    }
+
+    let mut put = db
+        .put_item()
+        .table_name(get_table("STARRED_TABLE"))
+        .item("user_id", AttributeValue::S(user_id.to_string()))
+        .item("message_id", AttributeValue::S(message_id.to_string()))
+        .item("starred_at", AttributeValue::N(starred_at.to_string()))
+        .condition_expression("attribute_not_exists(message_id)");
+    put = match &req {
+        StarRequest::Channel { server_id, channel_id } => put
+            .item("source_type", AttributeValue::S("channel".to_string()))
+            .item("server_id", AttributeValue::S(server_id.clone()))
+            .item("channel_id", AttributeValue::S(channel_id.clone())),
+        StarRequest::Dm { conversation_id } => put
+            .item("source_type", AttributeValue::S("dm".to_string()))
+            .item("conversation_id", AttributeValue::S(conversation_id.clone())),
+    };
+
+    if let Err(e) = put.send().await {
+        let exists = e
+            .as_service_error()
+            .map(|se| se.is_conditional_check_failed_exception())
+            .unwrap_or(false);
+        if !exists {
+            return Err((500, format!("Failed to star message: {}", e)));
+        }
+    }
+
+    Ok(starred)
+}
+
+pub async fn unstar_message(
+    db: &DynamoClient,
+    message_id: &str,
+    user_id: &str,
+) -> Result<(), (u16, String)> {
+    db.delete_item()
+        .table_name(get_table("STARRED_TABLE"))
+        .key("user_id", AttributeValue::S(user_id.to_string()))
+        .key("message_id", AttributeValue::S(message_id.to_string()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to unstar message: {}", e)))?;
+
+    Ok(())
+}
+
+/// Lost access (403) or a deleted channel (404) hides a star rather than
+/// failing the list
+fn skip_if_inaccessible<T>(result: Result<T, (u16, String)>) -> Result<Option<T>, (u16, String)> {
+    match result {
+        Ok(v) => Ok(Some(v)),
+        Err((403, _)) | Err((404, _)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// The user's starred messages with full content, most recently starred
+/// first. Messages that were deleted or that the user can no longer read
+/// are skipped.
+pub async fn list_starred(db: &DynamoClient, user_id: &str) -> Result<Vec<StarredMessage>, (u16, String)> {
+    let result = db
+        .query()
+        .table_name(get_table("STARRED_TABLE"))
+        .key_condition_expression("user_id = :uid")
+        .expression_attribute_values(":uid", AttributeValue::S(user_id.to_string()))
+        .limit(MAX_STARRED_PER_USER as i32)
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to list starred messages: {}", e)))?;
+
+    let refs: Vec<StarRef> = result.items().iter().filter_map(parse_star_ref).collect();
+
+    // Check each server and conversation once, however many stars it has
+    let mut scopes: HashMap<String, bool> = HashMap::new();
+    for r in &refs {
+        match &r.location {
+            StarRequest::Channel { server_id, .. } => scopes.insert(server_id.clone(), false),
+            StarRequest::Dm { conversation_id } => scopes.insert(conversation_id.clone(), true),
+        };
+    }
+    let checks: Vec<_> = stream::iter(scopes)
+        .map(|(scope_id, is_dm)| async move {
+            let result = if is_dm {
+                skip_if_inaccessible(dms::verify_participant(db, &scope_id, user_id).await).map(|c| c.is_some())
+            } else {
+                skip_if_inaccessible(messages::check_membership(db, &scope_id, user_id).await).map(|c| c.is_some())
+            };
+            (scope_id, result)
+        })
+        .buffer_unordered(HYDRATE_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut accessible: HashSet<String> = HashSet::new();
+    for (scope_id, result) in checks {
+        if result? {
+            accessible.insert(scope_id);
+        }
+    }
+
+    let visible: Vec<StarRef> = refs
+        .into_iter()
+        .filter(|r| {
+            let scope = match &r.location {
+                StarRequest::Channel { server_id, .. } => server_id,
+                StarRequest::Dm { conversation_id } => conversation_id,
+            };
+            accessible.contains(scope)
+        })
+        .collect();
+
+    let hydrated: Vec<Result<Option<StarredMessage>, (u16, String)>> = stream::iter(visible)
+        .map(|r| async move {
+            match r.location {
+                StarRequest::Channel { server_id, channel_id } => {
+                    // The channel may have been deleted since
+                    if skip_if_inaccessible(get_channel_record(db, &server_id, &channel_id).await)?.is_none() {
+                        return Ok(None);
+                    }
+                    let message = messages::find_message(db, &channel_id, &r.message_id).await?;
+                    Ok(message.map(|message| StarredMessage::Channel {
+                        server_id,
+                        message,
+                        starred_at: r.starred_at,
+                    }))
+                }
+                StarRequest::Dm { conversation_id } => {
+                    let message = dms::find_dm_message(db, &conversation_id, &r.message_id).await?;
+                    Ok(message.map(|message| StarredMessage::Dm {
+                        message,
+                        starred_at: r.starred_at,
+                    }))
+                }
+            }
+        })
+        .buffer_unordered(HYDRATE_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut starred = Vec::new();
+    for result in hydrated {
+        if let Some(item) = result? {
+            starred.push(item);
+        }
+    }
+    starred.sort_by_key(|s| std::cmp::Reverse(s.starred_at()));
+
+    Ok(starred)
+}
+
+fn parse_star_ref(item: &HashMap<String, AttributeValue>) -> Option<StarRef> {
+    let string = |key: &str| item.get(key).and_then(|v| v.as_s().ok()).cloned();
+    let location = match string("source_type")?.as_str() {
+        "channel" => StarRequest::Channel {
+            server_id: string("server_id")?,
+            channel_id: string("channel_id")?,
+        },
+        "dm" => StarRequest::Dm {
+            conversation_id: string("conversation_id")?,
+        },
+        _ => return None,
+    };
+    Some(StarRef {
+        location,
+        message_id: string("message_id")?,
+        starred_at: item.get("starred_at")?.as_n().ok()?.parse().ok()?,
+    })
+}
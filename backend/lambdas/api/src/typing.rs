@@ -0,0 +1,156 @@
+use aws_sdk_apigatewaymanagement::Client as ApiGwClient;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use serde::Serialize;
+use std::env;
+
+use crate::broadcast;
+use crate::clock;
+use crate::messages::{check_membership, verify_channel};
+
+// Typing markers are short-lived rows (one per user per channel) so a client
+// opening a channel can see who is already typing. They're broadcast live as
+// `typing_start` / `typing_stop`, and expire on their own if the stop is
+// never sent.
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TypingUser {
+    pub user_id: String,
+    pub username: String,
+    /// Unix ms after which the marker no longer counts
+    pub expires_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct TypingEvent<'a> {
+    user_id: &'a str,
+    username: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<i64>,
+}
+
+/// How long a typing signal lasts; clients re-send while still typing
+const TYPING_TTL_MS: i64 = 6_000;
+
+fn get_table(name: &str) -> String {
+    env::var(name).unwrap_or_else(|_| {
+        format!(
+            "agorusta-{}-dev",
+            name.to_lowercase().replace("_table", "s")
+        )
+    })
+}
+
+/// Mark the user as typing in a channel for the next few seconds
+pub async fn start_typing(
+    db: &DynamoClient,
+    server_id: &str,
+    channel_id: &str,
+    user_id: &str,
+    username: &str,
+) -> Result<TypingUser, (u16, String)> {
+    check_membership(db, server_id, user_id).await?;
+    verify_channel(db, server_id, channel_id).await?;
+
+    let expires_at = clock::now_millis() + TYPING_TTL_MS;
+    db.put_item()
+        .table_name(get_table("TYPING_TABLE"))
+        .item("channel_id", AttributeValue::S(channel_id.to_string()))
+        .item("user_id", AttributeValue::S(user_id.to_string()))
+        .item("username", AttributeValue::S(username.to_string()))
+        .item("expires_at", AttributeValue::N(expires_at.to_string()))
+        // DynamoDB TTL is in seconds and may lag; reads filter on expires_at
+        .item("ttl", AttributeValue::N((expires_at / 1000 + 1).to_string()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to record typing: {}", e)))?;
+
+    Ok(TypingUser {
+        user_id: user_id.to_string(),
+        username: username.to_string(),
+        expires_at,
+    })
+}
+
+/// Clear the user's typing marker, e.g. after sending or clearing the input
+pub async fn stop_typing(
+    db: &DynamoClient,
+    server_id: &str,
+    channel_id: &str,
+    user_id: &str,
+) -> Result<(), (u16, String)> {
+    check_membership(db, server_id, user_id).await?;
+
+    db.delete_item()
+        .table_name(get_table("TYPING_TABLE"))
+        .key("channel_id", AttributeValue::S(channel_id.to_string()))
+        .key("user_id", AttributeValue::S(user_id.to_string()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to clear typing: {}", e)))?;
+
+    Ok(())
+}
+
+/// Who is typing in a channel right now
+pub async fn list_typing(
+    db: &DynamoClient,
+    server_id: &str,
+    channel_id: &str,
+    user_id: &str,
+) -> Result<Vec<TypingUser>, (u16, String)> {
+    check_membership(db, server_id, user_id).await?;
+    verify_channel(db, server_id, channel_id).await?;
+
+    let now = clock::now_millis();
+    let result = db
+        .query()
+        .table_name(get_table("TYPING_TABLE"))
+        .key_condition_expression("channel_id = :cid")
+        .filter_expression("expires_at > :now")
+        .expression_attribute_values(":cid", AttributeValue::S(channel_id.to_string()))
+        .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+        .send()
+        .await
+        .map_err(|e| (500, format!("Failed to list typing users: {}", e)))?;
+
+    Ok(result
+        .items()
+        .iter()
+        .filter_map(|item| {
+            Some(TypingUser {
+                user_id: item.get("user_id")?.as_s().ok()?.clone(),
+                username: item.get("username")?.as_s().ok()?.clone(),
+                expires_at: item.get("expires_at")?.as_n().ok()?.parse().ok()?,
+            })
+        })
+        .collect())
+}
+
+/// Tell the channel's subscribers that someone started or stopped typing
+pub async fn broadcast_typing(
+    db: &DynamoClient,
+    apigw: &ApiGwClient,
+    server_id: &str,
+    channel_id: &str,
+    user_id: &str,
+    username: &str,
+    expires_at: Option<i64>,
+) {
+    let event = if expires_at.is_some() { "typing_start" } else { "typing_stop" };
+    let payload = match broadcast::build_payload(
+        event,
+        None,
+        Some(server_id),
+        Some(channel_id),
+        &TypingEvent { user_id, username, expires_at },
+    ) {
+        Ok(b) => b,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to serialize typing event");
+            return;
+        }
+    };
+
+    broadcast::send_to_subscribers(db, apigw, channel_id, &payload).await;
+}
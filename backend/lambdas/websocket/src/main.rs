@@ -1,7 +1,9 @@
-use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
 use aws_sdk_dynamodb::Client as DynamoClient;
+use jsonwebtoken::errors::ErrorKind;
 use jsonwebtoken::{decode, DecodingKey, Validation};
 use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::env;
 use std::sync::Arc;
@@ -29,6 +31,7 @@ struct RequestContext {
 #[derive(Debug, Deserialize)]
 struct QueryParams {
     token: Option<String>,
+    resume: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -44,6 +47,15 @@ struct Claims {
     email: String,
     username: String,
     exp: usize,
+    #[serde(default)]
+    sid: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResumeClaims {
+    sub: String,
+    channels: Vec<String>,
+    purpose: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -51,8 +63,20 @@ struct WebSocketMessage {
     action: String,
     #[serde(default)]
     channel_id: Option<String>,
+    /// For the bulk subscribe_many/unsubscribe_many actions
+    #[serde(default)]
+    channel_ids: Vec<String>,
+    /// For the subscribe_dm action
+    #[serde(default)]
+    conversation_id: Option<String>,
+    /// Fresh access token for the reauth action
+    #[serde(default)]
+    token: Option<String>,
 }
 
+/// Max channels in one subscribe_many/unsubscribe_many frame
+const MAX_BULK_CHANNELS: usize = shared::limits::MAX_CHANNELS_PER_REQUEST;
+
 struct AppState {
     db: DynamoClient,
 }
@@ -61,6 +85,38 @@ fn get_jwt_secret() -> String {
     env::var("JWT_SECRET").unwrap_or_else(|_| "dev-secret-change-in-production".to_string())
 }
 
+/// `JWT_SECRET` plus any older secrets in `JWT_SECRET_PREVIOUS`
+/// (comma-separated), matching the API so a rotation doesn't drop sockets
+/// opened with tokens signed by the old secret
+fn get_jwt_verification_secrets() -> Vec<String> {
+    let mut secrets = vec![get_jwt_secret()];
+    if let Ok(previous) = env::var("JWT_SECRET_PREVIOUS") {
+        secrets.extend(
+            previous
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string),
+        );
+    }
+    secrets
+}
+
+/// Decode a JWT signed with any accepted secret, only moving on to the next
+/// secret when the signature doesn't match
+fn decode_jwt<T: DeserializeOwned>(token: &str) -> Result<T, jsonwebtoken::errors::Error> {
+    let validation = Validation::default();
+    let mut last_err: jsonwebtoken::errors::Error = ErrorKind::InvalidSignature.into();
+    for secret in get_jwt_verification_secrets() {
+        match decode::<T>(token, &DecodingKey::from_secret(secret.as_bytes()), &validation) {
+            Ok(data) => return Ok(data.claims),
+            Err(e) if matches!(e.kind(), ErrorKind::InvalidSignature) => last_err = e,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last_err)
+}
+
 fn get_table(name: &str) -> String {
     env::var(name).unwrap_or_else(|_| {
         format!(
@@ -70,14 +126,135 @@ fn get_table(name: &str) -> String {
     })
 }
 
+/// Seconds of inactivity before a connection record expires. Every client
+/// message pushes the expiry forward by this much.
+fn connection_ttl_seconds() -> i64 {
+    env::var("CONNECTION_IDLE_TTL_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(86400)
+}
+
+fn next_ttl() -> AttributeValue {
+    AttributeValue::N((chrono::Utc::now().timestamp() + connection_ttl_seconds()).to_string())
+}
+
 fn validate_token(token: &str) -> Result<Claims, String> {
-    decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(get_jwt_secret().as_bytes()),
-        &Validation::default(),
-    )
-    .map(|data| data.claims)
-    .map_err(|e| format!("Invalid token: {}", e))
+    decode_jwt::<Claims>(token).map_err(|e| format!("Invalid token: {}", e))
+}
+
+/// Whether the token's session is still open. Tokens without a session id
+/// predate sessions and are accepted.
+async fn session_active(state: &AppState, claims: &Claims) -> Result<bool, String> {
+    let Some(session_id) = &claims.sid else {
+        return Ok(true);
+    };
+
+    let result = state
+        .db
+        .get_item()
+        .table_name(get_table("SESSIONS_TABLE"))
+        .key("user_id", AttributeValue::S(claims.sub.clone()))
+        .key("session_id", AttributeValue::S(session_id.clone()))
+        .projection_expression("session_id")
+        .send()
+        .await
+        .map_err(|e| format!("Failed to check session: {}", e))?;
+
+    Ok(result.item().is_some())
+}
+
+/// Decode a resume token issued by `POST /ws/resume-token` and return the
+/// channels it encodes. The token must belong to the connecting user.
+fn validate_resume_token(token: &str, user_id: &str) -> Result<Vec<String>, String> {
+    let claims = decode_jwt::<ResumeClaims>(token)
+        .map_err(|e| format!("Invalid resume token: {}", e))?;
+
+    if claims.purpose != "ws_resume" {
+        return Err("Invalid resume token: wrong purpose".to_string());
+    }
+    if claims.sub != user_id {
+        return Err("Invalid resume token: user mismatch".to_string());
+    }
+
+    Ok(claims.channels)
+}
+
+// Presence: one row per user counting their open connections, so closing
+// one tab of several doesn't mark them offline. `last_seen` moves on
+// connect, disconnect and ping. The API treats a count left above zero
+// with no activity for a whole idle TTL as offline, which covers a
+// $disconnect that never arrived.
+
+/// Count a new connection for the user. Best-effort: presence shouldn't
+/// block connecting.
+async fn presence_connected(state: &AppState, user_id: &str) {
+    let result = state
+        .db
+        .update_item()
+        .table_name(get_table("PRESENCE_TABLE"))
+        .key("user_id", AttributeValue::S(user_id.to_string()))
+        .update_expression("ADD connections :one SET last_seen = :now")
+        .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+        .expression_attribute_values(":now", AttributeValue::N(chrono::Utc::now().timestamp().to_string()))
+        .send()
+        .await;
+
+    if let Err(e) = result {
+        tracing::warn!(user_id = %user_id, error = %e, "Failed to record presence");
+    }
+}
+
+/// Count a closed connection. The condition keeps a duplicate or late
+/// $disconnect from taking the count below zero.
+async fn presence_disconnected(state: &AppState, user_id: &str) {
+    let result = state
+        .db
+        .update_item()
+        .table_name(get_table("PRESENCE_TABLE"))
+        .key("user_id", AttributeValue::S(user_id.to_string()))
+        .update_expression("ADD connections :minus_one SET last_seen = :now")
+        .condition_expression("connections > :zero")
+        .expression_attribute_values(":minus_one", AttributeValue::N("-1".to_string()))
+        .expression_attribute_values(":zero", AttributeValue::N("0".to_string()))
+        .expression_attribute_values(":now", AttributeValue::N(chrono::Utc::now().timestamp().to_string()))
+        .send()
+        .await;
+
+    if let Err(e) = result {
+        let at_zero = e
+            .as_service_error()
+            .map(|se| se.is_conditional_check_failed_exception())
+            .unwrap_or(false);
+        if !at_zero {
+            tracing::warn!(user_id = %user_id, error = %e, "Failed to record presence");
+        }
+    }
+}
+
+/// Keep an online user's presence fresh. Only touches an existing row.
+async fn presence_seen(state: &AppState, user_id: &str) {
+    let result = state
+        .db
+        .update_item()
+        .table_name(get_table("PRESENCE_TABLE"))
+        .key("user_id", AttributeValue::S(user_id.to_string()))
+        .update_expression("SET last_seen = :now")
+        .condition_expression("attribute_exists(user_id)")
+        .expression_attribute_values(":now", AttributeValue::N(chrono::Utc::now().timestamp().to_string()))
+        .send()
+        .await;
+
+    if let Err(e) = result {
+        let missing = e
+            .as_service_error()
+            .map(|se| se.is_conditional_check_failed_exception())
+            .unwrap_or(false);
+        if !missing {
+            tracing::warn!(user_id = %user_id, error = %e, "Failed to refresh presence");
+        }
+    }
 }
 
 async fn handle_connect(
@@ -108,9 +285,59 @@ async fn handle_connect(
         }
     };
 
-    // Store connection in DynamoDB with TTL (24 hours)
-    let ttl = chrono::Utc::now().timestamp() + 86400;
+    match session_active(state, &claims).await {
+        Ok(true) => {}
+        Ok(false) => {
+            tracing::warn!(connection_id = %connection_id, user_id = %claims.sub, "Session revoked");
+            return WebSocketResponse {
+                status_code: 401,
+                body: Some(r#"{"error":"unauthorized"}"#.to_string()),
+            };
+        }
+        Err(e) => {
+            tracing::error!(connection_id = %connection_id, error = %e, "Session check failed");
+            return WebSocketResponse {
+                status_code: 500,
+                body: Some(r#"{"error":"internal error"}"#.to_string()),
+            };
+        }
+    }
+
+    // Restore subscriptions from a resume token if one was presented. A bad
+    // or expired token doesn't block the connection; the client just has to
+    // re-subscribe manually.
+    let requested = match query_params.as_ref().and_then(|q| q.resume.as_ref()) {
+        Some(resume) => match validate_resume_token(resume, &claims.sub) {
+            Ok(channels) => channels,
+            Err(e) => {
+                tracing::warn!(connection_id = %connection_id, error = %e, "Ignoring resume token");
+                vec![]
+            }
+        },
+        None => vec![],
+    };
+
+    // The API refuses conversations in resume tokens, but a token only
+    // proves what the client asked for, so hold conversations to the same
+    // participant check as subscribe_dm
+    let mut channels = Vec::with_capacity(requested.len());
+    for id in requested {
+        if !is_conversation_id(&id) {
+            channels.push(id);
+            continue;
+        }
+        match is_participant(state, &id, &claims.sub).await {
+            Ok(true) => channels.push(id),
+            Ok(false) => {
+                tracing::warn!(connection_id = %connection_id, user_id = %claims.sub, conversation_id = %id, "Dropping resumed conversation for non-participant");
+            }
+            Err(e) => {
+                tracing::warn!(connection_id = %connection_id, conversation_id = %id, error = %e, "Dropping resumed conversation; participant check failed");
+            }
+        }
+    }
 
+    // Store connection in DynamoDB with an idle TTL, refreshed on activity
     let result = state
         .db
         .put_item()
@@ -118,16 +345,19 @@ async fn handle_connect(
         .item("connection_id", AttributeValue::S(connection_id.to_string()))
         .item("user_id", AttributeValue::S(claims.sub.clone()))
         .item("email", AttributeValue::S(claims.email.clone()))
-        .item("channels", AttributeValue::Ss(vec![])) // Empty string set initially
-        .item("ttl", AttributeValue::N(ttl.to_string()))
+        .item("channels", AttributeValue::Ss(channels.clone())) // Empty unless resumed
+        .item("token_exp", AttributeValue::N(claims.exp.to_string()))
+        .item("ttl", next_ttl())
         .send()
         .await;
 
     match result {
         Ok(_) => {
+            presence_connected(state, &claims.sub).await;
             tracing::info!(
                 connection_id = %connection_id,
                 user_id = %claims.sub,
+                resumed_channels = channels.len(),
                 "Client connected"
             );
             WebSocketResponse {
@@ -151,11 +381,21 @@ async fn handle_disconnect(state: &AppState, connection_id: &str) -> WebSocketRe
         .delete_item()
         .table_name(get_table("CONNECTIONS_TABLE"))
         .key("connection_id", AttributeValue::S(connection_id.to_string()))
+        .return_values(ReturnValue::AllOld)
         .send()
         .await;
 
     match result {
-        Ok(_) => {
+        Ok(output) => {
+            // Only a connection that was still recorded counts down, so a
+            // repeated $disconnect can't decrement twice
+            let user_id = output
+                .attributes()
+                .and_then(|item| item.get("user_id"))
+                .and_then(|v| v.as_s().ok());
+            if let Some(user_id) = user_id {
+                presence_disconnected(state, user_id).await;
+            }
             tracing::info!(connection_id = %connection_id, "Client disconnected");
         }
         Err(e) => {
@@ -169,6 +409,361 @@ async fn handle_disconnect(state: &AppState, connection_id: &str) -> WebSocketRe
     }
 }
 
+/// Add (`subscribe`) or remove channels from a connection's subscription
+/// set in a single write
+async fn bulk_update_subscriptions(
+    state: &AppState,
+    connection_id: &str,
+    channel_ids: Vec<String>,
+    subscribe: bool,
+) -> WebSocketResponse {
+    let mut channel_ids: Vec<String> = channel_ids
+        .into_iter()
+        .filter(|id| !id.is_empty())
+        .collect();
+    channel_ids.sort();
+    channel_ids.dedup();
+
+    if channel_ids.is_empty() {
+        return WebSocketResponse {
+            status_code: 400,
+            body: Some(r#"{"error":"channel_ids required"}"#.to_string()),
+        };
+    }
+    if channel_ids.len() > MAX_BULK_CHANNELS {
+        return WebSocketResponse {
+            status_code: 400,
+            body: Some(
+                serde_json::json!({
+                    "error": format!("at most {} channel_ids per frame", MAX_BULK_CHANNELS)
+                })
+                .to_string(),
+            ),
+        };
+    }
+
+    if subscribe && channel_ids.iter().any(|id| is_conversation_id(id)) {
+        return WebSocketResponse {
+            status_code: 400,
+            body: Some(r#"{"error":"use subscribe_dm for conversations"}"#.to_string()),
+        };
+    }
+
+    let (op, status) = if subscribe {
+        ("ADD", "subscribed")
+    } else {
+        ("DELETE", "unsubscribed")
+    };
+
+    let result = state
+        .db
+        .update_item()
+        .table_name(get_table("CONNECTIONS_TABLE"))
+        .key("connection_id", AttributeValue::S(connection_id.to_string()))
+        .update_expression(format!("{} channels :channels SET #ttl = :ttl", op))
+        .expression_attribute_names("#ttl", "ttl")
+        .expression_attribute_values(":ttl", next_ttl())
+        .expression_attribute_values(":channels", AttributeValue::Ss(channel_ids.clone()))
+        .send()
+        .await;
+
+    match result {
+        Ok(_) => {
+            tracing::info!(
+                connection_id = %connection_id,
+                count = channel_ids.len(),
+                status = %status,
+                "Bulk subscription update"
+            );
+            WebSocketResponse {
+                status_code: 200,
+                body: Some(
+                    serde_json::json!({
+                        "status": status,
+                        "channel_ids": channel_ids
+                    })
+                    .to_string(),
+                ),
+            }
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to update subscriptions");
+            WebSocketResponse {
+                status_code: 500,
+                body: Some(r#"{"error":"failed to update subscriptions"}"#.to_string()),
+            }
+        }
+    }
+}
+
+/// Swap the token a connection was opened with for a fresh one, so a
+/// long-lived socket can outlast its original token. The new token must be
+/// for the same user.
+async fn reauth_connection(state: &AppState, connection_id: &str, token: Option<String>) -> WebSocketResponse {
+    let Some(token) = token else {
+        return WebSocketResponse {
+            status_code: 400,
+            body: Some(r#"{"error":"token required"}"#.to_string()),
+        };
+    };
+
+    let claims = match validate_token(&token) {
+        Ok(c) => c,
+        Err(e) => {
+            tracing::warn!(connection_id = %connection_id, error = %e, "Invalid reauth token");
+            return WebSocketResponse {
+                status_code: 401,
+                body: Some(r#"{"error":"unauthorized"}"#.to_string()),
+            };
+        }
+    };
+
+    match session_active(state, &claims).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return WebSocketResponse {
+                status_code: 401,
+                body: Some(r#"{"error":"unauthorized"}"#.to_string()),
+            };
+        }
+        Err(e) => {
+            tracing::error!(connection_id = %connection_id, error = %e, "Session check failed");
+            return WebSocketResponse {
+                status_code: 500,
+                body: Some(r#"{"error":"internal error"}"#.to_string()),
+            };
+        }
+    }
+
+    let existing = state
+        .db
+        .get_item()
+        .table_name(get_table("CONNECTIONS_TABLE"))
+        .key("connection_id", AttributeValue::S(connection_id.to_string()))
+        .projection_expression("user_id")
+        .send()
+        .await;
+    let owner = match existing {
+        Ok(result) => result
+            .item()
+            .and_then(|item| item.get("user_id"))
+            .and_then(|v| v.as_s().ok())
+            .cloned(),
+        Err(e) => {
+            tracing::error!(connection_id = %connection_id, error = %e, "Failed to load connection");
+            return WebSocketResponse {
+                status_code: 500,
+                body: Some(r#"{"error":"internal error"}"#.to_string()),
+            };
+        }
+    };
+    match owner {
+        None => {
+            return WebSocketResponse {
+                status_code: 410,
+                body: Some(r#"{"error":"connection expired"}"#.to_string()),
+            };
+        }
+        // A token for someone else must never take over this socket
+        Some(user_id) if user_id != claims.sub => {
+            tracing::warn!(connection_id = %connection_id, user_id = %claims.sub, "Reauth with another user's token");
+            return WebSocketResponse {
+                status_code: 403,
+                body: Some(r#"{"error":"token is for a different user"}"#.to_string()),
+            };
+        }
+        Some(_) => {}
+    }
+
+    // The user_id condition guards against the row being replaced in between
+    let result = state
+        .db
+        .update_item()
+        .table_name(get_table("CONNECTIONS_TABLE"))
+        .key("connection_id", AttributeValue::S(connection_id.to_string()))
+        .update_expression("SET token_exp = :exp, email = :email, #ttl = :ttl")
+        .condition_expression("user_id = :uid")
+        .expression_attribute_names("#ttl", "ttl")
+        .expression_attribute_values(":exp", AttributeValue::N(claims.exp.to_string()))
+        .expression_attribute_values(":email", AttributeValue::S(claims.email.clone()))
+        .expression_attribute_values(":uid", AttributeValue::S(claims.sub.clone()))
+        .expression_attribute_values(":ttl", next_ttl())
+        .send()
+        .await;
+
+    match result {
+        Ok(_) => {
+            tracing::info!(connection_id = %connection_id, user_id = %claims.sub, "Connection reauthenticated");
+            WebSocketResponse {
+                status_code: 200,
+                body: Some(
+                    serde_json::json!({
+                        "status": "reauthenticated",
+                        "exp": claims.exp
+                    })
+                    .to_string(),
+                ),
+            }
+        }
+        Err(e) => {
+            let gone = e
+                .as_service_error()
+                .map(|se| se.is_conditional_check_failed_exception())
+                .unwrap_or(false);
+            if gone {
+                return WebSocketResponse {
+                    status_code: 410,
+                    body: Some(r#"{"error":"connection expired"}"#.to_string()),
+                };
+            }
+            tracing::error!(connection_id = %connection_id, error = %e, "Failed to reauthenticate connection");
+            WebSocketResponse {
+                status_code: 500,
+                body: Some(r#"{"error":"internal error"}"#.to_string()),
+            }
+        }
+    }
+}
+
+/// Conversation ids are the two participant ids joined by `_`; channel ids
+/// never contain one
+fn is_conversation_id(id: &str) -> bool {
+    id.contains('_')
+}
+
+/// Whether `user_id` is in the conversation. Each participant has their own
+/// row keyed by (id, user_id).
+async fn is_participant(state: &AppState, conversation_id: &str, user_id: &str) -> Result<bool, String> {
+    state
+        .db
+        .get_item()
+        .table_name(get_table("DM_CONVERSATIONS_TABLE"))
+        .key("id", AttributeValue::S(conversation_id.to_string()))
+        .key("user_id", AttributeValue::S(user_id.to_string()))
+        .projection_expression("id")
+        .send()
+        .await
+        .map(|result| result.item().is_some())
+        .map_err(|e| e.to_string())
+}
+
+/// Subscribe a connection to a DM conversation after checking that the
+/// connection's user is a participant, so nobody can listen in on someone
+/// else's conversation by guessing its id
+async fn subscribe_conversation(
+    state: &AppState,
+    connection_id: &str,
+    conversation_id: Option<String>,
+) -> WebSocketResponse {
+    let Some(conversation_id) = conversation_id.filter(|id| is_conversation_id(id)) else {
+        return WebSocketResponse {
+            status_code: 400,
+            body: Some(r#"{"error":"conversation_id required"}"#.to_string()),
+        };
+    };
+
+    let existing = state
+        .db
+        .get_item()
+        .table_name(get_table("CONNECTIONS_TABLE"))
+        .key("connection_id", AttributeValue::S(connection_id.to_string()))
+        .projection_expression("user_id")
+        .send()
+        .await;
+    let user_id = match existing {
+        Ok(result) => result
+            .item()
+            .and_then(|item| item.get("user_id"))
+            .and_then(|v| v.as_s().ok())
+            .cloned(),
+        Err(e) => {
+            tracing::error!(connection_id = %connection_id, error = %e, "Failed to load connection");
+            return WebSocketResponse {
+                status_code: 500,
+                body: Some(r#"{"error":"internal error"}"#.to_string()),
+            };
+        }
+    };
+    let Some(user_id) = user_id else {
+        return WebSocketResponse {
+            status_code: 410,
+            body: Some(r#"{"error":"connection expired"}"#.to_string()),
+        };
+    };
+
+    match is_participant(state, &conversation_id, &user_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            tracing::warn!(connection_id = %connection_id, user_id = %user_id, conversation_id = %conversation_id, "Subscribe to conversation by non-participant");
+            return WebSocketResponse {
+                status_code: 403,
+                body: Some(r#"{"error":"not a participant in this conversation"}"#.to_string()),
+            };
+        }
+        Err(e) => {
+            tracing::error!(connection_id = %connection_id, error = %e, "Failed to check conversation");
+            return WebSocketResponse {
+                status_code: 500,
+                body: Some(r#"{"error":"internal error"}"#.to_string()),
+            };
+        }
+    }
+
+    let result = state
+        .db
+        .update_item()
+        .table_name(get_table("CONNECTIONS_TABLE"))
+        .key("connection_id", AttributeValue::S(connection_id.to_string()))
+        .update_expression("ADD channels :channel SET #ttl = :ttl")
+        .condition_expression("user_id = :uid")
+        .expression_attribute_names("#ttl", "ttl")
+        .expression_attribute_values(":ttl", next_ttl())
+        .expression_attribute_values(":uid", AttributeValue::S(user_id))
+        .expression_attribute_values(
+            ":channel",
+            AttributeValue::Ss(vec![conversation_id.clone()]),
+        )
+        .send()
+        .await;
+
+    match result {
+        Ok(_) => {
+            tracing::info!(
+                connection_id = %connection_id,
+                conversation_id = %conversation_id,
+                "Subscribed to conversation"
+            );
+            WebSocketResponse {
+                status_code: 200,
+                body: Some(
+                    serde_json::json!({
+                        "status": "subscribed",
+                        "conversation_id": conversation_id
+                    })
+                    .to_string(),
+                ),
+            }
+        }
+        Err(e) => {
+            let gone = e
+                .as_service_error()
+                .map(|se| se.is_conditional_check_failed_exception())
+                .unwrap_or(false);
+            if gone {
+                return WebSocketResponse {
+                    status_code: 410,
+                    body: Some(r#"{"error":"connection expired"}"#.to_string()),
+                };
+            }
+            tracing::error!(error = %e, "Failed to subscribe to conversation");
+            WebSocketResponse {
+                status_code: 500,
+                body: Some(r#"{"error":"failed to subscribe"}"#.to_string()),
+            }
+        }
+    }
+}
+
 async fn handle_message(
     state: &AppState,
     connection_id: &str,
@@ -196,6 +791,44 @@ async fn handle_message(
     };
 
     match msg.action.as_str() {
+        "ping" => {
+            // Keep-alive: only refreshes the idle expiry
+            let result = state
+                .db
+                .update_item()
+                .table_name(get_table("CONNECTIONS_TABLE"))
+                .key("connection_id", AttributeValue::S(connection_id.to_string()))
+                .update_expression("SET #ttl = :ttl")
+                .condition_expression("attribute_exists(connection_id)")
+                .expression_attribute_names("#ttl", "ttl")
+                .expression_attribute_values(":ttl", next_ttl())
+                .return_values(ReturnValue::AllNew)
+                .send()
+                .await;
+
+            match result {
+                Ok(output) => {
+                    let user_id = output
+                        .attributes()
+                        .and_then(|item| item.get("user_id"))
+                        .and_then(|v| v.as_s().ok());
+                    if let Some(user_id) = user_id {
+                        presence_seen(state, user_id).await;
+                    }
+                    WebSocketResponse {
+                        status_code: 200,
+                        body: Some(r#"{"status":"pong"}"#.to_string()),
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!(connection_id = %connection_id, error = %e, "Failed to refresh connection");
+                    WebSocketResponse {
+                        status_code: 410,
+                        body: Some(r#"{"error":"connection expired"}"#.to_string()),
+                    }
+                }
+            }
+        }
         "subscribe" => {
             let channel_id = match msg.channel_id {
                 Some(c) => c,
@@ -206,6 +839,12 @@ async fn handle_message(
                     };
                 }
             };
+            if is_conversation_id(&channel_id) {
+                return WebSocketResponse {
+                    status_code: 400,
+                    body: Some(r#"{"error":"use subscribe_dm for conversations"}"#.to_string()),
+                };
+            }
 
             // Add channel to connection's subscription list
             let result = state
@@ -213,7 +852,9 @@ async fn handle_message(
                 .update_item()
                 .table_name(get_table("CONNECTIONS_TABLE"))
                 .key("connection_id", AttributeValue::S(connection_id.to_string()))
-                .update_expression("ADD channels :channel")
+                .update_expression("ADD channels :channel SET #ttl = :ttl")
+                .expression_attribute_names("#ttl", "ttl")
+                .expression_attribute_values(":ttl", next_ttl())
                 .expression_attribute_values(
                     ":channel",
                     AttributeValue::Ss(vec![channel_id.clone()]),
@@ -265,7 +906,9 @@ async fn handle_message(
                 .update_item()
                 .table_name(get_table("CONNECTIONS_TABLE"))
                 .key("connection_id", AttributeValue::S(connection_id.to_string()))
-                .update_expression("DELETE channels :channel")
+                .update_expression("DELETE channels :channel SET #ttl = :ttl")
+                .expression_attribute_names("#ttl", "ttl")
+                .expression_attribute_values(":ttl", next_ttl())
                 .expression_attribute_values(
                     ":channel",
                     AttributeValue::Ss(vec![channel_id.clone()]),
@@ -300,6 +943,14 @@ async fn handle_message(
                 }
             }
         }
+        "subscribe_many" => {
+            bulk_update_subscriptions(state, connection_id, msg.channel_ids, true).await
+        }
+        "unsubscribe_many" => {
+            bulk_update_subscriptions(state, connection_id, msg.channel_ids, false).await
+        }
+        "subscribe_dm" => subscribe_conversation(state, connection_id, msg.conversation_id).await,
+        "reauth" => reauth_connection(state, connection_id, msg.token).await,
         _ => {
             tracing::warn!(action = %msg.action, "Unknown action");
             WebSocketResponse {
@@ -348,7 +999,7 @@ async fn main() -> Result<(), Error> {
         .init();
 
     // Initialize AWS SDK
-    let config = aws_config::load_from_env().await;
+    let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
     let db = DynamoClient::new(&config);
     let state = Arc::new(AppState { db });
 
@@ -358,3 +1009,84 @@ async fn main() -> Result<(), Error> {
     }))
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_dynamodb::operation::get_item::GetItemOutput;
+    use aws_sdk_dynamodb::operation::update_item::{UpdateItemInput, UpdateItemOutput};
+    use aws_smithy_mocks::{mock, mock_client, Rule, RuleMode};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use std::sync::Mutex;
+
+    /// Records the `:ttl` of every UpdateItem on the connections table
+    fn capture_ttl(written: Arc<Mutex<Vec<i64>>>) -> Rule {
+        mock!(aws_sdk_dynamodb::Client::update_item).then_compute_output(move |input: &UpdateItemInput| {
+            assert!(input.update_expression().unwrap().contains("#ttl = :ttl"));
+            let ttl = input
+                .expression_attribute_values()
+                .and_then(|values| values.get(":ttl"))
+                .and_then(|v| v.as_n().ok())
+                .and_then(|n| n.parse().ok())
+                .unwrap();
+            written.lock().unwrap().push(ttl);
+            UpdateItemOutput::builder().build()
+        })
+    }
+
+    fn assert_extended(written: &Mutex<Vec<i64>>, before: i64) {
+        let written = written.lock().unwrap();
+        assert_eq!(written.len(), 1);
+        assert!(written[0] >= before + connection_ttl_seconds());
+    }
+
+    #[tokio::test]
+    async fn subscribe_extends_ttl() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let update = capture_ttl(written.clone());
+        let state = AppState {
+            db: mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&update]),
+        };
+        let before = chrono::Utc::now().timestamp();
+
+        let body = Some(r#"{"action":"subscribe","channel_id":"chan-1"}"#.to_string());
+        let response = handle_message(&state, "conn-1", &body).await;
+
+        assert_eq!(response.status_code, 200);
+        assert_extended(&written, before);
+    }
+
+    #[tokio::test]
+    async fn reauth_extends_ttl() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let owner = mock!(aws_sdk_dynamodb::Client::get_item).then_output(|| {
+            GetItemOutput::builder()
+                .item("user_id", AttributeValue::S("user-1".to_string()))
+                .build()
+        });
+        let update = capture_ttl(written.clone());
+        let state = AppState {
+            db: mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&owner, &update]),
+        };
+        let before = chrono::Utc::now().timestamp();
+
+        let claims = Claims {
+            sub: "user-1".to_string(),
+            email: "ada@example.com".to_string(),
+            username: "ada".to_string(),
+            exp: (before + 3600) as usize,
+            sid: None,
+        };
+        let token = encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(get_jwt_secret().as_bytes()),
+        )
+        .unwrap();
+
+        let response = reauth_connection(&state, "conn-1", Some(token)).await;
+
+        assert_eq!(response.status_code, 200);
+        assert_extended(&written, before);
+    }
+}
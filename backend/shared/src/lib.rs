@@ -1,4 +1,5 @@
 pub mod models;
 pub mod error;
+pub mod limits;
 
 pub use error::AppError;
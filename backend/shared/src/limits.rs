@@ -0,0 +1,5 @@
+/// Most channel ids one request may carry. The API applies it to resume
+/// tokens and the WebSocket lambda to subscribe_many/unsubscribe_many
+/// frames; both become one write to a connection's channel set, and a
+/// token the API issues must fit in what the WebSocket side accepts.
+pub const MAX_CHANNELS_PER_REQUEST: usize = 100;
@@ -13,6 +13,7 @@ export interface User {
 
 export interface AuthResponse {
 	token: string;
+	refresh_token: string;
 	user: User;
 }
 
@@ -20,7 +21,7 @@ export interface Server {
 	id: string;
 	name: string;
 	owner_id: string;
-	icon_url: string | null;
+	icon_url?: string;
 	created_at: number;
 }
 
@@ -30,6 +31,9 @@ export interface Channel {
 	name: string;
 	channel_type: string;
 	created_at: number;
+	last_message_at?: number;
+	position?: number;
+	read_only?: boolean;
 }
 
 export interface Member {
@@ -47,6 +51,7 @@ export interface Message {
 	author_username: string;
 	content: string;
 	created_at: number;
+	seq?: number;
 }
 
 export interface MessagesResponse {
@@ -67,20 +72,57 @@ function getToken(): string | null {
 	return localStorage.getItem('token');
 }
 
-export function setToken(token: string): void {
+function getRefreshToken(): string | null {
+	if (typeof window === 'undefined') return null;
+	return localStorage.getItem('refresh_token');
+}
+
+export function setToken(token: string, refreshToken?: string): void {
 	localStorage.setItem('token', token);
+	if (refreshToken) {
+		localStorage.setItem('refresh_token', refreshToken);
+	}
 }
 
 export function clearToken(): void {
 	localStorage.removeItem('token');
+	localStorage.removeItem('refresh_token');
+}
+
+// Concurrent requests that hit an expired token share one refresh
+let refreshing: Promise<boolean> | null = null;
+
+async function refreshAccessToken(): Promise<boolean> {
+	const refreshToken = getRefreshToken();
+	if (!refreshToken) return false;
+
+	refreshing ??= (async () => {
+		try {
+			const response = await fetch(`${API_URL}/auth/refresh`, {
+				method: 'POST',
+				headers: { 'Content-Type': 'application/json' },
+				body: JSON.stringify({ refresh_token: refreshToken })
+			});
+			if (!response.ok) return false;
+			const data: { token: string } = await response.json();
+			setToken(data.token);
+			return true;
+		} catch {
+			return false;
+		} finally {
+			refreshing = null;
+		}
+	})();
+	return refreshing;
 }
 
 // ============ API Client ============
 
 export async function api<T>(
 	endpoint: string,
-	options: RequestInit = {}
-): Promise<{ data?: T; error?: string }> {
+	options: RequestInit = {},
+	retryOnExpired = true
+): Promise<{ data?: T; error?: string; code?: string }> {
 	try {
 		const token = getToken();
 		const headers: Record<string, string> = {
@@ -97,10 +139,15 @@ export async function api<T>(
 			headers
 		});
 
+		// Access tokens are short-lived; refresh once and retry
+		if (response.status === 401 && token && retryOnExpired && (await refreshAccessToken())) {
+			return api<T>(endpoint, options, false);
+		}
+
 		const data = await response.json();
 
 		if (!response.ok) {
-			return { error: data.error || 'Request failed' };
+			return { error: data.error || 'Request failed', code: data.code };
 		}
 
 		return { data };
@@ -142,6 +189,20 @@ export async function login(
 	});
 }
 
+export async function logout(): Promise<void> {
+	const refreshToken = getRefreshToken();
+	if (!refreshToken) return;
+	try {
+		await fetch(`${API_URL}/auth/logout`, {
+			method: 'POST',
+			headers: { 'Content-Type': 'application/json' },
+			body: JSON.stringify({ refresh_token: refreshToken })
+		});
+	} catch {
+		// Logging out locally still works; the session expires on its own
+	}
+}
+
 export async function getMe(): Promise<{ data?: User; error?: string }> {
 	return api<User>('/auth/me');
 }
@@ -219,8 +280,8 @@ export interface Invite {
 	server_name: string;
 	created_by: string;
 	created_at: number;
-	expires_at: number | null;
-	max_uses: number | null;
+	expires_at?: number;
+	max_uses?: number;
 	use_count: number;
 }
 
@@ -229,6 +290,7 @@ export interface InviteInfo {
 	server_name: string;
 	server_id: string;
 	member_count: number;
+	channels?: string[];
 }
 
 export async function createInvite(
@@ -254,8 +316,11 @@ export async function deleteInvite(
 	});
 }
 
-export async function getInviteInfo(code: string): Promise<{ data?: InviteInfo; error?: string }> {
-	return api<InviteInfo>(`/invites/${code}`);
+export async function getInviteInfo(
+	code: string,
+	includeChannels = false
+): Promise<{ data?: InviteInfo; error?: string }> {
+	return api<InviteInfo>(`/invites/${code}${includeChannels ? '?include_channels=true' : ''}`);
 }
 
 export async function joinByCode(code: string): Promise<{ data?: ServerWithChannels; error?: string }> {
@@ -319,7 +384,7 @@ export interface Conversation {
 	other_user_id: string;
 	other_username: string;
 	updated_at: number;
-	last_message_preview: string | null;
+	last_message_preview?: string;
 	created_at: number;
 }
 
@@ -330,6 +395,7 @@ export interface DirectMessage {
 	author_username: string;
 	content: string;
 	created_at: number;
+	reply_to?: string;
 }
 
 export interface DirectMessagesResponse {
@@ -375,10 +441,11 @@ export async function getDmMessages(
 
 export async function sendDmMessage(
 	conversationId: string,
-	content: string
+	content: string,
+	replyTo?: string
 ): Promise<{ data?: DirectMessage; error?: string }> {
 	return api<DirectMessage>(`/dms/${conversationId}/messages`, {
 		method: 'POST',
-		body: JSON.stringify({ content })
+		body: JSON.stringify({ content, reply_to: replyTo })
 	});
 }
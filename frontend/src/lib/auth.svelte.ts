@@ -1,4 +1,4 @@
-import { type User, login as apiLogin, register as apiRegister, getMe, setToken, clearToken } from './api';
+import { type User, login as apiLogin, register as apiRegister, logout as apiLogout, getMe, setToken, clearToken } from './api';
 
 class AuthState {
 	user = $state<User | null>(null);
@@ -35,7 +35,7 @@ class AuthState {
 		}
 
 		if (result.data) {
-			setToken(result.data.token);
+			setToken(result.data.token, result.data.refresh_token);
 			this.user = result.data.user;
 			return true;
 		}
@@ -53,7 +53,7 @@ class AuthState {
 		}
 
 		if (result.data) {
-			setToken(result.data.token);
+			setToken(result.data.token, result.data.refresh_token);
 			this.user = result.data.user;
 			return true;
 		}
@@ -62,6 +62,8 @@ class AuthState {
 	}
 
 	logout() {
+		// Reads the refresh token before it's cleared below
+		void apiLogout();
 		clearToken();
 		this.user = null;
 	}
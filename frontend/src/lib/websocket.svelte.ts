@@ -11,6 +11,7 @@ class WebSocketService {
 	private messageHandlers: Map<string, Set<MessageHandler>> = new Map();
 	private dmHandlers: Map<string, Set<DmHandler>> = new Map();
 	private subscribedChannels: Set<string> = new Set();
+	private subscribedConversations: Set<string> = new Set();
 
 	connected = $state(false);
 	error = $state<string | null>(null);
@@ -39,6 +40,9 @@ class WebSocketService {
 				this.subscribedChannels.forEach((channelId) => {
 					this.sendSubscribe(channelId);
 				});
+				this.subscribedConversations.forEach((conversationId) => {
+					this.sendSubscribeDm(conversationId);
+				});
 			};
 
 			this.ws.onclose = (event) => {
@@ -61,15 +65,19 @@ class WebSocketService {
 				this.error = 'WebSocket connection error';
 			};
 
-			this.ws.onmessage = (event) => {
+			this.ws.onmessage = async (event) => {
 				try {
 					const data = JSON.parse(event.data);
-					if (data.type === 'new_message') {
-						const message = data.message as Message;
+					// Envelope: { event, server_id?, channel_id?, data, compressed?, ts }
+					// Legacy (LEGACY_WS_EVENTS): { type, message }
+					const eventType: string = data.event ?? data.type;
+					const payload = data.event ? await decodeData(data) : data.message;
+					if (eventType === 'message_created' || eventType === 'new_message') {
+						const message = payload as Message;
 						const handlers = this.messageHandlers.get(message.channel_id);
 						handlers?.forEach((handler) => handler(message));
-					} else if (data.type === 'new_dm') {
-						const message = data.message as DirectMessage;
+					} else if (eventType === 'dm_created' || eventType === 'new_dm') {
+						const message = payload as DirectMessage;
 						const handlers = this.dmHandlers.get(message.conversation_id);
 						handlers?.forEach((handler) => handler(message));
 					}
@@ -90,6 +98,7 @@ class WebSocketService {
 		}
 		this.connected = false;
 		this.subscribedChannels.clear();
+		this.subscribedConversations.clear();
 		this.messageHandlers.clear();
 		this.dmHandlers.clear();
 	}
@@ -130,6 +139,18 @@ class WebSocketService {
 		}
 	}
 
+	private sendSubscribeDm(conversationId: string) {
+		if (this.ws?.readyState === WebSocket.OPEN) {
+			this.ws.send(
+				JSON.stringify({
+					action: 'subscribe_dm',
+					conversation_id: conversationId
+				})
+			);
+			console.log('Subscribed to conversation:', conversationId);
+		}
+	}
+
 	private sendUnsubscribe(channelId: string) {
 		if (this.ws?.readyState === WebSocket.OPEN) {
 			this.ws.send(
@@ -149,10 +170,10 @@ class WebSocketService {
 		}
 		this.dmHandlers.get(conversationId)!.add(handler);
 
-		// Send subscribe if not already subscribed (uses same mechanism as channels)
-		if (!this.subscribedChannels.has(conversationId)) {
-			this.subscribedChannels.add(conversationId);
-			this.sendSubscribe(conversationId);
+		// The server checks participation before subscribing
+		if (!this.subscribedConversations.has(conversationId)) {
+			this.subscribedConversations.add(conversationId);
+			this.sendSubscribeDm(conversationId);
 		}
 
 		// Return unsubscribe function
@@ -160,11 +181,21 @@ class WebSocketService {
 			this.dmHandlers.get(conversationId)?.delete(handler);
 			if (this.dmHandlers.get(conversationId)?.size === 0) {
 				this.dmHandlers.delete(conversationId);
-				this.subscribedChannels.delete(conversationId);
+				this.subscribedConversations.delete(conversationId);
 				this.sendUnsubscribe(conversationId);
 			}
 		};
 	}
 }
 
+// Large payloads arrive as base64 gzipped JSON with `compressed: "gzip"`
+async function decodeData(envelope: { data: unknown; compressed?: string }): Promise<unknown> {
+	if (envelope.compressed !== 'gzip') {
+		return envelope.data;
+	}
+	const bytes = Uint8Array.from(atob(envelope.data as string), (c) => c.charCodeAt(0));
+	const stream = new Blob([bytes]).stream().pipeThrough(new DecompressionStream('gzip'));
+	return JSON.parse(await new Response(stream).text());
+}
+
 export const websocket = new WebSocketService();